@@ -0,0 +1,273 @@
+use crate::{generation::ImageOutcome, i18n::tr, image_utils, imaging, server::OpenRouterServer};
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 默认的颜色容差（RGB 欧氏距离）
+pub const DEFAULT_TOLERANCE: f32 = 60.0;
+/// 颜色容差的上限
+pub const MAX_TOLERANCE: f32 = 200.0;
+/// 默认的边缘羽化半径（像素）
+pub const DEFAULT_FEATHER: f32 = 1.0;
+/// 边缘羽化半径的上限（像素）
+pub const MAX_FEATHER: f32 = 20.0;
+
+/// 估计实际背景色时，只采用与键控色距离在此范围内的边缘像素
+const KEY_SAMPLE_DISTANCE: f32 = 120.0;
+/// 符合条件的边缘像素少于此比例时，直接使用标称键控色
+const KEY_SAMPLE_MIN_RATIO: f32 = 0.25;
+
+/// 让模型绘制的纯色背景（键控色）
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyColor {
+    /// 纯绿色 #00FF00（默认）
+    #[default]
+    Green,
+    /// 品红色 #FF00FF，适合主体本身含有绿色的情况
+    Magenta,
+    /// 纯蓝色 #0000FF
+    Blue,
+}
+
+impl KeyColor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyColor::Green => "green",
+            KeyColor::Magenta => "magenta",
+            KeyColor::Blue => "blue",
+        }
+    }
+
+    fn hex(&self) -> &'static str {
+        match self {
+            KeyColor::Green => "#00FF00",
+            KeyColor::Magenta => "#FF00FF",
+            KeyColor::Blue => "#0000FF",
+        }
+    }
+
+    fn rgb(&self) -> [u8; 3] {
+        match self {
+            KeyColor::Green => [0, 255, 0],
+            KeyColor::Magenta => [255, 0, 255],
+            KeyColor::Blue => [0, 0, 255],
+        }
+    }
+}
+
+/// 本地抠图参数
+#[derive(Debug, Clone, Copy)]
+pub struct ChromaKeyOptions {
+    pub key_color: KeyColor,
+    /// 与背景色的距离不超过此值的像素完全透明，超过两倍时完全不透明
+    pub tolerance: f32,
+    /// 边缘羽化半径（像素），0 表示不羽化
+    pub feather: f32,
+}
+
+impl Default for ChromaKeyOptions {
+    fn default() -> Self {
+        Self {
+            key_color: KeyColor::default(),
+            tolerance: DEFAULT_TOLERANCE,
+            feather: DEFAULT_FEATHER,
+        }
+    }
+}
+
+impl ChromaKeyOptions {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_TOLERANCE).contains(&self.tolerance) {
+            return Err(anyhow!(tr!(
+                "background.tolerance_range",
+                max = MAX_TOLERANCE,
+                value = self.tolerance
+            )));
+        }
+        if !(0.0..=MAX_FEATHER).contains(&self.feather) {
+            return Err(anyhow!(tr!(
+                "background.feather_range",
+                max = MAX_FEATHER,
+                value = self.feather
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 生成图像时追加到提示词后的背景要求
+pub fn generate_instruction(key_color: KeyColor) -> String {
+    format!(
+        "Place the subject alone on a flat, uniform, pure {name} ({hex}) background with no \
+         gradient, texture, shadow or reflection, and do not use {name} anywhere on the subject.",
+        name = key_color.as_str(),
+        hex = key_color.hex()
+    )
+}
+
+/// 去除背景时发送给模型的编辑指令
+pub fn removal_instruction(key_color: KeyColor) -> String {
+    format!(
+        "Keep the main subject exactly as it is and replace everything else with a flat, uniform, \
+         pure {name} ({hex}) background with no gradient, texture, shadow or reflection. Do not use \
+         {name} anywhere on the subject. Return only the edited image.",
+        name = key_color.as_str(),
+        hex = key_color.hex()
+    )
+}
+
+/// 对纯色背景的图像做色度键抠图，返回带透明通道的图像
+///
+/// 模型绘制的背景往往与标称键控色略有偏差，因此先从图像边缘估计实际背景色；
+/// 半透明像素按背景色反混合，去除边缘的溢色
+pub fn chroma_key(image: &DynamicImage, options: &ChromaKeyOptions) -> RgbaImage {
+    let mut rgba = image.to_rgba8();
+    let key = estimate_key(&rgba, options.key_color.rgb());
+    let soft_band = options.tolerance.max(1.0);
+
+    let mut alpha = GrayImage::new(rgba.width(), rgba.height());
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let d = distance(pixel, key);
+        let a = ((d - options.tolerance) / soft_band).clamp(0.0, 1.0);
+        let a = a * f32::from(pixel.0[3]) / 255.0;
+        alpha.put_pixel(x, y, Luma([(a * 255.0).round() as u8]));
+    }
+
+    // 只向内羽化：取模糊结果与原始遮罩的较小值，避免在主体外出现键控色光晕
+    let feathered = if options.feather > 0.0 {
+        Some(image::imageops::blur(&alpha, options.feather))
+    } else {
+        None
+    };
+
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let mask = alpha.get_pixel(x, y).0[0];
+        let a = f32::from(mask) / 255.0;
+        if a > 0.0 && a < 1.0 {
+            for (value, background) in pixel.0.iter_mut().zip(key) {
+                *value = ((f32::from(*value) - (1.0 - a) * f32::from(background)) / a)
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+        }
+        let final_alpha = feathered
+            .as_ref()
+            .map_or(mask, |blurred| blurred.get_pixel(x, y).0[0].min(mask));
+        pixel.0[3] = final_alpha;
+        if final_alpha == 0 {
+            pixel.0[..3].fill(0);
+        }
+    }
+    rgba
+}
+
+/// 用边缘像素的中位数估计实际背景色
+fn estimate_key(image: &RgbaImage, nominal: [u8; 3]) -> [u8; 3] {
+    let (width, height) = image.dimensions();
+    let border = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+
+    let mut total = 0usize;
+    let mut samples: [Vec<u8>; 3] = Default::default();
+    for (x, y) in border {
+        total += 1;
+        let pixel = image.get_pixel(x, y);
+        if distance(pixel, nominal) <= KEY_SAMPLE_DISTANCE {
+            for (channel, values) in samples.iter_mut().enumerate() {
+                values.push(pixel.0[channel]);
+            }
+        }
+    }
+    if total == 0 || (samples[0].len() as f32) < total as f32 * KEY_SAMPLE_MIN_RATIO {
+        return nominal;
+    }
+    samples.map(|mut values| {
+        values.sort_unstable();
+        values[values.len() / 2]
+    })
+}
+
+fn distance(pixel: &Rgba<u8>, key: [u8; 3]) -> f32 {
+    (0..3)
+        .map(|channel| {
+            let diff = f32::from(pixel.0[channel]) - f32::from(key[channel]);
+            diff * diff
+        })
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// 一张抠图结果
+#[derive(Debug)]
+pub struct Cutout {
+    /// 模型输出（纯色背景）的保存路径
+    pub source_path: Option<String>,
+    pub result: Result<String, String>,
+}
+
+impl OpenRouterServer {
+    /// 对模型输出的每张图像做本地抠图，结果保存为同目录下的 `{原文件名}_transparent.png`
+    pub(crate) async fn cut_out_images(
+        &self,
+        outcome: &ImageOutcome,
+        options: ChromaKeyOptions,
+    ) -> Vec<Cutout> {
+        let mut cutouts = Vec::with_capacity(outcome.images.len());
+        for image in &outcome.images {
+            let source_path = image.saved_path.clone();
+            let result = self
+                .cut_out_image(&image.url, source_path.as_deref(), options)
+                .await
+                .map_err(|e| e.to_string());
+            cutouts.push(Cutout {
+                source_path,
+                result,
+            });
+        }
+        cutouts
+    }
+
+    async fn cut_out_image(
+        &self,
+        url: &str,
+        saved_path: Option<&str>,
+        options: ChromaKeyOptions,
+    ) -> Result<String> {
+        let input = saved_path.unwrap_or(url);
+        let image = self
+            .load_image(input)
+            .await
+            .map_err(|e| anyhow!("{}", e.message))?;
+        let (directory, stem) = match saved_path {
+            Some(path) => (
+                Path::new(path)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+                image_utils::extract_filename_without_extension(path),
+            ),
+            None => (
+                self.save_directory.read().await.clone().into(),
+                "image".to_string(),
+            ),
+        };
+        tokio::task::spawn_blocking(move || {
+            let cutout = chroma_key(&image, &options);
+            let bytes = imaging::encode_png(&DynamicImage::ImageRgba8(cutout))?;
+            let path = image_utils::write_new_file_atomic(
+                &directory,
+                &format!("{}_transparent", stem),
+                "png",
+                &bytes,
+            )?;
+            Ok(path.to_string_lossy().to_string())
+        })
+        .await?
+    }
+}
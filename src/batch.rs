@@ -0,0 +1,250 @@
+use crate::{
+    errors::ToolError,
+    generation::{ImageOutcome, ImageRequest},
+    i18n::tr,
+    server::OpenRouterServer,
+};
+use anyhow::{Result, anyhow};
+use rmcp::{
+    Peer, RoleServer,
+    model::{ProgressNotificationParam, ProgressToken},
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// 默认的批量生成并发数
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 4;
+/// 单次批量生成允许的最大条目数
+pub const MAX_BATCH_ITEMS: usize = 200;
+
+/// 批量中单个条目的执行结果
+#[derive(Debug)]
+pub struct BatchItemResult {
+    /// 条目序号（从 1 开始）
+    pub index: usize,
+    pub prompt: String,
+    pub outcome: Result<ImageOutcome, String>,
+}
+
+/// 向客户端推送进度通知所需的信息
+#[derive(Clone)]
+pub struct BatchProgress {
+    pub peer: Peer<RoleServer>,
+    pub token: ProgressToken,
+}
+
+/// 将模板与变量矩阵展开为提示词列表（笛卡尔积）
+///
+/// 模板中的 `{变量名}` 会被替换；变量按名称排序，排在前面的变化最慢
+pub fn expand_template(
+    template: &str,
+    variables: &BTreeMap<String, Vec<String>>,
+) -> Result<Vec<String>> {
+    for (name, values) in variables {
+        if values.is_empty() {
+            return Err(anyhow!(tr!("batch.variable_empty", name = name)));
+        }
+        if !template.contains(&format!("{{{}}}", name)) {
+            return Err(anyhow!(tr!("batch.variable_unused", name = name)));
+        }
+    }
+
+    let total = variables
+        .values()
+        .try_fold(1usize, |acc, values| acc.checked_mul(values.len()))
+        .filter(|total| *total <= MAX_BATCH_ITEMS)
+        .ok_or_else(|| {
+            ToolError::input_too_large(tr!("batch.too_many_items", max = MAX_BATCH_ITEMS))
+        })?;
+
+    let mut prompts = Vec::with_capacity(total);
+    for mut n in 0..total {
+        let mut chosen = BTreeMap::new();
+        for (name, values) in variables.iter().rev() {
+            chosen.insert(name.as_str(), values[n % values.len()].as_str());
+            n /= values.len();
+        }
+        prompts.push(render_template(template, &chosen)?);
+    }
+    Ok(prompts)
+}
+
+fn render_template(template: &str, values: &BTreeMap<&str, &str>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!(tr!("batch.unclosed_placeholder", template = template)))?;
+        let name = &after[..end];
+        let value = values
+            .get(name)
+            .ok_or_else(|| anyhow!(tr!("batch.variable_missing", name = name)))?;
+        rendered.push_str(value);
+        rest = &after[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+impl OpenRouterServer {
+    /// 以有限并发执行一批图像请求
+    ///
+    /// 单个条目失败不会影响其他条目；取消后尚未开始的条目标记为已取消
+    pub(crate) async fn run_batch(
+        &self,
+        requests: Vec<ImageRequest>,
+        concurrency: usize,
+        progress: Option<BatchProgress>,
+        cancellation: CancellationToken,
+    ) -> Vec<BatchItemResult> {
+        let total = requests.len();
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+
+        for (offset, request) in requests.into_iter().enumerate() {
+            let server = self.clone();
+            let semaphore = semaphore.clone();
+            let cancellation = cancellation.clone();
+            tasks.spawn(async move {
+                let index = offset + 1;
+                let prompt = request.prompt.clone();
+                let outcome = tokio::select! {
+                    _ = cancellation.cancelled() => Err(tr!("batch.cancelled")),
+                    permit = semaphore.acquire_owned() => match permit {
+                        Ok(_permit) => server
+                            .execute_image_request(request)
+                            .await
+                            .map_err(|e| e.message.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    },
+                };
+                BatchItemResult {
+                    index,
+                    prompt,
+                    outcome,
+                }
+            });
+        }
+
+        let mut results = Vec::with_capacity(total);
+        while let Some(joined) = tasks.join_next().await {
+            let result = match joined {
+                Ok(result) => result,
+                Err(e) => {
+                    tracing::warn!("批量生成任务异常退出: {}", e);
+                    continue;
+                }
+            };
+            if let Some(progress) = &progress {
+                let status = match &result.outcome {
+                    Ok(_) => tr!("batch.progress_succeeded"),
+                    Err(_) => tr!("batch.progress_failed"),
+                };
+                let notification = ProgressNotificationParam {
+                    progress_token: progress.token.clone(),
+                    progress: (results.len() + 1) as f64,
+                    total: Some(total as f64),
+                    message: Some(format!("#{} {}: {}", result.index, status, result.prompt)),
+                };
+                if let Err(e) = progress.peer.notify_progress(notification).await {
+                    tracing::debug!("发送进度通知失败: {}", e);
+                }
+            }
+            results.push(result);
+        }
+
+        results.sort_by_key(|result| result.index);
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+
+    fn variables(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(name, values)| {
+                (
+                    name.to_string(),
+                    values.iter().map(|v| v.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn template_expands_to_the_cartesian_product() {
+        let prompts = expand_template(
+            "a {color} {animal}",
+            &variables(&[
+                ("color", &["red", "blue"]),
+                ("animal", &["cat", "dog", "fox"]),
+            ]),
+        )
+        .unwrap();
+        // 按变量名排序，animal 在前、变化最慢
+        assert_eq!(
+            prompts,
+            [
+                "a red cat",
+                "a blue cat",
+                "a red dog",
+                "a blue dog",
+                "a red fox",
+                "a blue fox"
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_placeholders_use_the_same_value() {
+        let prompts =
+            expand_template("{x} and {x}", &variables(&[("x", &["sun", "moon"])])).unwrap();
+        assert_eq!(prompts, ["sun and sun", "moon and moon"]);
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert!(expand_template("a {color} cat", &variables(&[("color", &[])])).is_err());
+        assert!(expand_template("a cat", &variables(&[("color", &["red"])])).is_err());
+        assert!(
+            expand_template("a {color} {size} cat", &variables(&[("color", &["red"])])).is_err()
+        );
+        assert!(expand_template("a {color cat", &variables(&[("color", &["red"])])).is_err());
+    }
+
+    #[test]
+    fn item_count_is_limited() {
+        let values: Vec<String> = (0..MAX_BATCH_ITEMS).map(|i| i.to_string()).collect();
+        let refs: Vec<&str> = values.iter().map(String::as_str).collect();
+
+        let at_limit = variables(&[("a", &refs[..])]);
+        assert_eq!(
+            expand_template("{a}", &at_limit).unwrap().len(),
+            MAX_BATCH_ITEMS
+        );
+
+        let over = variables(&[("a", &refs[..]), ("b", &["x", "y"])]);
+        let error = expand_template("{a}{b}", &over).unwrap_err();
+        assert_eq!(
+            error.downcast::<ToolError>().unwrap().kind,
+            ErrorKind::InputTooLarge
+        );
+
+        // 乘积溢出 usize 时同样按超出上限处理
+        let mut huge = BTreeMap::new();
+        for name in ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"] {
+            huge.insert(name.to_string(), values.clone());
+        }
+        assert!(expand_template("{a}{b}{c}{d}{e}{f}{g}{h}{i}{j}", &huge).is_err());
+    }
+}
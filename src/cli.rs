@@ -1,5 +1,7 @@
-use clap::{Parser, ValueEnum};
+use crate::i18n::{self, Locale};
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum, builder::BoolishValueParser};
 use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
 pub enum TransportType {
@@ -37,8 +39,158 @@ pub struct CliArgs {
         help = "设置图片保存目录 (必须是绝对路径)"
     )]
     pub save_directory: Option<PathBuf>,
+
+    /// 设置保存文件名模板
+    #[arg(
+        long,
+        env = "MCP_FILENAME_TEMPLATE",
+        help = "设置保存文件名模板，例如 {date}/{prompt_slug}_{index}.png"
+    )]
+    pub filename_template: Option<String>,
+
+    /// 为每张保存的图像写入 sidecar 元数据 JSON
+    #[arg(
+        long,
+        env = "MCP_WRITE_SIDECAR",
+        value_parser = BoolishValueParser::new(),
+        help = "为每张保存的图像写入 <图像>.json 元数据文件"
+    )]
+    pub sidecar: bool,
+
+    /// 将生成来源信息嵌入图像文件
+    #[arg(
+        long,
+        env = "MCP_EMBED_METADATA",
+        value_parser = BoolishValueParser::new(),
+        help = "将提示词、模型、时间等来源信息嵌入图像文件（PNG tEXt/iTXt，JPEG/WebP XMP）"
+    )]
+    pub embed_metadata: bool,
+
+    /// 启用内容寻址存储
+    #[arg(
+        long,
+        env = "MCP_DEDUPE",
+        value_parser = BoolishValueParser::new(),
+        help = "启用内容寻址存储（保存目录下的 .store），相同内容的输出以硬链接复用"
+    )]
+    pub dedupe: bool,
+
+    /// 关闭生成历史记录
+    #[arg(long, help = "关闭生成历史记录（也可设置 MCP_HISTORY=false）")]
+    pub no_history: bool,
+
+    /// 设置生成历史数据库路径
+    #[arg(
+        long,
+        env = "MCP_HISTORY_DB",
+        help = "设置生成历史 SQLite 数据库路径（默认: <保存目录>/.nano-banana/history.sqlite3）"
+    )]
+    pub history_db: Option<PathBuf>,
+
+    /// 设置批量生成的最大并发数
+    #[arg(
+        long,
+        env = "MCP_BATCH_CONCURRENCY",
+        help = "设置 batch_generate 同时发出的最大请求数（默认: 4）"
+    )]
+    pub batch_concurrency: Option<usize>,
+
+    /// 设置后台任务的 worker 数量
+    #[arg(
+        long,
+        env = "MCP_JOB_WORKERS",
+        help = "设置后台任务（submit_generation_job）同时执行的数量（默认: 2）"
+    )]
+    pub job_workers: Option<usize>,
+
+    /// 设置 describe_image 使用的视觉模型
+    #[arg(
+        long,
+        env = "MCP_VISION_MODEL",
+        help = "设置 describe_image 使用的视觉模型（默认: google/gemini-2.5-flash）"
+    )]
+    pub vision_model: Option<String>,
+
+    /// 设置提示词增强使用的文本模型
+    #[arg(
+        long,
+        env = "MCP_TEXT_MODEL",
+        help = "设置 enhance_prompt 使用的文本模型（默认: google/gemini-2.5-flash）"
+    )]
+    pub text_model: Option<String>,
+
+    /// 设置 compose_images 绘制标题使用的字体
+    #[arg(
+        long,
+        env = "MCP_CAPTION_FONT",
+        help = "设置 compose_images 绘制标题使用的 TTF/OTF 字体文件（默认尝试常见系统字体）"
+    )]
+    pub caption_font: Option<String>,
+
+    /// 设置 upscale_image 默认使用的后端
+    #[arg(
+        long,
+        env = "MCP_UPSCALE_BACKEND",
+        help = "设置 upscale_image 默认使用的后端: local（本地重采样，默认）或 model（上游图像模型）"
+    )]
+    pub upscale_backend: Option<String>,
+
+    /// 设置 upscale_image 模型后端使用的模型
+    #[arg(
+        long,
+        env = "MCP_UPSCALE_MODEL",
+        help = "设置 upscale_image 模型后端使用的模型（默认与 --model 相同）"
+    )]
+    pub upscale_model: Option<String>,
+
+    /// 设置 MCP 提示词模板目录
+    #[arg(
+        long,
+        env = "MCP_PROMPTS_DIR",
+        help = "设置 MCP 提示词模板目录（默认: <保存目录>/.nano-banana/prompts）"
+    )]
+    pub prompts_dir: Option<PathBuf>,
+
+    /// 请求疑似被误拦截时改写提示词重试
+    #[arg(
+        long,
+        env = "MCP_RETRY_BLOCKED",
+        value_parser = BoolishValueParser::new(),
+        help = "请求被安全策略拦截且看起来是误判时，用文本模型改写提示词后重试一次"
+    )]
+    pub retry_blocked: bool,
+
+    /// 允许下载内网地址的远程图像
+    #[arg(
+        long,
+        env = "MCP_ALLOW_PRIVATE_DOWNLOADS",
+        value_parser = BoolishValueParser::new(),
+        help = "允许本地处理时下载解析到回环、内网或链路本地地址的远程图像（默认拒绝）"
+    )]
+    pub allow_private_downloads: bool,
+
+    /// 设置本地价格表
+    #[arg(
+        long,
+        env = "MCP_PRICE_TABLE",
+        help = "设置本地模型价格表 JSON 文件，未返回 usage.cost 时用于估算费用，优先于上游 models 接口的价格"
+    )]
+    pub price_table: Option<PathBuf>,
+
+    /// 设置服务器消息的语言
+    #[arg(
+        long,
+        env = "MCP_LANG",
+        value_parser = Locale::from_str,
+        help = "设置工具描述、错误和响应文本的语言: zh-CN（默认）或 en"
+    )]
+    pub lang: Option<Locale>,
 }
 
+/// 先确定语言，使帮助文字和参数错误也使用该语言
 pub fn parse_args() -> CliArgs {
-    CliArgs::parse()
+    let args: Vec<String> = std::env::args().collect();
+    i18n::init(Locale::detect(&args));
+    let matches = i18n::localize_command(CliArgs::command()).get_matches();
+    CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
 }
@@ -0,0 +1,346 @@
+use crate::{errors::ToolError, i18n::tr, imaging};
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, Rgba, RgbaImage, imageops::FilterType};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 单次拼接允许的最大图像数量
+pub const MAX_COMPOSE_IMAGES: usize = 64;
+/// 默认的间距（像素）
+pub const DEFAULT_GUTTER: u32 = 16;
+/// 允许设置的最大间距（像素）
+pub const MAX_GUTTER: u32 = 512;
+/// 默认的标题字号（像素）
+pub const DEFAULT_CAPTION_SIZE: u32 = 32;
+/// 标题字号的取值范围（像素）
+pub const CAPTION_SIZE_RANGE: (u32, u32) = (8, 256);
+
+/// 未配置标题字体时依次尝试的系统字体，优先选择支持中文的字体
+const FALLBACK_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-zenhei.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/STHeiti Medium.ttc",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\simhei.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial Unicode.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// 拼接布局
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ComposeLayout {
+    /// N×M 网格，每个格子大小相同
+    #[default]
+    Grid,
+    /// 水平并排，统一高度
+    SideBySide,
+    /// 垂直堆叠，统一宽度
+    Stacked,
+}
+
+impl ComposeLayout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComposeLayout::Grid => "grid",
+            ComposeLayout::SideBySide => "side_by_side",
+            ComposeLayout::Stacked => "stacked",
+        }
+    }
+}
+
+/// 拼接参数
+#[derive(Debug, Clone)]
+pub struct ComposeOptions {
+    pub layout: ComposeLayout,
+    pub columns: u32,
+    pub rows: u32,
+    pub gutter: u32,
+    pub background: Rgba<u8>,
+    /// 每张图像下方的标题，空字符串表示没有标题
+    pub captions: Vec<String>,
+    pub caption_size: u32,
+}
+
+impl ComposeOptions {
+    pub fn has_captions(&self) -> bool {
+        self.captions.iter().any(|c| !c.trim().is_empty())
+    }
+}
+
+/// 计算布局的列数和行数；网格只给出一个维度时按图像数量推算另一个
+pub fn layout_dimensions(
+    layout: ComposeLayout,
+    count: usize,
+    columns: Option<u32>,
+    rows: Option<u32>,
+) -> Result<(u32, u32)> {
+    let count = count as u32;
+    let dimensions = match layout {
+        ComposeLayout::SideBySide => (count, 1),
+        ComposeLayout::Stacked => (1, count),
+        ComposeLayout::Grid => match (columns, rows) {
+            (Some(0), _) | (_, Some(0)) => return Err(anyhow!(tr!("compose.grid_zero"))),
+            (Some(columns), Some(rows)) => (columns, rows),
+            (Some(columns), None) => (columns, count.div_ceil(columns)),
+            (None, Some(rows)) => (count.div_ceil(rows), rows),
+            (None, None) => {
+                let columns = (1..=count).find(|c| c * c >= count).unwrap_or(1);
+                (columns, count.div_ceil(columns))
+            }
+        },
+    };
+    let (columns, rows) = dimensions;
+    if columns as usize > MAX_COMPOSE_IMAGES || rows as usize > MAX_COMPOSE_IMAGES {
+        return Err(anyhow!(tr!(
+            "compose.grid_too_large",
+            max = MAX_COMPOSE_IMAGES
+        )));
+    }
+    if u64::from(columns) * u64::from(rows) < u64::from(count) {
+        return Err(anyhow!(tr!(
+            "compose.grid_too_small",
+            columns = columns,
+            rows = rows,
+            count = count
+        )));
+    }
+    Ok(dimensions)
+}
+
+/// 读取标题字体：优先使用配置的字体文件，否则尝试常见的系统字体
+pub fn load_caption_font(configured: Option<&str>) -> Result<FontVec> {
+    if let Some(path) = configured {
+        let bytes = std::fs::read(path)
+            .map_err(|e| anyhow!(tr!("compose.font_unreadable", path = path, error = e)))?;
+        return FontVec::try_from_vec(bytes)
+            .map_err(|e| anyhow!(tr!("compose.font_invalid", path = path, error = e)));
+    }
+    FALLBACK_FONTS
+        .iter()
+        .filter(|path| Path::new(path).is_file())
+        .find_map(|path| {
+            std::fs::read(path)
+                .ok()
+                .and_then(|bytes| FontVec::try_from_vec(bytes).ok())
+        })
+        .ok_or_else(|| anyhow!(tr!("compose.font_not_found")))
+}
+
+/// 标题中是否有字体无法显示的字符
+pub fn has_missing_glyphs(font: &FontVec, captions: &[String]) -> bool {
+    captions
+        .iter()
+        .flat_map(|caption| caption.chars())
+        .any(|c| !c.is_whitespace() && font.glyph_id(c).0 == 0)
+}
+
+/// 按布局把多张图像拼接为一张图像
+///
+/// 网格中每个格子使用所有图像的最小宽高，并排时统一为最小高度，堆叠时统一为最小宽度；
+/// 图像按比例缩放后居中放入格子，不会被拉伸或放大
+pub fn compose(
+    images: &[DynamicImage],
+    options: &ComposeOptions,
+    font: Option<&FontVec>,
+) -> Result<RgbaImage> {
+    if images.is_empty() {
+        return Err(anyhow!(tr!("compose.no_images")));
+    }
+    let min_width = images.iter().map(|i| i.width()).min().unwrap_or(1);
+    let min_height = images.iter().map(|i| i.height()).min().unwrap_or(1);
+    let columns = options.columns as usize;
+    let rows = options.rows as usize;
+
+    let (column_widths, row_heights): (Vec<u32>, Vec<u32>) = match options.layout {
+        ComposeLayout::Grid => (vec![min_width; columns], vec![min_height; rows]),
+        ComposeLayout::SideBySide => (
+            images
+                .iter()
+                .map(|i| scaled_length(i.width(), i.height(), min_height))
+                .collect(),
+            vec![min_height],
+        ),
+        ComposeLayout::Stacked => (
+            vec![min_width],
+            images
+                .iter()
+                .map(|i| scaled_length(i.height(), i.width(), min_width))
+                .collect(),
+        ),
+    };
+
+    let caption_band = match font {
+        Some(_) if options.has_captions() => (options.caption_size as f32 * 1.6).ceil() as u32,
+        _ => 0,
+    };
+    let gutter = options.gutter;
+    let width = column_widths.iter().map(|w| u64::from(*w)).sum::<u64>()
+        + u64::from(gutter) * (columns as u64 + 1);
+    let height = row_heights
+        .iter()
+        .map(|h| u64::from(*h) + u64::from(caption_band))
+        .sum::<u64>()
+        + u64::from(gutter) * (rows as u64 + 1);
+    let (width, height) = (
+        u32::try_from(width)
+            .map_err(|_| ToolError::input_too_large(tr!("imaging.output_too_large")))?,
+        u32::try_from(height)
+            .map_err(|_| ToolError::input_too_large(tr!("imaging.output_too_large")))?,
+    );
+    imaging::ensure_output_size(width, height)?;
+
+    let mut canvas = RgbaImage::from_pixel(width, height, options.background);
+    let text_color = contrasting_color(options.background);
+    for (index, image) in images.iter().enumerate() {
+        let (column, row) = (index % columns, index / columns);
+        let cell_x = gutter
+            + column_widths[..column]
+                .iter()
+                .map(|w| w + gutter)
+                .sum::<u32>();
+        let cell_y = gutter
+            + row_heights[..row]
+                .iter()
+                .map(|h| h + caption_band + gutter)
+                .sum::<u32>();
+        let (cell_width, cell_height) = (column_widths[column], row_heights[row]);
+
+        let fitted = if image.width() <= cell_width && image.height() <= cell_height {
+            image.to_rgba8()
+        } else {
+            image
+                .resize(cell_width, cell_height, FilterType::Lanczos3)
+                .to_rgba8()
+        };
+        let x = cell_x + (cell_width - fitted.width()) / 2;
+        let y = cell_y + (cell_height - fitted.height()) / 2;
+        image::imageops::overlay(&mut canvas, &fitted, i64::from(x), i64::from(y));
+
+        if let Some(font) = font
+            && let Some(caption) = options.captions.get(index)
+            && !caption.trim().is_empty()
+        {
+            draw_caption(
+                &mut canvas,
+                font,
+                caption.trim(),
+                options.caption_size as f32,
+                (cell_x, cell_y + cell_height),
+                (cell_width, caption_band),
+                text_color,
+            );
+        }
+    }
+    Ok(canvas)
+}
+
+/// 按比例缩放后另一条边的长度
+fn scaled_length(length: u32, other: u32, target_other: u32) -> u32 {
+    ((u64::from(length) * u64::from(target_other)) / u64::from(other.max(1))).max(1) as u32
+}
+
+/// 根据背景亮度选择黑色或白色文字；透明背景使用黑色
+fn contrasting_color(background: Rgba<u8>) -> Rgba<u8> {
+    let [r, g, b, a] = background.0;
+    let luminance = 0.299 * f32::from(r) + 0.587 * f32::from(g) + 0.114 * f32::from(b);
+    if a < 128 || luminance > 140.0 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+/// 在标题区域内居中绘制一行文字，超出宽度时截断并追加省略号
+fn draw_caption(
+    canvas: &mut RgbaImage,
+    font: &FontVec,
+    text: &str,
+    size: f32,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    color: Rgba<u8>,
+) {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let measure = |text: &str| -> f32 {
+        let mut previous = None;
+        text.chars()
+            .map(|c| {
+                let id = font.glyph_id(c);
+                let kern = previous.map_or(0.0, |p| scaled.kern(p, id));
+                previous = Some(id);
+                kern + scaled.h_advance(id)
+            })
+            .sum()
+    };
+
+    let mut line = text.to_string();
+    if measure(&line) > width as f32 {
+        let mut chars: Vec<char> = text.chars().collect();
+        while !chars.is_empty() {
+            chars.pop();
+            line = chars.iter().collect::<String>().trim_end().to_string() + "…";
+            if measure(&line) <= width as f32 {
+                break;
+            }
+        }
+    }
+
+    let line_width = measure(&line);
+    let mut caret = x as f32 + ((width as f32 - line_width) / 2.0).max(0.0);
+    let baseline =
+        y as f32 + (height as f32 - (scaled.ascent() - scaled.descent())) / 2.0 + scaled.ascent();
+    let mut previous = None;
+    for c in line.chars() {
+        let id = font.glyph_id(c);
+        if let Some(p) = previous {
+            caret += scaled.kern(p, id);
+        }
+        previous = Some(id);
+        let glyph = id.with_scale_and_position(scale, point(caret, baseline));
+        caret += scaled.h_advance(id);
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + i64::from(gx);
+            let py = bounds.min.y as i64 + i64::from(gy);
+            if px < i64::from(x)
+                || px >= i64::from(x + width)
+                || py < 0
+                || py >= i64::from(canvas.height())
+            {
+                return;
+            }
+            blend(canvas.get_pixel_mut(px as u32, py as u32), color, coverage);
+        });
+    }
+}
+
+/// 按覆盖率把文字颜色叠加到像素上（source-over，兼容透明背景）
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f32) {
+    let alpha = coverage.clamp(0.0, 1.0) * f32::from(color.0[3]) / 255.0;
+    let below = f32::from(pixel.0[3]) / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + below;
+    if out_alpha <= 0.0 {
+        return;
+    }
+    for channel in 0..3 {
+        pixel.0[channel] = ((f32::from(color.0[channel]) * alpha
+            + f32::from(pixel.0[channel]) * below)
+            / out_alpha)
+            .round() as u8;
+    }
+    pixel.0[3] = (out_alpha * 255.0).round() as u8;
+}
@@ -1,3 +1,4 @@
+use crate::{i18n::tr, image_utils::SaveOptions};
 use anyhow::{Result, anyhow};
 use std::env;
 
@@ -10,6 +11,23 @@ pub struct OpenRouterConfig {
     pub http_port: u16,
     pub model: String,
     pub sse_keep_alive_secs: Option<u64>,
+    pub filename_template: Option<String>,
+    pub write_sidecar: bool,
+    pub embed_metadata: bool,
+    pub dedupe: bool,
+    pub history_enabled: bool,
+    pub history_db: Option<String>,
+    pub batch_concurrency: usize,
+    pub job_workers: usize,
+    pub vision_model: String,
+    pub text_model: String,
+    pub caption_font: Option<String>,
+    pub upscale_backend: crate::upscale::UpscaleBackend,
+    pub upscale_model: String,
+    pub prompts_dir: Option<String>,
+    pub retry_blocked: bool,
+    pub allow_private_downloads: bool,
+    pub price_table: Option<String>,
 }
 
 impl OpenRouterConfig {
@@ -18,9 +36,9 @@ impl OpenRouterConfig {
 
         // 首先尝试从命令行参数获取 API key
         let args: Vec<String> = env::args().collect();
-        let api_key = Self::get_api_key_from_args(&args)
+        let api_key = Self::get_flag_from_args(&args, "--api-key")
             .or_else(|| env::var("OPENROUTER_API_KEY").ok())
-            .ok_or_else(|| anyhow!("OPENROUTER_API_KEY 环境变量或 --api-key 命令行参数是必需的"))?;
+            .ok_or_else(|| anyhow!(tr!("config.api_key_required")))?;
 
         let base_url = env::var("OPENROUTER_BASE_URL")
             .unwrap_or_else(|_| "https://openrouter.ai/api/v1".to_string());
@@ -48,10 +66,110 @@ impl OpenRouterConfig {
         //   - gpt-4o-image (一些服务使用这个名称)
         //   - google/gemini-2.5-flash-image-preview
         //   - google/gemini-3-pro-image-preview
-        let model = Self::get_model_from_args(&args)
+        let model = Self::get_flag_from_args(&args, "--model")
             .or_else(|| env::var("MCP_MODEL").ok())
             .unwrap_or_else(|| "google/gemini-2.5-flash-preview-06-17".to_string());
 
+        // 保存文件名模板：优先命令行参数，然后环境变量，未设置时沿用默认命名
+        let filename_template = Self::get_flag_from_args(&args, "--filename-template")
+            .or_else(|| env::var("MCP_FILENAME_TEMPLATE").ok())
+            .filter(|t| !t.trim().is_empty());
+        if let Some(template) = &filename_template {
+            crate::naming::validate_template(template)
+                .map_err(|e| anyhow!(tr!("config.invalid_filename_template", error = e)))?;
+        }
+
+        // 是否为每张保存的图像写入 sidecar 元数据 JSON
+        let write_sidecar =
+            Self::has_flag(&args, "--sidecar") || Self::env_flag("MCP_WRITE_SIDECAR");
+
+        // 是否把生成来源嵌入到图像文件自身的元数据中
+        let embed_metadata =
+            Self::has_flag(&args, "--embed-metadata") || Self::env_flag("MCP_EMBED_METADATA");
+
+        // 是否启用内容寻址存储，对相同内容的输出去重
+        let dedupe = Self::has_flag(&args, "--dedupe") || Self::env_flag("MCP_DEDUPE");
+
+        // 生成历史数据库：默认启用，位于保存目录下；可用 --no-history 或 MCP_HISTORY=false 关闭
+        let history_enabled =
+            !Self::has_flag(&args, "--no-history") && Self::env_bool("MCP_HISTORY").unwrap_or(true);
+        let history_db = Self::get_flag_from_args(&args, "--history-db")
+            .or_else(|| env::var("MCP_HISTORY_DB").ok())
+            .filter(|p| !p.trim().is_empty());
+
+        // upscale_image 默认使用的后端和模型后端使用的模型
+        let upscale_backend = match Self::get_flag_from_args(&args, "--upscale-backend")
+            .or_else(|| env::var("MCP_UPSCALE_BACKEND").ok())
+        {
+            Some(value) => value.parse()?,
+            None => crate::upscale::UpscaleBackend::default(),
+        };
+        let upscale_model = Self::get_flag_from_args(&args, "--upscale-model")
+            .or_else(|| env::var("MCP_UPSCALE_MODEL").ok())
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| model.clone());
+
+        // batch_generate 同时向上游发出的最大请求数
+        let batch_concurrency = match Self::get_flag_from_args(&args, "--batch-concurrency")
+            .or_else(|| env::var("MCP_BATCH_CONCURRENCY").ok())
+        {
+            Some(value) => value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!(tr!("config.invalid_batch_concurrency", value = value)))?,
+            None => crate::batch::DEFAULT_BATCH_CONCURRENCY,
+        };
+
+        // 后台任务 worker 数量
+        let job_workers = match Self::get_flag_from_args(&args, "--job-workers")
+            .or_else(|| env::var("MCP_JOB_WORKERS").ok())
+        {
+            Some(value) => value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!(tr!("config.invalid_job_workers", value = value)))?,
+            None => crate::jobs::DEFAULT_JOB_WORKERS,
+        };
+
+        // describe_image 使用的视觉模型
+        let vision_model = Self::get_flag_from_args(&args, "--vision-model")
+            .or_else(|| env::var("MCP_VISION_MODEL").ok())
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| crate::describe::DEFAULT_VISION_MODEL.to_string());
+
+        // enhance_prompt 使用的文本模型
+        let text_model = Self::get_flag_from_args(&args, "--text-model")
+            .or_else(|| env::var("MCP_TEXT_MODEL").ok())
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| crate::enhance::DEFAULT_TEXT_MODEL.to_string());
+
+        // compose_images 绘制标题使用的字体文件（可选，未设置时尝试常见系统字体）
+        let caption_font = Self::get_flag_from_args(&args, "--caption-font")
+            .or_else(|| env::var("MCP_CAPTION_FONT").ok())
+            .filter(|p| !p.trim().is_empty());
+
+        // MCP 提示词模板目录（可选，未设置时使用保存目录下的 .nano-banana/prompts）
+        let prompts_dir = Self::get_flag_from_args(&args, "--prompts-dir")
+            .or_else(|| env::var("MCP_PROMPTS_DIR").ok())
+            .filter(|p| !p.trim().is_empty());
+
+        // 请求被安全策略拦截且看起来是误判时，是否改写提示词后重试一次
+        let retry_blocked =
+            Self::has_flag(&args, "--retry-blocked") || Self::env_flag("MCP_RETRY_BLOCKED");
+
+        // 是否允许下载解析到回环、内网或链路本地地址的远程图像
+        let allow_private_downloads = Self::has_flag(&args, "--allow-private-downloads")
+            || Self::env_flag("MCP_ALLOW_PRIVATE_DOWNLOADS");
+
+        // 本地价格表（可选），优先于上游 models 接口的价格
+        let price_table = Self::get_flag_from_args(&args, "--price-table")
+            .or_else(|| env::var("MCP_PRICE_TABLE").ok())
+            .filter(|p| !p.trim().is_empty());
+
         // 不再验证模型名称，允许用户使用任意兼容 OpenAI chat/completions API 的模型
         // 这样可以支持各种第三方 API 转发服务（如 tu-zi.com、one-api 等）
 
@@ -63,33 +181,84 @@ impl OpenRouterConfig {
             http_port,
             model,
             sse_keep_alive_secs,
+            filename_template,
+            write_sidecar,
+            embed_metadata,
+            dedupe,
+            history_enabled,
+            history_db,
+            batch_concurrency,
+            job_workers,
+            vision_model,
+            text_model,
+            caption_font,
+            upscale_backend,
+            upscale_model,
+            prompts_dir,
+            retry_blocked,
+            allow_private_downloads,
+            price_table,
         })
     }
 
-    /// 从命令行参数中获取 API key
-    fn get_api_key_from_args(args: &[String]) -> Option<String> {
+    /// 从命令行参数中获取指定选项的值，支持 `--flag value` 和 `--flag=value` 两种形式
+    pub(crate) fn get_flag_from_args(args: &[String], flag: &str) -> Option<String> {
+        let prefix = format!("{}=", flag);
         for (i, arg) in args.iter().enumerate() {
-            if arg == "--api-key" && i + 1 < args.len() {
+            if arg == flag && i + 1 < args.len() {
                 return Some(args[i + 1].clone());
             }
-            if arg.starts_with("--api-key=") {
-                return Some(arg.trim_start_matches("--api-key=").to_string());
+            if let Some(value) = arg.strip_prefix(&prefix) {
+                return Some(value.to_string());
             }
         }
         None
     }
 
-    /// 从命令行参数中获取模型
-    fn get_model_from_args(args: &[String]) -> Option<String> {
-        for (i, arg) in args.iter().enumerate() {
-            if arg == "--model" && i + 1 < args.len() {
-                return Some(args[i + 1].clone());
-            }
-            if arg.starts_with("--model=") {
-                return Some(arg.trim_start_matches("--model=").to_string());
-            }
+    /// 检查命令行中是否出现了布尔开关
+    fn has_flag(args: &[String], flag: &str) -> bool {
+        args.iter().any(|arg| arg == flag)
+    }
+
+    /// 读取布尔型环境变量，接受 1/true/yes/on 和 0/false/no/off，未设置或无法识别时返回 None
+    fn env_bool(name: &str) -> Option<bool> {
+        let value = env::var(name).ok()?;
+        match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(true),
+            "0" | "false" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// 读取默认关闭的布尔型环境变量
+    fn env_flag(name: &str) -> bool {
+        Self::env_bool(name).unwrap_or(false)
+    }
+
+    /// 保存图像时的可选处理
+    pub fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            write_sidecar: self.write_sidecar,
+            embed_metadata: self.embed_metadata,
+            dedupe: self.dedupe,
+        }
+    }
+
+    /// 根据 base_url 推断上游服务提供方名称
+    pub fn provider(&self) -> String {
+        let host = self
+            .base_url
+            .split("://")
+            .nth(1)
+            .unwrap_or(&self.base_url)
+            .split(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        if host.ends_with("openrouter.ai") {
+            "openrouter".to_string()
+        } else {
+            host.to_string()
         }
-        None
     }
 
     pub fn get_headers(&self) -> reqwest::header::HeaderMap {
@@ -0,0 +1,238 @@
+use crate::{
+    generation::DEFAULT_MAX_TOKENS, i18n::tr, server::OpenRouterServer,
+    tools::extract_text_and_images,
+};
+use anyhow::{Result, anyhow};
+use rmcp::{ErrorData as McpError, schemars};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// 默认的视觉模型
+pub const DEFAULT_VISION_MODEL: &str = "google/gemini-2.5-flash";
+/// alt-text 模式的默认最大长度（字符）
+pub const DEFAULT_ALT_TEXT_MAX_CHARS: usize = 125;
+/// alt-text 模式允许设置的最大长度（字符）
+pub const MAX_ALT_TEXT_CHARS: usize = 1000;
+
+/// 描述图像时使用较低的温度，输出更稳定
+const DESCRIBE_TEMPERATURE: f64 = 0.2;
+/// JSON 模式下最多请求的次数（含首次）
+const MAX_JSON_ATTEMPTS: u32 = 2;
+
+/// describe_image 的输出模式
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DescribeMode {
+    /// 一句话标题
+    #[default]
+    Caption,
+    /// 详细描述
+    Detailed,
+    /// 长度受限的无障碍替代文本
+    AltText,
+    /// 包含 caption / tags / objects / colors 的 JSON
+    Json,
+}
+
+impl DescribeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DescribeMode::Caption => "caption",
+            DescribeMode::Detailed => "detailed",
+            DescribeMode::AltText => "alt_text",
+            DescribeMode::Json => "json",
+        }
+    }
+}
+
+/// JSON 模式的输出结构
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ImageDescription {
+    /// 一句话描述
+    pub caption: String,
+    /// 关键词标签
+    pub tags: Vec<String>,
+    /// 画面中的主要物体
+    pub objects: Vec<String>,
+    /// 主要颜色
+    pub colors: Vec<String>,
+}
+
+impl ImageDescription {
+    fn validate(&self) -> Result<()> {
+        if self.caption.trim().is_empty() {
+            return Err(anyhow!(tr!("describe.empty_caption")));
+        }
+        for (field, values) in [
+            ("tags", &self.tags),
+            ("objects", &self.objects),
+            ("colors", &self.colors),
+        ] {
+            if values.iter().any(|v| v.trim().is_empty()) {
+                return Err(anyhow!(tr!("describe.empty_value", field = field)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 构造发送给视觉模型的指令
+pub fn describe_prompt(mode: DescribeMode, max_chars: usize, language: Option<&str>) -> String {
+    let mut prompt = match mode {
+        DescribeMode::Caption => {
+            "Write a single-sentence caption for this image. Reply with the caption only."
+                .to_string()
+        }
+        DescribeMode::Detailed => "Describe this image in detail: subject, setting, composition, \
+             lighting, colors, style and any visible text. Reply with the description only."
+            .to_string(),
+        DescribeMode::AltText => format!(
+            "Write alt text for this image for screen-reader users. Describe what matters for \
+             understanding the image, do not start with \"image of\" or \"picture of\", and use \
+             at most {} characters. Reply with the alt text only.",
+            max_chars
+        ),
+        DescribeMode::Json => "Analyze this image and reply with a single JSON object and nothing \
+             else, using exactly these keys: \"caption\" (string, one sentence), \"tags\" (array \
+             of short keyword strings), \"objects\" (array of the main objects), \"colors\" (array \
+             of the dominant colors)."
+            .to_string(),
+    };
+    if let Some(language) = language.map(str::trim).filter(|l| !l.is_empty()) {
+        prompt.push_str(&format!(" Write the text in {}.", language));
+    }
+    prompt
+}
+
+/// JSON 模式下请求上游按 schema 输出的 `response_format`
+pub fn json_response_format() -> Value {
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "image_description",
+            "strict": true,
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "caption": {"type": "string"},
+                    "tags": {"type": "array", "items": {"type": "string"}},
+                    "objects": {"type": "array", "items": {"type": "string"}},
+                    "colors": {"type": "array", "items": {"type": "string"}}
+                },
+                "required": ["caption", "tags", "objects", "colors"],
+                "additionalProperties": false
+            }
+        }
+    })
+}
+
+/// 从模型回复中解析并校验 JSON 描述，兼容包裹在 ```json 代码块中的输出
+pub fn parse_description(text: &str) -> Result<ImageDescription> {
+    let trimmed = text.trim();
+    let body = match (trimmed.find('{'), trimmed.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &trimmed[start..=end],
+        _ => return Err(anyhow!(tr!("describe.no_json_object"))),
+    };
+    let description: ImageDescription = serde_json::from_str(body)
+        .map_err(|e| anyhow!(tr!("describe.invalid_structure", error = e)))?;
+    description.validate()?;
+    Ok(description)
+}
+
+/// 把 alt-text 限制在 `max_chars` 个字符以内，尽量在句子或词语边界截断
+pub fn truncate_alt_text(text: &str, max_chars: usize) -> String {
+    let text = text.trim().trim_matches('"').trim();
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars).collect();
+    let boundary = cut
+        .rfind(['.', '。', '!', '！', '?', '？'])
+        .map(|i| i + cut[i..].chars().next().map_or(1, char::len_utf8))
+        .filter(|i| *i >= cut.len() / 2)
+        .or_else(|| cut.rfind([' ', '，', ',']).filter(|i| *i >= cut.len() / 2));
+    match boundary {
+        Some(end) => cut[..end].trim_end_matches([' ', '，', ',']).to_string(),
+        None => cut,
+    }
+}
+
+/// describe_image 的结果
+#[derive(Debug)]
+pub struct DescribeOutcome {
+    pub model: String,
+    /// 按模式处理后的文本（JSON 模式下为格式化后的 JSON）
+    pub text: String,
+    pub usage: Option<Value>,
+}
+
+impl OpenRouterServer {
+    /// 调用视觉模型描述图像，JSON 模式下校验输出结构，不合格时要求模型重试一次
+    pub(crate) async fn describe(
+        &self,
+        image_input: &str,
+        mode: DescribeMode,
+        max_chars: usize,
+        language: Option<&str>,
+    ) -> Result<DescribeOutcome, McpError> {
+        let model = self.config.vision_model.clone();
+        let resolved = self.resolve_image_input(image_input).await?;
+        let mut messages = vec![json!({
+            "role": "user",
+            "content": [
+                {"type": "text", "text": describe_prompt(mode, max_chars, language)},
+                {"type": "image_url", "image_url": {"url": resolved}}
+            ]
+        })];
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let mut request_body = json!({
+                "model": model,
+                "messages": messages,
+                "max_tokens": DEFAULT_MAX_TOKENS,
+                "temperature": DESCRIBE_TEMPERATURE
+            });
+            if mode == DescribeMode::Json {
+                request_body["response_format"] = json_response_format();
+            }
+            let response_data = self.send_chat_completion(&request_body).await?;
+            let (text, _) = extract_text_and_images(&response_data)?;
+            let usage = response_data.get("usage").cloned();
+
+            let text = match mode {
+                DescribeMode::Caption | DescribeMode::Detailed => text.trim().to_string(),
+                DescribeMode::AltText => truncate_alt_text(&text, max_chars),
+                DescribeMode::Json => match parse_description(&text) {
+                    Ok(description) => {
+                        serde_json::to_string_pretty(&description).unwrap_or_default()
+                    }
+                    Err(e) if attempts < MAX_JSON_ATTEMPTS => {
+                        tracing::debug!("视觉模型返回的 JSON 无效，重试: {}", e);
+                        messages.push(json!({"role": "assistant", "content": text}));
+                        messages.push(json!({
+                            "role": "user",
+                            "content": format!(
+                                "That reply was invalid ({}). Reply again with only the JSON object.",
+                                e
+                            )
+                        }));
+                        continue;
+                    }
+                    Err(e) => {
+                        return Err(McpError::internal_error(
+                            tr!("describe.invalid_json", error = e, text = text),
+                            None,
+                        ));
+                    }
+                },
+            };
+
+            return Ok(DescribeOutcome { model, text, usage });
+        }
+    }
+}
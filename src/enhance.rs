@@ -0,0 +1,114 @@
+use crate::{i18n::tr, server::OpenRouterServer, tools::extract_text_and_images};
+use rmcp::ErrorData as McpError;
+use serde_json::{Value, json};
+
+/// 默认的提示词增强模型
+pub const DEFAULT_TEXT_MODEL: &str = "google/gemini-2.5-flash";
+
+/// 改写提示词时使用的系统提示词
+const ENHANCE_SYSTEM_PROMPT: &str = "You rewrite short image-generation prompts into rich, \
+specific prompts for a text-to-image model. Keep the user's subject and intent, and never add \
+content the user did not ask for. Add concrete details about composition, setting, lighting, \
+color palette, style, lens or medium. Write in the same language as the user's prompt. Reply with \
+the rewritten prompt only: no preamble, no quotes, no markdown, at most 120 words.";
+
+/// 被安全策略拦截、疑似误判时改写提示词使用的系统提示词
+const REPHRASE_SYSTEM_PROMPT: &str = "An image-generation request was rejected by an automated \
+safety filter, but it appears to be a false positive. Rewrite the prompt so its benign intent is \
+unambiguous: keep the same subject, composition and style, keep every instruction about the input \
+images, and replace wording that could be misread as violent, sexual or otherwise harmful with \
+neutral descriptions. Do not add new content. Write in the same language as the prompt. Reply \
+with the rewritten prompt only: no preamble, no quotes, no markdown.";
+
+const ENHANCE_MAX_TOKENS: u32 = 400;
+const ENHANCE_TEMPERATURE: f64 = 0.7;
+
+/// 提示词增强的结果
+#[derive(Debug)]
+pub struct EnhancedPrompt {
+    pub original: String,
+    pub enhanced: String,
+    pub model: String,
+    pub usage: Option<Value>,
+}
+
+impl OpenRouterServer {
+    /// 使用文本模型改写提示词
+    pub(crate) async fn enhance_prompt_text(
+        &self,
+        prompt: &str,
+        style: Option<&str>,
+    ) -> Result<EnhancedPrompt, McpError> {
+        let mut user_content = prompt.to_string();
+        if let Some(style) = style.map(str::trim).filter(|s| !s.is_empty()) {
+            user_content.push_str(&format!("\n\nDesired style: {}", style));
+        }
+        self.rewrite_prompt(prompt, ENHANCE_SYSTEM_PROMPT, &user_content)
+            .await
+    }
+
+    /// 改写被安全策略拦截、疑似误判的提示词
+    pub(crate) async fn rephrase_blocked_prompt(
+        &self,
+        prompt: &str,
+    ) -> Result<EnhancedPrompt, McpError> {
+        self.rewrite_prompt(prompt, REPHRASE_SYSTEM_PROMPT, prompt)
+            .await
+    }
+
+    async fn rewrite_prompt(
+        &self,
+        prompt: &str,
+        system_prompt: &str,
+        user_content: &str,
+    ) -> Result<EnhancedPrompt, McpError> {
+        let model = self.config.text_model.clone();
+        let request_body = json!({
+            "model": model,
+            "messages": [
+                {"role": "system", "content": system_prompt},
+                {"role": "user", "content": user_content}
+            ],
+            "max_tokens": ENHANCE_MAX_TOKENS,
+            "temperature": ENHANCE_TEMPERATURE
+        });
+
+        let response_data = self.send_chat_completion(&request_body).await?;
+        let (text, _) = extract_text_and_images(&response_data)?;
+        let enhanced = clean_enhanced_prompt(&text);
+        if enhanced.is_empty() {
+            return Err(McpError::internal_error(tr!("enhance.empty_result"), None));
+        }
+
+        Ok(EnhancedPrompt {
+            original: prompt.to_string(),
+            enhanced,
+            model,
+            usage: response_data.get("usage").cloned(),
+        })
+    }
+}
+
+/// 去掉模型偶尔附带的引号、代码块和 "Prompt:" 前缀
+fn clean_enhanced_prompt(text: &str) -> String {
+    let mut cleaned = text.trim();
+    if let Some(inner) = cleaned
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+    {
+        // 跳过代码块的语言标记行
+        cleaned = inner
+            .split_once('\n')
+            .map_or(inner, |(_, body)| body)
+            .trim();
+    }
+    for prefix in ["Prompt:", "prompt:", "提示词：", "提示词:"] {
+        if let Some(rest) = cleaned.strip_prefix(prefix) {
+            cleaned = rest.trim();
+        }
+    }
+    cleaned
+        .trim_matches(|c| c == '"' || c == '“' || c == '”')
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,367 @@
+use crate::{i18n::tr, output::ToolOutput, safety::FinishStatus};
+use rmcp::{
+    ErrorData as McpError,
+    model::{CallToolResult, Content, ErrorCode},
+    schemars,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 工具调用失败的类别，随错误的 `data.kind` 返回给客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// 参数无效
+    InvalidParams,
+    /// 找不到输入的图像、生成记录、任务或会话
+    InputNotFound,
+    /// 输入或输出超过大小限制
+    InputTooLarge,
+    /// 上游拒绝了 API 密钥，或账户额度不足
+    UpstreamAuth,
+    /// 上游限流，稍后重试
+    RateLimited,
+    /// 请求被上游的内容安全策略拦截
+    PolicyBlocked,
+    /// 上游不可用：网络错误、超时、5xx 或无法解析的响应
+    UpstreamUnavailable,
+    /// 模型没有返回图像
+    NoImage,
+    /// 服务器内部错误
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidParams => "invalid_params",
+            ErrorKind::InputNotFound => "input_not_found",
+            ErrorKind::InputTooLarge => "input_too_large",
+            ErrorKind::UpstreamAuth => "upstream_auth",
+            ErrorKind::RateLimited => "rate_limited",
+            ErrorKind::PolicyBlocked => "policy_blocked",
+            ErrorKind::UpstreamUnavailable => "upstream_unavailable",
+            ErrorKind::NoImage => "no_image",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    /// 按上游返回的 HTTP 状态码归类
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            400 | 404 | 422 => ErrorKind::InvalidParams,
+            401 | 402 => ErrorKind::UpstreamAuth,
+            // OpenRouter 对需要审核的模型，在输入被标记时返回 403
+            403 => ErrorKind::PolicyBlocked,
+            413 => ErrorKind::InputTooLarge,
+            429 => ErrorKind::RateLimited,
+            _ => ErrorKind::UpstreamUnavailable,
+        }
+    }
+
+    /// 原样重试可能成功
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::RateLimited | ErrorKind::UpstreamUnavailable | ErrorKind::NoImage
+        )
+    }
+
+    /// 调用本身合法、在执行中失败的错误作为 `isError` 的工具结果返回，让模型能看到并调整；
+    /// 其余作为 MCP 协议错误返回
+    pub fn is_tool_error(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::UpstreamAuth
+                | ErrorKind::RateLimited
+                | ErrorKind::PolicyBlocked
+                | ErrorKind::UpstreamUnavailable
+                | ErrorKind::NoImage
+        )
+    }
+
+    /// 作为协议错误返回时使用的错误码
+    fn code(&self) -> ErrorCode {
+        match self {
+            ErrorKind::InvalidParams | ErrorKind::InputNotFound | ErrorKind::InputTooLarge => {
+                ErrorCode::INVALID_PARAMS
+            }
+            _ => ErrorCode::INTERNAL_ERROR,
+        }
+    }
+
+    /// 没有附带类别的错误按错误码归类
+    fn from_code(code: ErrorCode) -> Self {
+        if code == ErrorCode::INVALID_PARAMS || code == ErrorCode::INVALID_REQUEST {
+            ErrorKind::InvalidParams
+        } else {
+            ErrorKind::Internal
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 带类别的错误，可以经由 `anyhow::Error` 传递，在工具边界转换为 MCP 错误
+#[derive(Debug, Clone)]
+pub struct ToolError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// 上游返回的 HTTP 状态码
+    pub status: Option<u16>,
+    /// 上游建议的重试间隔（秒）
+    pub retry_after: Option<u64>,
+    /// 上游响应的结束状态，用于说明拦截或没有图像的原因
+    pub finish: Option<FinishStatus>,
+}
+
+impl ToolError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            status: None,
+            retry_after: None,
+            finish: None,
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidParams, message)
+    }
+
+    pub fn input_not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InputNotFound, message)
+    }
+
+    pub fn input_too_large(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InputTooLarge, message)
+    }
+
+    pub fn policy_blocked(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::PolicyBlocked, message)
+    }
+
+    pub fn upstream_unavailable(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::UpstreamUnavailable, message)
+    }
+
+    pub fn no_image(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NoImage, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+
+    /// 上游返回的非成功状态码
+    pub fn from_status(status: u16, message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::from_status(status), message).with_status(status)
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_retry_after(mut self, seconds: Option<u64>) -> Self {
+        self.retry_after = seconds;
+        self
+    }
+
+    /// 附带结束状态，没有任何状态信息时忽略
+    pub fn with_finish(mut self, finish: FinishStatus) -> Self {
+        self.finish = (!finish.is_empty()).then_some(finish);
+        self
+    }
+
+    /// 从保存的错误详情还原错误
+    pub fn from_details(details: ErrorDetails) -> Self {
+        Self {
+            kind: details.kind,
+            message: details.message,
+            status: details.status,
+            retry_after: details.retry_after,
+            finish: details.finish,
+        }
+    }
+
+    pub fn details(&self) -> ErrorDetails {
+        ErrorDetails {
+            kind: self.kind,
+            message: self.message.clone(),
+            retryable: self.kind.retryable(),
+            status: self.status,
+            retry_after: self.retry_after,
+            finish: self.finish.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+impl From<ToolError> for McpError {
+    fn from(error: ToolError) -> Self {
+        let data = serde_json::to_value(error.details()).ok();
+        McpError::new(error.kind.code(), error.message, data)
+    }
+}
+
+/// 错误的机器可读信息：协议错误放在 `error.data` 中，工具错误放在 `structuredContent.error` 中
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ErrorDetails {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// 原样重试是否可能成功
+    pub retryable: bool,
+    /// 上游返回的 HTTP 状态码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// 上游建议的重试间隔（秒）
+    #[serde(rename = "retry_after_secs", skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// 上游响应的结束状态：结束原因、拦截原因和被标记的安全类别
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish: Option<FinishStatus>,
+}
+
+/// 把 anyhow 错误转换为 MCP 错误：错误链中带类别的错误保留类别，其余按 `fallback` 归类；
+/// 消息使用最外层的完整描述
+pub fn from_anyhow(error: anyhow::Error, fallback: ErrorKind) -> McpError {
+    let message = error.to_string();
+    match error.downcast::<ToolError>() {
+        Ok(typed) => ToolError { message, ..typed }.into(),
+        Err(_) => ToolError::new(fallback, message).into(),
+    }
+}
+
+/// 参数校验失败，用于 `map_err`
+pub fn invalid_params(error: anyhow::Error) -> McpError {
+    from_anyhow(error, ErrorKind::InvalidParams)
+}
+
+/// 读取错误附带的详情；没有时按错误码补全，使所有错误都带有 `kind`
+pub fn details_of(error: &McpError) -> ErrorDetails {
+    error
+        .data
+        .as_ref()
+        .and_then(|data| serde_json::from_value::<ErrorDetails>(data.clone()).ok())
+        .unwrap_or_else(|| {
+            ToolError::new(ErrorKind::from_code(error.code), error.message.to_string()).details()
+        })
+}
+
+/// 在工具边界统一处理错误：执行中失败的作为 `isError` 的工具结果，其余补全 `data` 后作为协议错误
+pub fn into_tool_result(tool: &str, error: McpError) -> Result<CallToolResult, McpError> {
+    let details = details_of(&error);
+    if !details.kind.is_tool_error() {
+        let mut error = error;
+        if error.data.is_none() {
+            error.data = serde_json::to_value(&details).ok();
+        }
+        return Err(error);
+    }
+    let text = match details.retry_after {
+        Some(seconds) => tr!(
+            "errors.retry_after",
+            message = details.message,
+            seconds = seconds
+        ),
+        None => details.message.clone(),
+    };
+    let mut result = CallToolResult::error(vec![Content::text(text)]);
+    result.structured_content =
+        serde_json::to_value(ToolOutput::new(tool).with_error(details)).ok();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn http_statuses_map_to_kinds() {
+        let cases = [
+            (400, ErrorKind::InvalidParams),
+            (404, ErrorKind::InvalidParams),
+            (422, ErrorKind::InvalidParams),
+            (401, ErrorKind::UpstreamAuth),
+            (402, ErrorKind::UpstreamAuth),
+            (403, ErrorKind::PolicyBlocked),
+            (413, ErrorKind::InputTooLarge),
+            (429, ErrorKind::RateLimited),
+            (408, ErrorKind::UpstreamUnavailable),
+            (500, ErrorKind::UpstreamUnavailable),
+            (502, ErrorKind::UpstreamUnavailable),
+            (503, ErrorKind::UpstreamUnavailable),
+            (504, ErrorKind::UpstreamUnavailable),
+        ];
+        for (status, kind) in cases {
+            assert_eq!(ErrorKind::from_status(status), kind, "HTTP {}", status);
+            let error = ToolError::from_status(status, "upstream error");
+            assert_eq!(error.kind, kind);
+            assert_eq!(error.status, Some(status));
+        }
+    }
+
+    #[test]
+    fn details_survive_the_mcp_error() {
+        let error = ToolError::from_status(429, "slow down").with_retry_after(Some(7));
+        let details = details_of(&McpError::from(error));
+        assert_eq!(details.kind, ErrorKind::RateLimited);
+        assert!(details.retryable);
+        assert_eq!(details.status, Some(429));
+        assert_eq!(details.retry_after, Some(7));
+
+        // 没有附带详情的错误按错误码归类
+        let plain = details_of(&McpError::invalid_params("bad", None));
+        assert_eq!(plain.kind, ErrorKind::InvalidParams);
+        let internal = details_of(&McpError::internal_error("boom", None));
+        assert_eq!(internal.kind, ErrorKind::Internal);
+    }
+
+    #[test]
+    fn anyhow_errors_keep_their_kind_and_full_message() {
+        let error = anyhow::Error::from(ToolError::input_too_large("too big")).context("reading");
+        let error = invalid_params(error);
+        let details = details_of(&error);
+        assert_eq!(details.kind, ErrorKind::InputTooLarge);
+        assert_eq!(details.message, "reading");
+
+        let untyped = Err::<(), _>(anyhow::anyhow!("io failure"))
+            .context("saving")
+            .unwrap_err();
+        assert_eq!(
+            details_of(&from_anyhow(untyped, ErrorKind::Internal)).kind,
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn execution_failures_become_tool_results() {
+        let error = ToolError::from_status(429, "slow down").with_retry_after(Some(7));
+        let result = into_tool_result("generate_image", error.into()).unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["tool"], "generate_image");
+        assert_eq!(structured["error"]["kind"], "rate_limited");
+        assert_eq!(structured["error"]["retry_after_secs"], 7);
+
+        // 参数错误仍作为协议错误返回，并补全 data
+        let error =
+            into_tool_result("generate_image", McpError::invalid_params("bad", None)).unwrap_err();
+        assert_eq!(error.code, ErrorCode::INVALID_PARAMS);
+        assert_eq!(error.data.unwrap()["kind"], "invalid_params");
+    }
+}
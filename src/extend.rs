@@ -0,0 +1,246 @@
+use crate::{errors::ToolError, i18n::tr, imaging, transform};
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, Rgba, RgbaImage, imageops::FilterType};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+
+/// 待填充区域在画布上的颜色
+const FILL_COLOR: Rgba<u8> = Rgba([128, 128, 128, 255]);
+/// 模型输出的宽高比与画布相差超过此比例时，先从中心裁剪再缩放
+const ASPECT_TOLERANCE: f64 = 0.02;
+
+/// 各边扩展的像素数，未给出的边为 0
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExtendPadding {
+    pub top: Option<u32>,
+    pub right: Option<u32>,
+    pub bottom: Option<u32>,
+    pub left: Option<u32>,
+}
+
+/// 扩展后的画布，以及原图在画布中的位置
+#[derive(Debug, Clone, Copy)]
+pub struct ExtendPlan {
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ExtendPlan {
+    /// 按宽高比（原图居中）或各边像素数计算画布
+    pub fn new(
+        width: u32,
+        height: u32,
+        aspect: Option<&str>,
+        padding: Option<&ExtendPadding>,
+    ) -> Result<Self> {
+        let (canvas_width, canvas_height, x, y) = match (aspect, padding) {
+            (Some(_), Some(_)) => return Err(anyhow!(tr!("extend.aspect_and_padding"))),
+            (Some(aspect), None) => {
+                let target = transform::parse_aspect(aspect)?;
+                let (w, h) = if f64::from(width) / f64::from(height) < target {
+                    ((f64::from(height) * target).round() as u32, height)
+                } else {
+                    (width, (f64::from(width) / target).round() as u32)
+                };
+                let (w, h) = (w.max(width), h.max(height));
+                (w, h, (w - width) / 2, (h - height) / 2)
+            }
+            (None, Some(padding)) => {
+                let [top, right, bottom, left] =
+                    [padding.top, padding.right, padding.bottom, padding.left]
+                        .map(Option::unwrap_or_default);
+                let w = width
+                    .checked_add(left)
+                    .and_then(|w| w.checked_add(right))
+                    .ok_or_else(|| ToolError::input_too_large(tr!("extend.too_large")))?;
+                let h = height
+                    .checked_add(top)
+                    .and_then(|h| h.checked_add(bottom))
+                    .ok_or_else(|| ToolError::input_too_large(tr!("extend.too_large")))?;
+                (w, h, left, top)
+            }
+            (None, None) => return Err(anyhow!(tr!("extend.aspect_or_padding"))),
+        };
+        if canvas_width == width && canvas_height == height {
+            return Err(anyhow!(tr!(
+                "extend.nothing_to_fill",
+                width = width,
+                height = height
+            )));
+        }
+        imaging::ensure_output_size(canvas_width, canvas_height)?;
+        Ok(Self {
+            canvas_width,
+            canvas_height,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    /// 把原图放到灰色画布上，作为发送给模型的输入
+    pub fn build_canvas(&self, image: &DynamicImage) -> DynamicImage {
+        let mut canvas = RgbaImage::from_pixel(self.canvas_width, self.canvas_height, FILL_COLOR);
+        image::imageops::overlay(
+            &mut canvas,
+            &image.to_rgba8(),
+            i64::from(self.x),
+            i64::from(self.y),
+        );
+        DynamicImage::ImageRgba8(canvas)
+    }
+
+    /// 发送给模型的扩图指令，`hint` 为用户对新区域内容的描述
+    pub fn instruction(&self, hint: Option<&str>) -> String {
+        let mut instruction = format!(
+            "This image is a {cw}x{ch} pixel canvas. The original picture occupies the {w}x{h} \
+             pixel rectangle whose top-left corner is at ({x}, {y}); the flat gray (#808080) area \
+             around it is empty. Fill the empty area by extending the original scene naturally and \
+             seamlessly, continuing its lighting, perspective, colors, textures and style, and keep \
+             the original region unchanged. Do not add borders, frames or visible seams. Return \
+             only the complete image with the same {cw}:{ch} aspect ratio.",
+            cw = self.canvas_width,
+            ch = self.canvas_height,
+            w = self.width,
+            h = self.height,
+            x = self.x,
+            y = self.y
+        );
+        if let Some(hint) = hint.map(str::trim).filter(|hint| !hint.is_empty()) {
+            instruction.push_str(&format!(" Content for the new area: {}", hint));
+        }
+        instruction
+    }
+
+    /// 把模型输出对齐到画布尺寸：宽高比偏差过大时先从中心裁剪，再缩放到精确尺寸；
+    /// `original` 不为空时把原图像素覆盖回原位置。返回结果以及是否发生了裁剪
+    pub fn fit_output(
+        &self,
+        output: DynamicImage,
+        original: Option<&DynamicImage>,
+    ) -> (DynamicImage, bool) {
+        let (width, height) = (output.width(), output.height());
+        let target = f64::from(self.canvas_width) / f64::from(self.canvas_height);
+        let actual = f64::from(width) / f64::from(height);
+        let cropped = (actual / target - 1.0).abs() > ASPECT_TOLERANCE;
+        let output = if cropped {
+            let (w, h) = if actual > target {
+                ((f64::from(height) * target).round() as u32, height)
+            } else {
+                (width, (f64::from(width) / target).round() as u32)
+            };
+            let (w, h) = (w.clamp(1, width), h.clamp(1, height));
+            output.crop_imm((width - w) / 2, (height - h) / 2, w, h)
+        } else {
+            output
+        };
+        let output = if output.width() == self.canvas_width && output.height() == self.canvas_height
+        {
+            output
+        } else {
+            output.resize_exact(self.canvas_width, self.canvas_height, FilterType::Lanczos3)
+        };
+        let output = match original {
+            Some(original) => {
+                let mut canvas = output.to_rgba8();
+                image::imageops::overlay(
+                    &mut canvas,
+                    &original.to_rgba8(),
+                    i64::from(self.x),
+                    i64::from(self.y),
+                );
+                DynamicImage::ImageRgba8(canvas)
+            }
+            None => output,
+        };
+        (output, cropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+
+    fn padding(top: u32, right: u32, bottom: u32, left: u32) -> ExtendPadding {
+        ExtendPadding {
+            top: Some(top),
+            right: Some(right),
+            bottom: Some(bottom),
+            left: Some(left),
+        }
+    }
+
+    fn kind(error: anyhow::Error) -> Option<ErrorKind> {
+        error.downcast::<ToolError>().ok().map(|error| error.kind)
+    }
+
+    #[test]
+    fn padding_adds_to_each_side() {
+        let plan = ExtendPlan::new(100, 50, None, Some(&padding(10, 20, 30, 40))).unwrap();
+        assert_eq!((plan.canvas_width, plan.canvas_height), (160, 90));
+        assert_eq!((plan.x, plan.y), (40, 10));
+        assert_eq!((plan.width, plan.height), (100, 50));
+
+        // 未给出的边为 0
+        let right_only = ExtendPadding {
+            right: Some(25),
+            ..Default::default()
+        };
+        let plan = ExtendPlan::new(100, 50, None, Some(&right_only)).unwrap();
+        assert_eq!((plan.canvas_width, plan.canvas_height), (125, 50));
+        assert_eq!((plan.x, plan.y), (0, 0));
+    }
+
+    #[test]
+    fn aspect_centers_the_original() {
+        let wide = ExtendPlan::new(100, 100, Some("16:9"), None).unwrap();
+        assert_eq!((wide.canvas_width, wide.canvas_height), (178, 100));
+        assert_eq!((wide.x, wide.y), (39, 0));
+
+        let tall = ExtendPlan::new(160, 90, Some("1:1"), None).unwrap();
+        assert_eq!((tall.canvas_width, tall.canvas_height), (160, 160));
+        assert_eq!((tall.x, tall.y), (0, 35));
+    }
+
+    #[test]
+    fn invalid_plans_are_rejected() {
+        let extra = padding(1, 1, 1, 1);
+        assert!(ExtendPlan::new(100, 50, Some("1:1"), Some(&extra)).is_err());
+        assert!(ExtendPlan::new(100, 50, None, None).is_err());
+        // 目标比例与原图相同、或各边都为 0 时没有需要填充的区域
+        assert!(ExtendPlan::new(160, 90, Some("16:9"), None).is_err());
+        assert!(ExtendPlan::new(100, 50, None, Some(&ExtendPadding::default())).is_err());
+
+        let overflow = ExtendPlan::new(100, 50, None, Some(&padding(0, u32::MAX, 0, 1)));
+        assert_eq!(kind(overflow.unwrap_err()), Some(ErrorKind::InputTooLarge));
+        let huge = ExtendPlan::new(100, 50, None, Some(&padding(20_000, 20_000, 0, 0)));
+        assert_eq!(kind(huge.unwrap_err()), Some(ErrorKind::InputTooLarge));
+    }
+
+    #[test]
+    fn output_is_fitted_to_the_canvas() {
+        let plan = ExtendPlan::new(100, 100, None, Some(&padding(0, 100, 0, 0))).unwrap();
+        let original =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 100, Rgba([255, 0, 0, 255])));
+
+        // 比例接近时直接缩放
+        let (fitted, cropped) = plan.fit_output(DynamicImage::new_rgba8(400, 201), None);
+        assert!(!cropped);
+        assert_eq!((fitted.width(), fitted.height()), (200, 100));
+
+        // 比例偏差过大时先裁剪，原图像素覆盖回原位置
+        let (fitted, cropped) = plan.fit_output(DynamicImage::new_rgba8(300, 300), Some(&original));
+        assert!(cropped);
+        assert_eq!((fitted.width(), fitted.height()), (200, 100));
+        let fitted = fitted.to_rgba8();
+        assert_eq!(*fitted.get_pixel(50, 50), Rgba([255, 0, 0, 255]));
+        assert_eq!(*fitted.get_pixel(150, 50), Rgba([0, 0, 0, 0]));
+    }
+}
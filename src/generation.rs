@@ -0,0 +1,547 @@
+use crate::{
+    errors::{self, ToolError},
+    history::{self, HistoryRecord},
+    i18n::tr,
+    image_utils::{self, ImageInfo},
+    imaging,
+    metadata::{self, GenerationMetadata, InputDescriptor},
+    naming::{self, NamingContext},
+    roles::{self, ImageRole},
+    safety::{self, FinishStatus},
+    server::OpenRouterServer,
+    store::{ContentStore, STORE_DIR_NAME},
+    tools::extract_text_and_images,
+};
+use image::DynamicImage;
+use rmcp::ErrorData as McpError;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 请求上游模型时使用的默认参数
+pub const DEFAULT_MAX_TOKENS: u32 = 1000;
+pub const DEFAULT_TEMPERATURE: f64 = 0.7;
+
+/// 一次图像生成/编辑请求
+#[derive(Debug, Clone)]
+pub struct ImageRequest {
+    /// 发起请求的工具名称，如 `generate_image`
+    pub tool: String,
+    /// 文本提示词或编辑指令
+    pub prompt: String,
+    /// 输入图像（URL / base64 / 本地路径），为空表示纯文本生成
+    pub images: Vec<String>,
+    /// 每张输入图像的角色，为空表示不标注；非空时与 `images` 一一对应
+    pub image_roles: Vec<Option<ImageRole>>,
+    pub seed: Option<u64>,
+    pub model: String,
+    /// 工具收到的原始参数，写入历史记录
+    pub arguments: Value,
+    /// 由 regenerate 发起时，被重放的生成 ID
+    pub parent_id: Option<String>,
+    /// 多轮会话中此前的消息，按顺序放在本次用户消息之前
+    pub context: Vec<Value>,
+    /// 工具专属的文件名后缀（如 `_keyed`），追加在按模板渲染出的文件名主体之后；
+    /// 未配置文件名模板时文件名为 `{source_stem}` 加后缀
+    pub name_suffix: Option<String>,
+    /// 提示词经过增强时，用户最初提供的提示词
+    pub original_prompt: Option<String>,
+    /// 输入图像不是用户提供的原始文件（例如本地生成的画布）时，用于输出文件名的原始文件名主体
+    pub source_name: Option<String>,
+    /// 保存前对模型输出图像做的本地处理
+    pub output_processor: Option<OutputProcessor>,
+}
+
+/// 保存前对模型输出图像做的本地处理，例如把扩图结果对齐到画布尺寸
+#[derive(Clone)]
+pub struct OutputProcessor(Arc<dyn Fn(DynamicImage) -> anyhow::Result<DynamicImage> + Send + Sync>);
+
+impl OutputProcessor {
+    pub fn new(
+        process: impl Fn(DynamicImage) -> anyhow::Result<DynamicImage> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(process))
+    }
+
+    /// 处理响应中的每张 base64 图像，结果统一编码为 PNG
+    async fn apply(&self, images: Vec<Value>) -> Result<Vec<Value>, McpError> {
+        let mut processed = Vec::with_capacity(images.len());
+        for mut image in images {
+            let Some(url) = image.pointer("/image_url/url").and_then(Value::as_str) else {
+                processed.push(image);
+                continue;
+            };
+            if !url.starts_with("data:") {
+                tracing::warn!("模型返回的图像不是 base64 数据，跳过本地处理: {}", url);
+                processed.push(image);
+                continue;
+            }
+            let (_, bytes) = image_utils::decode_data_url(url).map_err(|e| {
+                McpError::internal_error(tr!("generation.decode_output_failed", error = e), None)
+            })?;
+            let process = self.0.clone();
+            let encoded = tokio::task::spawn_blocking(move || -> anyhow::Result<String> {
+                let decoded = image::load_from_memory(&bytes)?;
+                let output = imaging::encode_png(&process(decoded)?)?;
+                Ok(imaging::png_data_url(&output))
+            })
+            .await
+            .map_err(|e| McpError::internal_error(tr!("imaging.task_failed", error = e), None))?
+            .map_err(|e| {
+                McpError::internal_error(tr!("generation.process_output_failed", error = e), None)
+            })?;
+            image["image_url"]["url"] = json!(encoded);
+            processed.push(image);
+        }
+        Ok(processed)
+    }
+}
+
+impl std::fmt::Debug for OutputProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OutputProcessor")
+    }
+}
+
+/// 图像请求的结果
+#[derive(Debug)]
+pub struct ImageOutcome {
+    pub generation_id: String,
+    pub model: String,
+    pub save_directory: String,
+    /// 模型返回的文本（已移除内嵌的 base64 图像）
+    pub text: String,
+    pub images: Vec<ImageInfo>,
+    pub usage: Option<Value>,
+    /// 上游响应的结束状态
+    pub finish: FinishStatus,
+    /// 首次请求疑似被误拦截、改写提示词后重试成功时，实际使用的提示词
+    pub rephrased_prompt: Option<String>,
+    /// 本次发送的用户消息和模型回复（含图像），供多轮会话追加到上下文
+    pub user_message: Value,
+    pub assistant_message: Value,
+}
+
+impl ImageRequest {
+    pub fn new(tool: &str, prompt: &str, model: &str, arguments: Value) -> Self {
+        Self {
+            tool: tool.to_string(),
+            prompt: prompt.to_string(),
+            images: Vec::new(),
+            image_roles: Vec::new(),
+            seed: None,
+            model: model.to_string(),
+            arguments,
+            parent_id: None,
+            context: Vec::new(),
+            name_suffix: None,
+            original_prompt: None,
+            source_name: None,
+            output_processor: None,
+        }
+    }
+
+    pub fn with_images(mut self, images: Vec<String>) -> Self {
+        self.images = images;
+        self
+    }
+
+    pub fn with_image_roles(mut self, roles: Vec<Option<ImageRole>>) -> Self {
+        self.image_roles = roles;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_parent(mut self, parent_id: Option<String>) -> Self {
+        self.parent_id = parent_id;
+        self
+    }
+
+    pub fn with_context(mut self, context: Vec<Value>) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_original_prompt(mut self, original_prompt: Option<String>) -> Self {
+        self.original_prompt = original_prompt;
+        self
+    }
+
+    pub fn with_name_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.name_suffix = Some(suffix.into());
+        self
+    }
+
+    pub fn with_source_name(mut self, source_name: Option<String>) -> Self {
+        self.source_name = source_name;
+        self
+    }
+
+    pub fn with_output_processor(mut self, processor: OutputProcessor) -> Self {
+        self.output_processor = Some(processor);
+        self
+    }
+
+    fn parameters(&self) -> Value {
+        let mut parameters = json!({
+            "max_tokens": DEFAULT_MAX_TOKENS,
+            "temperature": DEFAULT_TEMPERATURE,
+            "seed": self.seed,
+        });
+        if let Some(original_prompt) = &self.original_prompt {
+            parameters["original_prompt"] = json!(original_prompt);
+        }
+        if !self.image_roles.is_empty() {
+            parameters["image_roles"] = json!(self.image_roles);
+        }
+        parameters
+    }
+
+    /// 主体图像（未标注角色时为第一张输入图像）为本地文件时，用其文件名作为输出文件名的基础
+    fn source_stem(&self) -> Option<String> {
+        if let Some(source_name) = &self.source_name {
+            return Some(source_name.clone());
+        }
+        let subject = self
+            .image_roles
+            .iter()
+            .position(|role| *role == Some(ImageRole::Subject))
+            .unwrap_or(0);
+        source_stem(self.images.get(subject)?)
+    }
+
+    fn image_role(&self, index: usize) -> Option<ImageRole> {
+        self.image_roles.get(index).copied().flatten()
+    }
+}
+
+/// 图像输入为本地文件时返回其文件名主体
+///
+/// 内容存储中的对象以哈希命名，按 URL / base64 输入处理
+pub fn source_stem(image_input: &str) -> Option<String> {
+    if image_input.starts_with("http://")
+        || image_input.starts_with("https://")
+        || image_input.starts_with("data:image/")
+        || Path::new(image_input)
+            .components()
+            .any(|c| c.as_os_str() == STORE_DIR_NAME)
+    {
+        None
+    } else {
+        Some(image_utils::extract_filename_without_extension(image_input))
+    }
+}
+
+impl OpenRouterServer {
+    /// 执行一次图像请求：解析输入、调用模型、保存输出，并写入生成历史
+    pub(crate) async fn execute_image_request(
+        &self,
+        request: ImageRequest,
+    ) -> Result<ImageOutcome, McpError> {
+        let started = Instant::now();
+        let generation_id = uuid::Uuid::new_v4().to_string();
+        let mut record = HistoryRecord::new(
+            &generation_id,
+            &request.tool,
+            &request.prompt,
+            &request.arguments,
+            &request.model,
+            &self.config.provider(),
+        );
+        record.parent_id = request.parent_id.clone();
+        if self.history.is_some() {
+            record.inputs = self.replayable_inputs(&request.images).await;
+        }
+
+        let mut result = self.run_image_request(&request, &generation_id).await;
+        if let Err(error) = &result
+            && self.should_rephrase(error)
+        {
+            match self.rephrase_blocked_prompt(&request.prompt).await {
+                Ok(rephrased) => {
+                    tracing::info!(
+                        "请求被安全策略拦截，疑似误判，改写提示词后重试: {}",
+                        rephrased.enhanced
+                    );
+                    let mut retry = request.clone();
+                    retry
+                        .original_prompt
+                        .get_or_insert_with(|| request.prompt.clone());
+                    retry.prompt = rephrased.enhanced.clone();
+                    result = self
+                        .run_image_request(&retry, &generation_id)
+                        .await
+                        .map(|outcome| ImageOutcome {
+                            rephrased_prompt: Some(rephrased.enhanced),
+                            ..outcome
+                        });
+                }
+                Err(e) => tracing::warn!("改写被拦截的提示词失败: {}", e.message),
+            }
+        }
+
+        record.latency_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(outcome) => {
+                record.status = "success".to_string();
+                record.usage = outcome.usage.clone();
+                record.response_text = Some(outcome.text.clone());
+                record.saved_paths = outcome
+                    .images
+                    .iter()
+                    .filter_map(|img| img.saved_path.clone())
+                    .collect();
+            }
+            Err(e) => {
+                record.status = "error".to_string();
+                record.error = Some(e.message.to_string());
+            }
+        }
+        self.record_history(record).await;
+
+        result
+    }
+
+    async fn run_image_request(
+        &self,
+        request: &ImageRequest,
+        generation_id: &str,
+    ) -> Result<ImageOutcome, McpError> {
+        let mut content = vec![json!({
+            "type": "text",
+            "text": request.prompt
+        })];
+        let mut inputs = Vec::with_capacity(request.images.len());
+        for (index, image_input) in request.images.iter().enumerate() {
+            let resolved = self.resolve_image_input(image_input).await?;
+            let role = request.image_role(index);
+            inputs.push(InputDescriptor::new(image_input, &resolved).with_role(role));
+            // 标注了角色时，在每张图像前插入说明，让模型区分主体和参考图
+            if !request.image_roles.is_empty() {
+                content.push(json!({
+                    "type": "text",
+                    "text": roles::image_label(index + 1, role)
+                }));
+            }
+            content.push(json!({
+                "type": "image_url",
+                "image_url": {"url": resolved}
+            }));
+        }
+
+        let user_message = json!({
+            "role": "user",
+            "content": content
+        });
+        let mut messages = request.context.clone();
+        messages.push(user_message.clone());
+
+        let mut request_body = json!({
+            "model": request.model,
+            "messages": messages,
+            "max_tokens": DEFAULT_MAX_TOKENS,
+            "temperature": DEFAULT_TEMPERATURE
+        });
+        if let Some(seed) = request.seed {
+            request_body["seed"] = json!(seed);
+        }
+        let mut metadata = GenerationMetadata::new(
+            &request.tool,
+            &request.prompt,
+            &request.model,
+            &self.config.provider(),
+            request.parameters(),
+            inputs,
+        );
+        metadata.generation_id = Some(generation_id.to_string());
+
+        let response_data = self.send_chat_completion(&request_body).await?;
+        let (text, images_array) = extract_text_and_images(&response_data)?;
+        let finish = FinishStatus::from_response(&response_data);
+        if images_array.is_empty() {
+            return Err(finish.no_image_error(&text).into());
+        }
+        let images_array = match &request.output_processor {
+            Some(processor) => processor.apply(images_array).await?,
+            None => images_array,
+        };
+        let usage = response_data.get("usage").cloned();
+        metadata.complete(&text, usage.as_ref());
+
+        let mut assistant_content = vec![json!({"type": "text", "text": text})];
+        for image in &images_array {
+            if let Some(image_url) = image.get("image_url") {
+                assistant_content.push(json!({"type": "image_url", "image_url": image_url}));
+            }
+        }
+        let assistant_message = json!({
+            "role": "assistant",
+            "content": assistant_content
+        });
+
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        let source_stem = request.source_stem();
+        let template = match (&self.config.filename_template, &source_stem) {
+            (Some(template), _) => template.as_str(),
+            (None, _) if request.name_suffix.is_some() => naming::DEFAULT_SUFFIXED_TEMPLATE,
+            (None, _) if request.images.is_empty() && request.context.is_empty() => {
+                naming::DEFAULT_GENERATE_TEMPLATE
+            }
+            (None, Some(_)) => naming::DEFAULT_EDIT_TEMPLATE,
+            (None, None) => naming::DEFAULT_EDIT_FALLBACK_TEMPLATE,
+        };
+        let naming = NamingContext::new(template, &request.model, &request.prompt)
+            .with_seed(request.seed)
+            .with_source_stem(source_stem)
+            .with_suffix(request.name_suffix.clone());
+        let images = image_utils::save_response_images(
+            &images_array,
+            Some(&current_save_dir),
+            &naming,
+            &metadata,
+            self.config.save_options(),
+        );
+
+        Ok(ImageOutcome {
+            generation_id: generation_id.to_string(),
+            model: request.model.clone(),
+            save_directory: current_save_dir,
+            text,
+            images,
+            usage,
+            finish,
+            rephrased_prompt: None,
+            user_message,
+            assistant_message,
+        })
+    }
+
+    /// 把输入图像转换为日后可以重放的引用
+    ///
+    /// URL 原样保留；本地文件记录绝对路径；base64 数据写入内容存储（开启去重时）
+    /// 或保存目录下的 `inputs/` 后记录其路径，避免把完整图像写入历史数据库
+    async fn replayable_inputs(&self, images: &[String]) -> Vec<String> {
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        images
+            .iter()
+            .map(|input| {
+                replayable_input(input, &current_save_dir, self.config.dedupe).unwrap_or_else(|e| {
+                    tracing::warn!("无法保存可重放的输入图像: {}", e);
+                    history::redact_data_urls(&json!(input))
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+            })
+            .collect()
+    }
+
+    /// 调用上游 chat/completions 接口并返回响应 JSON
+    pub(crate) async fn send_chat_completion(
+        &self,
+        request_body: &Value,
+    ) -> Result<Value, McpError> {
+        let url = format!("{}/chat/completions", self.config.base_url);
+        let response = self
+            .client
+            .post(&url)
+            .json(request_body)
+            .send()
+            .await
+            .map_err(|e| {
+                ToolError::upstream_unavailable(tr!("generation.request_failed", error = e))
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| tr!("generation.no_error_detail"));
+            // OpenRouter 审核拦截时在错误体的 metadata.reasons 中给出原因
+            let finish = serde_json::from_str::<Value>(&error_text)
+                .map(|body| FinishStatus::from_response(&body))
+                .unwrap_or_default();
+            return Err(ToolError::from_status(
+                status.as_u16(),
+                tr!("generation.api_error", status = status, error = error_text),
+            )
+            .with_retry_after(retry_after)
+            .with_finish(finish)
+            .into());
+        }
+
+        let mut response_data = response.json::<Value>().await.map_err(|e| {
+            McpError::from(ToolError::upstream_unavailable(tr!(
+                "generation.parse_response_failed",
+                error = e
+            )))
+        })?;
+        self.account_usage(request_body, &mut response_data).await;
+        Ok(response_data)
+    }
+
+    /// 开启了拦截重试、且拦截看起来是误判时，改写提示词重试一次
+    fn should_rephrase(&self, error: &McpError) -> bool {
+        self.config.retry_blocked && safety::rephrase_may_help(&errors::details_of(error))
+    }
+
+    /// 写入生成历史；历史功能关闭或写入失败时只记录日志，不影响工具结果
+    pub(crate) async fn record_history(&self, record: HistoryRecord) {
+        if let Some(history) = &self.history
+            && let Err(e) = history.insert(record).await
+        {
+            tracing::warn!("写入生成历史失败: {}", e);
+        }
+    }
+}
+
+/// 未开启去重时，保存 base64 输入图像的子目录
+const INPUTS_DIR_NAME: &str = "inputs";
+
+fn replayable_input(input: &str, save_directory: &str, dedupe: bool) -> anyhow::Result<String> {
+    if input.starts_with("http://") || input.starts_with("https://") {
+        return Ok(input.to_string());
+    }
+    if input.starts_with("data:image/") {
+        let (image_type, bytes) = image_utils::decode_data_url(input)?;
+        let sha256 = metadata::sha256_hex(&bytes);
+        let extension = image_utils::extension_for_image_type(&image_type);
+        if dedupe {
+            let (object_path, _) =
+                ContentStore::new(Path::new(save_directory)).put(&bytes, &sha256, &extension)?;
+            return Ok(object_path.to_string_lossy().to_string());
+        }
+        // 以哈希命名，同一输入重复使用时不会重复写入
+        let directory = Path::new(save_directory).join(INPUTS_DIR_NAME);
+        let path = directory.join(format!("input_{}.{}", &sha256[..16], extension));
+        if !path.is_file() {
+            std::fs::create_dir_all(&directory)?;
+            image_utils::write_file_atomic(&path, &bytes)?;
+        }
+        return Ok(path.to_string_lossy().to_string());
+    }
+    match image_utils::resolve_local_image_path(input, save_directory) {
+        Some(path) => Ok(std::fs::canonicalize(&path)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()),
+        None => Ok(input.to_string()),
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{i18n::tr, sqlite::Database};
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use rusqlite::{OptionalExtension, Row, params, params_from_iter};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// 默认的历史数据库文件（相对保存目录）
+pub const DEFAULT_HISTORY_FILE: &str = ".nano-banana/history.sqlite3";
+
+/// 单次查询最多返回的记录数
+pub const MAX_SEARCH_LIMIT: u32 = 200;
+
+/// base64 data URL 在写入历史前保留的字符数
+const DATA_URL_KEEP_CHARS: usize = 64;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS generations (
+    id            TEXT PRIMARY KEY,
+    tool          TEXT NOT NULL,
+    prompt        TEXT NOT NULL,
+    arguments     TEXT NOT NULL,
+    model         TEXT NOT NULL,
+    provider      TEXT NOT NULL,
+    usage         TEXT,
+    latency_ms    INTEGER NOT NULL,
+    status        TEXT NOT NULL,
+    error         TEXT,
+    response_text TEXT,
+    saved_paths   TEXT NOT NULL,
+    created_at    TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_generations_created_at ON generations(created_at);
+CREATE INDEX IF NOT EXISTS idx_generations_model ON generations(model);
+CREATE INDEX IF NOT EXISTS idx_generations_status ON generations(status);
+";
+
+/// 按顺序执行的结构迁移，已执行的数量记录在 `PRAGMA user_version` 中
+const MIGRATIONS: &[&str] = &[
+    // 可重放的输入图像引用，以及 regenerate 产生的父子关系
+    "ALTER TABLE generations ADD COLUMN inputs TEXT NOT NULL DEFAULT '[]';
+     ALTER TABLE generations ADD COLUMN parent_id TEXT;
+     CREATE INDEX IF NOT EXISTS idx_generations_parent_id ON generations(parent_id);",
+];
+
+/// 统一的时间戳格式，保证按字符串比较即按时间排序
+pub fn timestamp_now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// 一次 generate_image / edit_image 调用的历史记录
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryRecord {
+    pub id: String,
+    pub tool: String,
+    pub prompt: String,
+    pub arguments: Value,
+    pub model: String,
+    pub provider: String,
+    pub usage: Option<Value>,
+    pub latency_ms: u64,
+    pub status: String,
+    pub error: Option<String>,
+    pub response_text: Option<String>,
+    pub saved_paths: Vec<String>,
+    /// 可重放的输入图像：URL、本地文件绝对路径或内容存储中的对象路径
+    pub inputs: Vec<String>,
+    /// 由 regenerate 产生时，被重放的原始生成 ID
+    pub parent_id: Option<String>,
+    pub created_at: String,
+}
+
+impl HistoryRecord {
+    pub fn new(
+        id: &str,
+        tool: &str,
+        prompt: &str,
+        arguments: &Value,
+        model: &str,
+        provider: &str,
+    ) -> Self {
+        Self {
+            id: id.to_string(),
+            tool: tool.to_string(),
+            prompt: prompt.to_string(),
+            arguments: redact_data_urls(arguments),
+            model: model.to_string(),
+            provider: provider.to_string(),
+            usage: None,
+            latency_ms: 0,
+            status: "pending".to_string(),
+            error: None,
+            response_text: None,
+            saved_paths: Vec::new(),
+            inputs: Vec::new(),
+            parent_id: None,
+            created_at: timestamp_now(),
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        fn parse_json<T: serde::de::DeserializeOwned>(text: Option<String>) -> Option<T> {
+            text.and_then(|t| serde_json::from_str(&t).ok())
+        }
+        Ok(Self {
+            id: row.get("id")?,
+            tool: row.get("tool")?,
+            prompt: row.get("prompt")?,
+            arguments: parse_json(row.get("arguments")?).unwrap_or(Value::Null),
+            model: row.get("model")?,
+            provider: row.get("provider")?,
+            usage: parse_json(row.get("usage")?),
+            latency_ms: row.get::<_, i64>("latency_ms")? as u64,
+            status: row.get("status")?,
+            error: row.get("error")?,
+            response_text: row.get("response_text")?,
+            saved_paths: parse_json(row.get("saved_paths")?).unwrap_or_default(),
+            inputs: parse_json(row.get("inputs")?).unwrap_or_default(),
+            parent_id: row.get("parent_id")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+/// search_history 的过滤条件
+#[derive(Debug, Default)]
+pub struct HistoryQuery {
+    pub text: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub model: Option<String>,
+    pub status: Option<String>,
+    pub tool: Option<String>,
+    pub limit: u32,
+}
+
+/// 基于 SQLite 的生成历史存储
+#[derive(Clone)]
+pub struct HistoryStore {
+    db: Database,
+    path: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path, SCHEMA, MIGRATIONS)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn insert(&self, record: HistoryRecord) -> Result<()> {
+        self.db
+            .with_conn(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO generations
+                    (id, tool, prompt, arguments, model, provider, usage, latency_ms,
+                     status, error, response_text, saved_paths, inputs, parent_id, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                    params![
+                        record.id,
+                        record.tool,
+                        record.prompt,
+                        record.arguments.to_string(),
+                        record.model,
+                        record.provider,
+                        record.usage.as_ref().map(|u| u.to_string()),
+                        record.latency_ms as i64,
+                        record.status,
+                        record.error,
+                        record.response_text,
+                        serde_json::to_string(&record.saved_paths)?,
+                        serde_json::to_string(&record.inputs)?,
+                        record.parent_id,
+                        record.created_at,
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<HistoryRecord>> {
+        let id = id.to_string();
+        self.db
+            .with_conn(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT * FROM generations WHERE id = ?1",
+                        [id],
+                        HistoryRecord::from_row,
+                    )
+                    .optional()?)
+            })
+            .await
+    }
+
+    pub async fn search(&self, query: HistoryQuery) -> Result<Vec<HistoryRecord>> {
+        self.db
+            .with_conn(move |conn| {
+                let mut sql = String::from("SELECT * FROM generations WHERE 1 = 1");
+                let mut values: Vec<String> = Vec::new();
+
+                if let Some(text) = &query.text {
+                    let pattern = format!("%{}%", escape_like(text));
+                    sql.push_str(
+                        " AND (prompt LIKE ? ESCAPE '\\' OR response_text LIKE ? ESCAPE '\\')",
+                    );
+                    values.push(pattern.clone());
+                    values.push(pattern);
+                }
+                if let Some(since) = &query.since {
+                    sql.push_str(" AND created_at >= ?");
+                    values.push(since.clone());
+                }
+                if let Some(until) = &query.until {
+                    sql.push_str(" AND created_at <= ?");
+                    values.push(until.clone());
+                }
+                if let Some(model) = &query.model {
+                    sql.push_str(" AND model LIKE ? ESCAPE '\\'");
+                    values.push(format!("%{}%", escape_like(model)));
+                }
+                if let Some(status) = &query.status {
+                    sql.push_str(" AND status = ?");
+                    values.push(status.clone());
+                }
+                if let Some(tool) = &query.tool {
+                    sql.push_str(" AND tool = ?");
+                    values.push(tool.clone());
+                }
+                sql.push_str(&format!(
+                    " ORDER BY created_at DESC LIMIT {}",
+                    query.limit.clamp(1, MAX_SEARCH_LIMIT)
+                ));
+
+                let mut stmt = conn.prepare(&sql)?;
+                let records = stmt
+                    .query_map(params_from_iter(values.iter()), HistoryRecord::from_row)?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(records)
+            })
+            .await
+    }
+}
+
+/// 将用户提供的日期解析为历史记录使用的时间戳格式
+///
+/// 支持 RFC 3339 时间或 `YYYY-MM-DD` 日期；日期作为上限时取当天结束
+pub fn parse_time_bound(input: &str, end_of_day: bool) -> Result<String> {
+    let input = input.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::Millis, true));
+    }
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .map_err(|_| anyhow!(tr!("history.invalid_time", value = input)))?;
+    let time = if end_of_day {
+        date.and_hms_milli_opt(23, 59, 59, 999)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    }
+    .ok_or_else(|| anyhow!(tr!("history.invalid_date", value = input)))?;
+    Ok(time.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// 是否为 `data:<type>/<subtype>;base64,` 形式的 data URL
+fn is_base64_data_url(text: &str) -> bool {
+    let Some((header, _)) = text.split_once(";base64,") else {
+        return false;
+    };
+    let Some(mime) = header.strip_prefix("data:") else {
+        return false;
+    };
+    mime.split_once('/').is_some_and(|(kind, subtype)| {
+        [kind, subtype].iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        })
+    })
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// 截断参数中的 base64 data URL，避免把完整的 base64 图像写入历史数据库
+///
+/// 只处理 `data:<mime>;base64,` 形式的值，普通文本（如以 `data:` 开头的提示词）保持原样；
+/// 截断标记固定为英文，数据库内容不随界面语言变化
+pub fn redact_data_urls(value: &Value) -> Value {
+    match value {
+        Value::String(s) if is_base64_data_url(s) => {
+            let keep = s
+                .char_indices()
+                .nth(DATA_URL_KEEP_CHARS)
+                .map_or(s.len(), |(index, _)| index);
+            if keep == s.len() {
+                return value.clone();
+            }
+            Value::String(format!("{}…({} chars)", &s[..keep], s.len()))
+        }
+        Value::Array(items) => Value::Array(items.iter().map(redact_data_urls).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), redact_data_urls(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn base64_data_urls_are_truncated() {
+        let data_url = format!("data:image/png;base64,{}", "A".repeat(200));
+        let redacted = redact_data_urls(&json!({"images": [data_url]}));
+        let stored = redacted["images"][0].as_str().unwrap();
+        assert_eq!(stored, format!("{}…(222 chars)", &data_url[..64]));
+    }
+
+    #[test]
+    fn prompts_starting_with_data_are_kept() {
+        let prompt = format!("data: {}", "猫".repeat(100));
+        let arguments = json!({"prompt": prompt});
+        assert_eq!(redact_data_urls(&arguments), arguments);
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        // 非法但形式匹配的 data URL 中出现多字节字符时，按字符而不是字节截断
+        let data_url = format!("data:image/png;base64,{}", "猫".repeat(100));
+        let stored = redact_data_urls(&json!(data_url));
+        let stored = stored.as_str().unwrap();
+        let (prefix, _) = stored.split_once('…').unwrap();
+        assert_eq!(prefix.chars().count(), DATA_URL_KEEP_CHARS);
+    }
+
+    #[test]
+    fn short_data_urls_are_kept() {
+        let data_url = json!("data:image/gif;base64,R0lGOD");
+        assert_eq!(redact_data_urls(&data_url), data_url);
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{Result, anyhow};
+use clap::Command;
+use rmcp::model::{JsonObject, Tool};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// 服务器消息使用的语言
+///
+/// 运行时消息（错误、响应文本）在每种语言的消息目录中都有完整条目；
+/// 工具描述、参数 schema 和命令行帮助以源码中的中文为准，其他语言的目录按需覆盖
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    ZhCn,
+    En,
+}
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::ZhCn => "zh-CN",
+            Locale::En => "en",
+        }
+    }
+
+    /// 按 `--lang` 命令行参数、`MCP_LANG` 环境变量的顺序确定语言，都未设置或无法识别时使用默认值
+    pub fn detect(args: &[String]) -> Self {
+        crate::config::OpenRouterConfig::get_flag_from_args(args, "--lang")
+            .or_else(|| std::env::var("MCP_LANG").ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    fn catalog(&self) -> &'static HashMap<String, String> {
+        static ZH_CN: OnceLock<HashMap<String, String>> = OnceLock::new();
+        static EN: OnceLock<HashMap<String, String>> = OnceLock::new();
+        match self {
+            Locale::ZhCn => {
+                ZH_CN.get_or_init(|| parse_catalog(include_str!("../locales/zh-CN.json")))
+            }
+            Locale::En => EN.get_or_init(|| parse_catalog(include_str!("../locales/en.json"))),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().replace('_', "-").as_str() {
+            "zh" | "zh-cn" | "zh-hans" => Ok(Locale::ZhCn),
+            "en" | "en-us" | "en-gb" => Ok(Locale::En),
+            _ => Err(anyhow!(tr!("config.invalid_locale", value = value))),
+        }
+    }
+}
+
+fn parse_catalog(source: &str) -> HashMap<String, String> {
+    serde_json::from_str(source).expect("内置消息目录必须是字符串到字符串的 JSON 对象")
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// 设置进程使用的语言，只在启动时调用一次
+pub fn init(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+pub fn locale() -> Locale {
+    LOCALE.get().copied().unwrap_or_default()
+}
+
+/// 当前语言目录中的条目，没有时返回 None
+pub fn lookup(key: &str) -> Option<&'static str> {
+    locale().catalog().get(key).map(String::as_str)
+}
+
+/// 取出当前语言的消息并替换 `{name}` 占位符；缺少条目时回退到中文目录，再回退到键名本身
+pub fn message(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let template = lookup(key)
+        .or_else(|| Locale::ZhCn.catalog().get(key).map(String::as_str))
+        .unwrap_or(key);
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            args.iter()
+                .find(|(arg, _)| *arg == name)
+                .map(|(_, value)| (value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                output.push_str(&value.to_string());
+                rest = &after[end + 1..];
+            }
+            // 不是参数的花括号原样保留，如 `{source_stem}`
+            None => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// 按当前语言取出消息：`tr!("key")` 或 `tr!("key", name = value, ...)`
+macro_rules! tr {
+    ($key:literal $(,)?) => {
+        $crate::i18n::message($key, &[])
+    };
+    ($key:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::message(
+            $key,
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+],
+        )
+    };
+}
+pub(crate) use tr;
+
+/// 用当前语言覆盖工具描述、参数 schema 和输出 schema 中的说明文字
+pub fn localize_tool(tool: &mut Tool) {
+    if let Some(description) = lookup(&format!("tool.{}", tool.name)) {
+        tool.description = Some(description.to_string().into());
+    }
+    tool.input_schema = Arc::new(localize_schema(&tool.input_schema));
+    if let Some(output_schema) = &tool.output_schema {
+        tool.output_schema = Some(Arc::new(localize_schema(output_schema)));
+    }
+}
+
+/// schema 中的说明按 `schema.<类型名>[.<字段或枚举值>...]` 查找，示例按同一路径加 `.example` 查找
+fn localize_schema(schema: &JsonObject) -> JsonObject {
+    let mut schema = schema.clone();
+    if let Some(title) = schema
+        .get("title")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+    {
+        localize_node(&mut schema, &title);
+    }
+    for key in ["definitions", "$defs"] {
+        if let Some(Value::Object(definitions)) = schema.get_mut(key) {
+            for (name, definition) in definitions.iter_mut() {
+                if let Value::Object(definition) = definition {
+                    localize_node(definition, name);
+                }
+            }
+        }
+    }
+    schema
+}
+
+fn localize_node(node: &mut JsonObject, path: &str) {
+    if node.contains_key("description")
+        && let Some(description) = lookup(&format!("schema.{}", path))
+    {
+        node.insert("description".to_string(), Value::from(description));
+    }
+    if let Some(Value::Array(examples)) = node.get_mut("examples")
+        && let Some(example) = lookup(&format!("schema.{}.example", path))
+    {
+        for value in examples.iter_mut().filter(|value| value.is_string()) {
+            *value = Value::from(example);
+        }
+    }
+    if let Some(Value::Object(properties)) = node.get_mut("properties") {
+        for (name, property) in properties.iter_mut() {
+            if let Value::Object(property) = property {
+                localize_node(property, &format!("{}.{}", path, name));
+            }
+        }
+    }
+    if let Some(Value::Object(items)) = node.get_mut("items") {
+        localize_node(items, path);
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(variants)) = node.get_mut(key) {
+            for variant in variants.iter_mut() {
+                if let Value::Object(variant) = variant {
+                    // 枚举值和带标签的变体以标签值作为路径的一段
+                    let tag = variant_tag(variant);
+                    match tag {
+                        Some(tag) => localize_node(variant, &format!("{}.{}", path, tag)),
+                        None => localize_node(variant, path),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn variant_tag(variant: &JsonObject) -> Option<String> {
+    if let Some(tag) = variant.get("const").and_then(Value::as_str) {
+        return Some(tag.to_string());
+    }
+    variant
+        .get("properties")
+        .and_then(Value::as_object)?
+        .values()
+        .find_map(|property| property.get("const").and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+/// 用当前语言覆盖命令行的说明和各参数的帮助文字
+pub fn localize_command(mut command: Command) -> Command {
+    if let Some(about) = lookup("cli.about") {
+        command = command.about(about);
+    }
+    if let Some(long_about) = lookup("cli.long_about") {
+        command = command.long_about(long_about);
+    }
+    let ids: Vec<String> = command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    for id in ids {
+        if let Some(help) = lookup(&format!("cli.arg.{}", id)) {
+            command = command.mut_arg(id, |arg| arg.help(help));
+        }
+    }
+    command
+}
@@ -1,27 +1,145 @@
+use crate::errors::ToolError;
+use crate::i18n::tr;
+use crate::imaging;
+use crate::metadata::{self, GenerationMetadata, OutputDescriptor};
+use crate::naming::NamingContext;
+use crate::provenance;
+use crate::store::{self, ContentStore};
 use anyhow::{Result, anyhow};
 use base64::{Engine as _, engine::general_purpose};
 use chrono::Utc;
+use std::collections::HashSet;
 use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
 
-/// 生成递增的文件名，避免重复
-fn generate_incremental_filename(base_name: &str, extension: &str, directory: &str) -> String {
-    let dir_path = Path::new(directory);
-    let mut counter = 1;
+/// 临时文件前缀，写入过程中的文件以此命名，崩溃后残留的文件可被安全清理
+const TEMP_FILE_PREFIX: &str = ".nano-banana-";
+const TEMP_FILE_SUFFIX: &str = ".tmp";
 
-    loop {
-        let filename = if counter == 1 {
-            format!("{}.{}", base_name, extension)
-        } else {
-            format!("{}_{}.{}", base_name, counter, extension)
-        };
+/// 临时文件超过这么久未修改才视为遗留文件；同一目录可能被其他实例同时写入
+const TEMP_FILE_STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// 输入图像（远程下载、本地文件或 data URL）允许的最大字节数
+pub const MAX_INPUT_BYTES: u64 = 32 * 1024 * 1024;
+
+/// 文件名冲突时的最大重试次数
+pub(crate) const MAX_SAVE_ATTEMPTS: u32 = 10_000;
+
+/// 生成第 counter 个候选文件名：第一个不带序号，之后追加 `_N`
+pub(crate) fn incremental_filename(base_name: &str, extension: &str, counter: u32) -> String {
+    if counter == 1 {
+        format!("{}.{}", base_name, extension)
+    } else {
+        format!("{}_{}.{}", base_name, counter, extension)
+    }
+}
+
+/// 在目标目录中创建临时文件，保证与最终文件位于同一文件系统以便原子重命名
+fn create_temp_file(directory: &Path) -> Result<tempfile::NamedTempFile> {
+    sweep_directory_once(directory);
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(TEMP_FILE_PREFIX).suffix(TEMP_FILE_SUFFIX);
+    // tempfile 默认使用 0600 权限，保存的图片应与普通文件一样可被其他程序读取
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o644));
+    }
+    Ok(builder.tempfile_in(directory)?)
+}
 
-        let filepath = dir_path.join(&filename);
-        if !filepath.exists() {
-            return filename;
+/// 原子地把字节写入目录中一个尚不存在的文件
+///
+/// 先写入同目录下的临时文件并落盘，再以 no-clobber 方式重命名到目标文件名；
+/// 如果目标已被其他请求占用，则递增序号重试。写入中途崩溃只会留下临时文件，
+/// 不会在保存目录中出现截断的图片。
+pub fn write_new_file_atomic(
+    directory: &Path,
+    base_name: &str,
+    extension: &str,
+    bytes: &[u8],
+) -> Result<PathBuf> {
+    let mut temp_file = create_temp_file(directory)?;
+    temp_file.write_all(bytes)?;
+    temp_file.as_file().sync_all()?;
+
+    for counter in 1..=MAX_SAVE_ATTEMPTS {
+        let filepath = directory.join(incremental_filename(base_name, extension, counter));
+        if filepath.exists() {
+            continue;
+        }
+        match temp_file.persist_noclobber(&filepath) {
+            Ok(_) => return Ok(filepath),
+            Err(e) if e.error.kind() == ErrorKind::AlreadyExists => {
+                // 并发请求抢先占用了该文件名，取回临时文件继续尝试下一个序号
+                temp_file = e.file;
+            }
+            Err(e) => return Err(anyhow!(tr!("save.persist_failed", error = e.error))),
+        }
+    }
+
+    Err(anyhow!(tr!(
+        "save.no_available_name",
+        name = base_name,
+        extension = extension,
+        attempts = MAX_SAVE_ATTEMPTS
+    )))
+}
+
+/// 清理保存目录（包括模板创建的子目录和 `.store`）中由崩溃或中断遗留的临时文件
+pub fn cleanup_stale_temp_files(directory: &Path) {
+    remove_stale_temp_files(directory, true);
+}
+
+/// 每个目录在本进程中第一次写入时清理一次遗留的临时文件，
+/// 覆盖保存目录之外的输出位置（如本地输入图像所在的目录）
+fn sweep_directory_once(directory: &Path) {
+    static SWEPT: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    let first_write = SWEPT
+        .get_or_init(Default::default)
+        .lock()
+        .map(|mut swept| swept.insert(directory.to_path_buf()))
+        .unwrap_or(false);
+    if first_write {
+        remove_stale_temp_files(directory, false);
+    }
+}
+
+/// 删除目录中超过 [`TEMP_FILE_STALE_AFTER`] 未修改的临时文件，不跟随符号链接
+fn remove_stale_temp_files(directory: &Path, recursive: bool) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if recursive {
+                remove_stale_temp_files(&entry.path(), true);
+            }
+            continue;
+        }
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !file_type.is_file()
+            || !name.starts_with(TEMP_FILE_PREFIX)
+            || !name.ends_with(TEMP_FILE_SUFFIX)
+        {
+            continue;
+        }
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age >= TEMP_FILE_STALE_AFTER);
+        if stale && let Err(e) = fs::remove_file(entry.path()) {
+            tracing::warn!("清理临时文件 {} 失败: {}", entry.path().display(), e);
         }
-        counter += 1;
     }
 }
 
@@ -34,23 +152,25 @@ pub fn extract_filename_without_extension(file_path: &str) -> String {
         .to_string()
 }
 
-/// 保存base64图像到文件系统
-pub fn save_base64_image(
-    base64_data: &str,
-    directory: &str,
-    filename: Option<&str>,
-) -> Result<String> {
-    // 确保目录存在
-    let dir_path = Path::new(directory);
-    if !dir_path.exists() {
-        fs::create_dir_all(dir_path)?;
-    }
+/// 原子地写入（或覆盖）指定路径的文件，写入中途失败不会留下不完整的文件
+pub fn write_file_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp_file = create_temp_file(directory)?;
+    temp_file.write_all(bytes)?;
+    temp_file.as_file().sync_all()?;
+    temp_file
+        .persist(path)
+        .map_err(|e| anyhow!(tr!("save.write_failed", error = e.error)))?;
+    Ok(())
+}
 
+/// 解析 `data:image/...;base64,...` 数据，返回图像类型（如 `png`）和解码后的字节
+pub fn decode_data_url(base64_data: &str) -> Result<(String, Vec<u8>)> {
     // 从base64数据中提取图像格式
     let captures = base64_data.split(";base64,").collect::<Vec<&str>>();
 
     if captures.len() != 2 {
-        return Err(anyhow!("无效的base64图像数据格式"));
+        return Err(anyhow!(tr!("image_input.invalid_base64_image")));
     }
 
     let mime_part = captures[0];
@@ -60,39 +180,78 @@ pub fn save_base64_image(
     let image_type = mime_part
         .split("data:image/")
         .nth(1)
-        .ok_or_else(|| anyhow!("无法解析图像类型"))?;
-
-    // 生成文件名（如果未提供）
-    let final_filename = if let Some(name) = filename {
-        if !name.contains('.') {
-            format!("{}.{}", name, image_type)
-        } else {
-            name.to_string()
+        .ok_or_else(|| anyhow!(tr!("image_input.unknown_image_type")))?;
+
+    let image_bytes = general_purpose::STANDARD
+        .decode(actual_base64_data)
+        .map_err(|e| anyhow!(tr!("image_input.base64_decode_failed", error = e)))?;
+
+    Ok((image_type.to_string(), image_bytes))
+}
+
+/// 根据 MIME 子类型（如 `jpeg`）确定保存时使用的文件扩展名
+pub(crate) fn extension_for_image_type(image_type: &str) -> String {
+    match image_type {
+        "jpeg" => "jpg".to_string(),
+        "svg+xml" => "svg".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// 模板指定的扩展名与图像实际格式一致时保留（如 `jpeg` 与 `jpg`），否则按实际格式确定
+fn matching_extension(requested: Option<&str>, image_type: &str) -> String {
+    let actual = extension_for_image_type(image_type);
+    match requested {
+        Some(requested) if extension_for_image_type(requested) == actual => requested.to_string(),
+        _ => actual,
+    }
+}
+
+/// 拆分目标文件名为 (主体, 扩展名)；未提供文件名时按时间戳生成，缺少扩展名时按图像类型补全
+fn split_filename(filename: Option<&str>, image_type: &str) -> (String, String) {
+    if let Some(name) = filename {
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), ext.to_string()),
+            _ => (name.to_string(), extension_for_image_type(image_type)),
         }
     } else {
         let timestamp = Utc::now().format("%Y%m%d_%H%M%S").to_string();
-        format!("image_{}.{}", timestamp, image_type)
-    };
+        (
+            format!("image_{}", timestamp),
+            extension_for_image_type(image_type),
+        )
+    }
+}
 
-    let filepath = dir_path.join(&final_filename);
+/// 保存图像字节到文件系统
+///
+/// 目标文件已存在时不会覆盖，而是自动追加递增序号
+pub fn save_image_bytes(
+    image_bytes: &[u8],
+    image_type: &str,
+    directory: &str,
+    filename: Option<&str>,
+) -> Result<String> {
+    // 确保目录存在
+    let dir_path = Path::new(directory);
+    if !dir_path.exists() {
+        fs::create_dir_all(dir_path)?;
+    }
 
-    // 将base64转换为字节并保存
-    let image_bytes = general_purpose::STANDARD
-        .decode(actual_base64_data)
-        .map_err(|e| anyhow!("base64解码失败: {}", e))?;
+    let (base_name, extension) = split_filename(filename, image_type);
 
-    let mut file = fs::File::create(&filepath)?;
-    file.write_all(&image_bytes)?;
+    let filepath = write_new_file_atomic(dir_path, &base_name, &extension, image_bytes)?;
 
     Ok(filepath.to_string_lossy().to_string())
 }
 
-/// 保存OpenRouter API的响应图像，支持递增文件名
+/// 保存OpenRouter API的响应图像，文件名由模板渲染，冲突时自动递增
 pub fn save_response_images(
     images: &[serde_json::Value],
     save_directory: Option<&str>,
-    base_filename: Option<&str>,
-    is_edit: bool,
+    naming: &NamingContext,
+    metadata: &GenerationMetadata,
+    options: SaveOptions,
 ) -> Vec<ImageInfo> {
     // 如果没有指定保存目录，直接返回不保存的结果
     let dir = match save_directory {
@@ -110,6 +269,7 @@ pub fn save_response_images(
                         url: image_url.to_string(),
                         saved_path: None,
                         debug_info: String::new(),
+                        ..Default::default()
                     }
                 })
                 .collect();
@@ -119,24 +279,25 @@ pub fn save_response_images(
     let dir_path = Path::new(dir);
 
     // 如果目录不存在，尝试创建
-    if !dir_path.exists() {
-        if let Err(e) = fs::create_dir_all(dir_path) {
-            return images
-                .iter()
-                .map(|img| {
-                    let image_url = img
-                        .get("image_url")
-                        .and_then(|url_obj| url_obj.get("url"))
-                        .and_then(|url| url.as_str())
-                        .unwrap_or("");
-                    ImageInfo {
-                        url: image_url.to_string(),
-                        saved_path: None,
-                        debug_info: format!("目录创建失败: {}", e),
-                    }
-                })
-                .collect();
-        }
+    if !dir_path.exists()
+        && let Err(e) = fs::create_dir_all(dir_path)
+    {
+        return images
+            .iter()
+            .map(|img| {
+                let image_url = img
+                    .get("image_url")
+                    .and_then(|url_obj| url_obj.get("url"))
+                    .and_then(|url| url.as_str())
+                    .unwrap_or("");
+                ImageInfo {
+                    url: image_url.to_string(),
+                    saved_path: None,
+                    debug_info: tr!("save.create_dir_failed", error = e),
+                    ..Default::default()
+                }
+            })
+            .collect();
     }
 
     // 再次检查目录是否有效
@@ -152,7 +313,8 @@ pub fn save_response_images(
                 ImageInfo {
                     url: image_url.to_string(),
                     saved_path: None,
-                    debug_info: "路径不是有效目录".to_string(),
+                    debug_info: tr!("save.not_a_directory"),
+                    ..Default::default()
                 }
             })
             .collect();
@@ -173,36 +335,92 @@ pub fn save_response_images(
                 url: image_url.to_string(),
                 saved_path: None,
                 debug_info: String::new(),
+                ..Default::default()
             };
 
             if image_url.starts_with("data:image/") {
-                // 生成递增的文件名
-                let filename = if let Some(base_name) = base_filename {
-                    if is_edit {
-                        let base_with_edited = format!("{}_edited", base_name);
-                        generate_incremental_filename(&base_with_edited, "png", dir)
-                    } else {
-                        generate_incremental_filename(base_name, "png", dir)
+                // 序号由保存时的原子写入负责递增，避免并发请求选中同一个文件名
+                let rendered = match naming.render(index + 1) {
+                    Ok(rendered) => rendered,
+                    Err(e) => {
+                        image_info.debug_info = tr!("save.render_name_failed", error = e);
+                        return image_info;
+                    }
+                };
+                let target_dir = dir_path.join(&rendered.subdirectory);
+
+                let (image_type, mut image_bytes) = match decode_data_url(image_url) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        image_info.debug_info = tr!("save.failed", error = e);
+                        return image_info;
                     }
+                };
+                let filename = format!(
+                    "{}.{}",
+                    rendered.stem,
+                    matching_extension(rendered.extension.as_deref(), &image_type)
+                );
+                if options.embed_metadata {
+                    match provenance::embed(&image_bytes, &metadata.provenance()) {
+                        Ok(embedded) => image_bytes = embedded,
+                        Err(e) => image_info.debug_info = tr!("save.embed_failed", error = e),
+                    }
+                }
+                image_info.mime_type = Some(format!("image/{}", image_type));
+                image_info.byte_size = Some(image_bytes.len());
+                if let Some((width, height)) = imaging::image_dimensions(&image_bytes) {
+                    image_info.width = Some(width);
+                    image_info.height = Some(height);
+                }
+                image_info.sha256 = Some(metadata::sha256_hex(&image_bytes));
+
+                let saved = if options.dedupe {
+                    let (base_name, extension) = split_filename(Some(&filename), &image_type);
+                    ContentStore::new(dir_path)
+                        .save(
+                            &image_bytes,
+                            image_info.sha256.as_deref().unwrap_or_default(),
+                            &target_dir,
+                            &base_name,
+                            &extension,
+                        )
+                        .map(|stored| {
+                            image_info.deduplicated = stored.deduplicated;
+                            stored.path.to_string_lossy().to_string()
+                        })
                 } else {
-                    let default_name = if is_edit {
-                        "edited_image"
-                    } else {
-                        "generated_image"
-                    };
-                    generate_incremental_filename(
-                        &format!("{}_{}", default_name, index + 1),
-                        "png",
-                        dir,
+                    save_image_bytes(
+                        &image_bytes,
+                        &image_type,
+                        &target_dir.to_string_lossy(),
+                        Some(&filename),
                     )
                 };
 
-                match save_base64_image(image_url, dir, Some(&filename)) {
+                match saved {
                     Ok(saved_path) => {
+                        if options.write_sidecar {
+                            let output = OutputDescriptor {
+                                file: &saved_path,
+                                index: index + 1,
+                                mime_type: image_info.mime_type.as_deref().unwrap_or_default(),
+                                byte_size: image_bytes.len(),
+                                sha256: image_info.sha256.as_deref().unwrap_or_default(),
+                                saved_at: Utc::now().to_rfc3339(),
+                            };
+                            match metadata::write_sidecar(Path::new(&saved_path), metadata, &output)
+                            {
+                                Ok(sidecar_path) => image_info.sidecar_path = Some(sidecar_path),
+                                Err(e) => {
+                                    image_info.debug_info = tr!("save.sidecar_failed", error = e);
+                                }
+                            }
+                        }
                         image_info.saved_path = Some(saved_path);
                     }
                     Err(e) => {
-                        image_info.debug_info = format!("保存失败: {}", e);
+                        image_info.debug_info = tr!("save.failed", error = e);
                     }
                 }
             }
@@ -212,17 +430,46 @@ pub fn save_response_images(
         .collect()
 }
 
-#[derive(Debug)]
+/// 保存图像时的可选处理
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SaveOptions {
+    /// 在图像旁写入 `<image>.json` sidecar 元数据
+    pub write_sidecar: bool,
+    /// 将生成来源写入图像文件自身的元数据（PNG tEXt/iTXt，JPEG/WebP XMP）
+    pub embed_metadata: bool,
+    /// 使用内容寻址存储，对相同内容的输出去重
+    pub dedupe: bool,
+}
+
+#[derive(Debug, Default)]
 pub struct ImageInfo {
     pub url: String,
     pub saved_path: Option<String>,
     pub debug_info: String,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<usize>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sha256: Option<String>,
+    pub sidecar_path: Option<String>,
+    /// 相同内容此前已保存过，本次输出复用了存储中的对象
+    pub deduplicated: bool,
 }
 
 /// 检测图片输入类型并返回标准化的内容格式
 pub fn detect_and_process_image_input(image_input: &str) -> Result<ImageContent> {
     // 检测是否为 base64 数据
     if image_input.starts_with("data:image/") {
+        // base64 每 4 个字符对应 3 个字节，不必解码即可估算大小
+        let size = image_input.len() as u64 / 4 * 3;
+        if size > MAX_INPUT_BYTES {
+            return Err(ToolError::input_too_large(tr!(
+                "image_input.data_url_too_large",
+                size = size,
+                max = MAX_INPUT_BYTES
+            ))
+            .into());
+        }
         return Ok(ImageContent {
             content_type: "base64".to_string(),
             data: image_input.to_string(),
@@ -242,53 +489,43 @@ pub fn detect_and_process_image_input(image_input: &str) -> Result<ImageContent>
     // 检测是否为本地文件路径
     let path = Path::new(image_input);
     if path.exists() && path.is_file() {
-        // 读取文件并转换为 base64
-        let file_bytes = fs::read(image_input)?;
-        let mime_type = detect_mime_type_from_path(path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
-        });
+        return read_image_file(path);
     }
 
     // 如果都不是，尝试作为相对路径处理
     let current_dir = std::env::current_dir()?;
     let full_path = current_dir.join(image_input);
     if full_path.exists() && full_path.is_file() {
-        let file_bytes = fs::read(&full_path)?;
-        let mime_type = detect_mime_type_from_path(&full_path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
-        });
+        return read_image_file(&full_path);
     }
 
     // 如果仍然找不到，尝试在 save_directory 中查找
     if let Ok(save_dir) = std::env::var("MCP_SAVE_DIRECTORY") {
         let save_path = Path::new(&save_dir).join(image_input);
         if save_path.exists() && save_path.is_file() {
-            let file_bytes = fs::read(&save_path)?;
-            let mime_type = detect_mime_type_from_path(&save_path)?;
-            let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-            let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-
-            return Ok(ImageContent {
-                content_type: "base64".to_string(),
-                data: data_url,
-                mime_type,
-            });
+            return read_image_file(&save_path);
         }
     }
 
-    Err(anyhow!("无法识别的图片输入格式: {}", image_input))
+    Err(anyhow!(tr!(
+        "image_input.unrecognized",
+        input = image_input
+    )))
+}
+
+/// 将本地图像参数解析为实际存在的文件路径
+///
+/// 先按绝对/相对路径查找，再到保存目录中查找
+pub fn resolve_local_image_path(image_input: &str, save_directory: &str) -> Option<PathBuf> {
+    let path = Path::new(image_input);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    let save_path = Path::new(save_directory).join(image_input);
+    if save_path.is_file() {
+        return Some(save_path);
+    }
+    None
 }
 
 /// 在指定的保存目录中查找图片文件
@@ -298,23 +535,42 @@ pub fn find_image_in_save_directory(
 ) -> Result<ImageContent> {
     let save_path = Path::new(save_directory).join(image_input);
     if save_path.exists() && save_path.is_file() {
-        let file_bytes = fs::read(&save_path)?;
-        let mime_type = detect_mime_type_from_path(&save_path)?;
-        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
-        let data_url = format!("data:{};base64,{}", mime_type, base64_data);
-
-        return Ok(ImageContent {
-            content_type: "base64".to_string(),
-            data: data_url,
-            mime_type,
-        });
+        return read_image_file(&save_path);
     }
 
-    Err(anyhow!(
-        "在保存目录 '{}' 中找不到图片文件: {}",
-        save_directory,
-        image_input
-    ))
+    Err(anyhow!(tr!(
+        "image_input.not_found_in_save_dir",
+        dir = save_directory,
+        input = image_input
+    )))
+}
+
+/// 读取本地图像文件并转换为 base64 data URL
+///
+/// 编码结果按路径、修改时间和大小缓存，重复引用同一个文件时不会再次读取和编码
+fn read_image_file(path: &Path) -> Result<ImageContent> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_INPUT_BYTES {
+        return Err(ToolError::input_too_large(tr!(
+            "image_input.file_too_large",
+            path = path.display(),
+            size = size,
+            max = MAX_INPUT_BYTES
+        ))
+        .into());
+    }
+    let mime_type = detect_mime_type_from_path(path)?;
+    let data_url = store::cached_data_url(path, || {
+        let file_bytes = fs::read(path)?;
+        let base64_data = general_purpose::STANDARD.encode(&file_bytes);
+        Ok(format!("data:{};base64,{}", mime_type, base64_data))
+    })?;
+
+    Ok(ImageContent {
+        content_type: "base64".to_string(),
+        data: data_url.to_string(),
+        mime_type,
+    })
 }
 
 /// 从 base64 数据中提取 MIME 类型
@@ -322,12 +578,12 @@ fn extract_mime_type_from_base64(base64_data: &str) -> Result<String> {
     let mime_part = base64_data
         .split(";base64,")
         .next()
-        .ok_or_else(|| anyhow!("无效的base64数据格式"))?;
+        .ok_or_else(|| anyhow!(tr!("image_input.invalid_base64")))?;
 
     if mime_part.starts_with("data:image/") {
         Ok(mime_part.to_string())
     } else {
-        Err(anyhow!("无效的图像MIME类型: {}", mime_part))
+        Err(anyhow!(tr!("image_input.invalid_mime", mime = mime_part)))
     }
 }
 
@@ -336,7 +592,7 @@ fn detect_mime_type_from_path(file_path: &Path) -> Result<String> {
     let extension = file_path
         .extension()
         .and_then(|ext| ext.to_str())
-        .ok_or_else(|| anyhow!("无法获取文件扩展名"))?;
+        .ok_or_else(|| anyhow!(tr!("image_input.no_extension")))?;
 
     let mime_type = match extension.to_lowercase().as_str() {
         "jpg" | "jpeg" => "image/jpeg",
@@ -355,8 +611,62 @@ fn detect_mime_type_from_path(file_path: &Path) -> Result<String> {
 /// 图片内容结构体
 #[derive(Debug)]
 pub struct ImageContent {
+    #[allow(dead_code)]
     pub content_type: String, // "url", "base64", "file"
-    pub data: String,         // 实际的数据内容
+    pub data: String, // 实际的数据内容
     #[allow(dead_code)]
     pub mime_type: String, // MIME 类型
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在目录中创建一个临时文件，修改时间设为 age 之前
+    fn temp_file(directory: &Path, name: &str, age: Duration) -> PathBuf {
+        fs::create_dir_all(directory).unwrap();
+        let path = directory.join(name);
+        let file = fs::File::create(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() - age)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn stale_temp_files_are_removed_recursively() {
+        let root = tempfile::tempdir().unwrap();
+        let old = TEMP_FILE_STALE_AFTER + Duration::from_secs(60);
+        let stale = [
+            temp_file(root.path(), ".nano-banana-a.tmp", old),
+            temp_file(&root.path().join("2025-01-31"), ".nano-banana-b.tmp", old),
+            temp_file(
+                &root.path().join(".store/sha256/ab"),
+                ".nano-banana-c.tmp",
+                old,
+            ),
+        ];
+        // 其他实例正在写入的临时文件和普通文件都要保留
+        let kept = [
+            temp_file(root.path(), ".nano-banana-d.tmp", Duration::from_secs(5)),
+            temp_file(root.path(), "image.png", old),
+            temp_file(&root.path().join("2025-01-31"), "nano-banana-e.tmp", old),
+        ];
+
+        cleanup_stale_temp_files(root.path());
+        for path in &stale {
+            assert!(!path.exists(), "{} 应被删除", path.display());
+        }
+        for path in &kept {
+            assert!(path.exists(), "{} 应被保留", path.display());
+        }
+    }
+
+    #[test]
+    fn template_extension_follows_actual_format() {
+        assert_eq!(matching_extension(None, "png"), "png");
+        assert_eq!(matching_extension(Some("png"), "png"), "png");
+        assert_eq!(matching_extension(Some("png"), "jpeg"), "jpg");
+        assert_eq!(matching_extension(Some("jpeg"), "jpeg"), "jpeg");
+        assert_eq!(matching_extension(Some("jpg"), "webp"), "webp");
+    }
+}
@@ -0,0 +1,479 @@
+use crate::{
+    errors::ToolError, i18n::tr, image_utils, naming, server::OpenRouterServer,
+    tools::display_image_input,
+};
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose};
+use image::{DynamicImage, ImageFormat, Rgba, codecs::jpeg::JpegEncoder};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{StatusCode, Url, header::LOCATION, redirect::Policy};
+use rmcp::{ErrorData as McpError, schemars};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 本地处理允许输出的最大像素数，避免一次请求占用过多内存
+pub const MAX_OUTPUT_PIXELS: u64 = 50_000_000;
+
+/// 下载远程输入图像的超时时间
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 下载远程输入图像时最多跟随的重定向次数
+const MAX_DOWNLOAD_REDIRECTS: usize = 10;
+
+/// JPEG 输出的默认质量
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// 本地处理结果的输出格式
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// PNG（默认）
+    #[default]
+    Png,
+    /// 不支持透明通道，透明区域会变为黑色
+    Jpeg,
+    /// 无损 WebP
+    Webp,
+}
+
+impl OutputFormat {
+    /// 对应的 MIME 子类型，同时用于确定文件扩展名
+    pub fn image_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    /// 输入格式可以原样输出时返回对应的输出格式
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            ImageFormat::WebP => Some(OutputFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// 解析颜色：支持 `#RGB`、`#RRGGBB`、`#RRGGBBAA`（`#` 可省略）以及 `transparent`
+pub fn parse_color(value: &str) -> Result<Rgba<u8>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if !hex.is_ascii() {
+        return Err(anyhow!(tr!("imaging.invalid_color", value = value)));
+    }
+    let channel = |i: usize, width: usize| -> Result<u8> {
+        let digits = &hex[i * width..(i + 1) * width];
+        let parsed = u8::from_str_radix(digits, 16)
+            .map_err(|_| anyhow!(tr!("imaging.invalid_color", value = value)))?;
+        Ok(if width == 1 { parsed * 17 } else { parsed })
+    };
+    match hex.len() {
+        3 => Ok(Rgba([channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 255])),
+        6 => Ok(Rgba([channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255])),
+        8 => Ok(Rgba([
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        ])),
+        _ => Err(anyhow!(tr!("imaging.invalid_color_format", value = value))),
+    }
+}
+
+/// 检查输出尺寸是否在允许范围内
+pub fn ensure_output_size(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(anyhow!(tr!(
+            "imaging.invalid_size",
+            width = width,
+            height = height
+        )));
+    }
+    if u64::from(width) * u64::from(height) > MAX_OUTPUT_PIXELS {
+        return Err(ToolError::input_too_large(tr!(
+            "imaging.size_too_large",
+            width = width,
+            height = height,
+            max = MAX_OUTPUT_PIXELS
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// 把图像编码为 PNG 字节
+pub fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    encode_image(image, OutputFormat::Png, DEFAULT_JPEG_QUALITY)
+}
+
+/// 只读取文件头获取图像尺寸，不解码像素
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// 把 PNG 字节包装为 data URL
+pub fn png_data_url(bytes: &[u8]) -> String {
+    format!(
+        "data:image/png;base64,{}",
+        general_purpose::STANDARD.encode(bytes)
+    )
+}
+
+/// 按指定格式编码图像；`quality` 只对 JPEG 生效
+pub fn encode_image(image: &DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let result = match format {
+        OutputFormat::Png => image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png),
+        OutputFormat::Webp => image
+            .to_rgba8()
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::WebP),
+        OutputFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut bytes, quality).encode_image(&image.to_rgb8())
+        }
+    };
+    result.map_err(|e| anyhow!(tr!("imaging.encode_failed", error = e)))?;
+    Ok(bytes)
+}
+
+impl OpenRouterServer {
+    /// 读取并解码图像输入（URL / base64 / 本地路径 / 保存目录中的文件名），用于本地处理
+    pub(crate) async fn load_image(&self, image_input: &str) -> Result<DynamicImage, McpError> {
+        let bytes = self.load_image_bytes(image_input).await?;
+        decode_image(bytes, image_input).await
+    }
+
+    /// 读取图像输入的原始字节
+    pub(crate) async fn load_image_bytes(&self, image_input: &str) -> Result<Vec<u8>, McpError> {
+        let resolved = self.resolve_image_input(image_input).await?;
+        let bytes = if resolved.starts_with("data:") {
+            image_utils::decode_data_url(&resolved)
+                .map(|(_, bytes)| bytes)
+                .map_err(|e| {
+                    McpError::invalid_params(
+                        tr!(
+                            "imaging.read_failed_with_error",
+                            input = display_image_input(image_input),
+                            error = e
+                        ),
+                        None,
+                    )
+                })?
+        } else if resolved.starts_with("http://") || resolved.starts_with("https://") {
+            self.download_image(&resolved).await?
+        } else {
+            return Err(ToolError::input_not_found(tr!(
+                "imaging.read_failed",
+                input = image_input
+            ))
+            .into());
+        };
+        Ok(bytes)
+    }
+
+    /// 把本地处理的结果保存到保存目录，文件已存在时自动追加序号
+    pub(crate) async fn save_local_image(
+        &self,
+        bytes: &[u8],
+        image_type: &str,
+        stem: &str,
+    ) -> Result<String, McpError> {
+        let save_directory = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        let stem = naming::sanitize_segment(stem);
+        let stem = if stem.is_empty() { "image" } else { &stem };
+        let filename = format!(
+            "{}.{}",
+            stem,
+            image_utils::extension_for_image_type(image_type)
+        );
+        image_utils::save_image_bytes(bytes, image_type, &save_directory, Some(&filename))
+            .map_err(|e| McpError::internal_error(tr!("imaging.save_failed", error = e), None))
+    }
+
+    /// 下载远程图像，超过 `MAX_INPUT_BYTES` 时中止
+    ///
+    /// 重定向逐跳处理，每一跳都检查目标地址，未开启 `--allow-private-downloads` 时
+    /// 拒绝回环、内网和链路本地地址
+    async fn download_image(&self, url: &str) -> Result<Vec<u8>, McpError> {
+        let download_error = |e: reqwest::Error| -> McpError {
+            let error =
+                ToolError::input_not_found(tr!("imaging.download_failed", url = url, error = e));
+            match e.status() {
+                Some(status) => error.with_status(status.as_u16()).into(),
+                None => error.into(),
+            }
+        };
+        let too_large = || -> McpError {
+            ToolError::input_too_large(tr!(
+                "imaging.download_too_large",
+                url = url,
+                max = image_utils::MAX_INPUT_BYTES
+            ))
+            .into()
+        };
+        let mut target = Url::parse(url).map_err(|e| -> McpError {
+            ToolError::input_not_found(tr!("imaging.download_failed", url = url, error = e)).into()
+        })?;
+        let mut redirects = 0;
+        let mut response = loop {
+            if !self.config.allow_private_downloads {
+                check_download_target(&target).await?;
+            }
+            let response = self
+                .download_client
+                .get(target.clone())
+                .send()
+                .await
+                .map_err(download_error)?;
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok());
+            match location {
+                Some(location) if is_followed_redirect(response.status()) => {
+                    redirects += 1;
+                    if redirects > MAX_DOWNLOAD_REDIRECTS {
+                        return Err(ToolError::input_not_found(tr!(
+                            "imaging.download_too_many_redirects",
+                            url = url,
+                            max = MAX_DOWNLOAD_REDIRECTS
+                        ))
+                        .into());
+                    }
+                    target = target.join(location).map_err(|e| -> McpError {
+                        ToolError::input_not_found(tr!(
+                            "imaging.download_failed",
+                            url = url,
+                            error = e
+                        ))
+                        .into()
+                    })?;
+                }
+                _ => break response.error_for_status().map_err(download_error)?,
+            }
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > image_utils::MAX_INPUT_BYTES)
+        {
+            return Err(too_large());
+        }
+        // 服务器可能没有声明或少报长度，边读边检查
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+            if (bytes.len() + chunk.len()) as u64 > image_utils::MAX_INPUT_BYTES {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+/// 下载远程图像使用的 HTTP 客户端
+///
+/// 不自动跟随重定向，由 `download_image` 逐跳检查目标；不允许内网地址时，
+/// 连接前还会过滤域名的解析结果，避免检查之后 DNS 记录被换成内网地址
+pub fn download_client(allow_private: bool) -> reqwest::Result<reqwest::Client> {
+    let builder = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .redirect(Policy::none());
+    if allow_private {
+        builder.build()
+    } else {
+        builder
+            .dns_resolver(Arc::new(PublicAddressResolver))
+            .build()
+    }
+}
+
+/// 只返回公网地址的 DNS 解析器
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(tr!("imaging.download_no_public_address", host = name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// 检查下载目标：主机为 IP 或解析出的任一地址不是公网地址时拒绝
+async fn check_download_target(url: &Url) -> Result<(), McpError> {
+    let Some(host) = url.host_str() else {
+        return Ok(());
+    };
+    // IPv6 主机在 URL 中带方括号
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<IpAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(0);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| -> McpError {
+                    ToolError::input_not_found(tr!("imaging.download_failed", url = url, error = e))
+                        .into()
+                })?
+                .map(|addr| addr.ip())
+                .collect()
+        }
+    };
+    match addrs.into_iter().find(|ip| !is_public_ip(*ip)) {
+        Some(ip) => Err(ToolError::invalid_params(tr!(
+            "imaging.download_private_address",
+            url = url,
+            ip = ip
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// 需要跟随 Location 的重定向状态码
+fn is_followed_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// 是否为公网地址；回环、内网、链路本地（含云元数据地址 169.254.169.254）、
+/// 运营商 NAT、组播和保留地址都不算
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // 100.64.0.0/10 运营商级 NAT
+        || (a == 100 && (64..128).contains(&b))
+        // 198.18.0.0/15 基准测试
+        || (a == 198 && (b == 18 || b == 19))
+        // 240.0.0.0/4 保留
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // IPv4 映射地址（::ffff:a.b.c.d）、IPv4 兼容地址（::a.b.c.d）和 NAT64（64:ff9b::/96）按内嵌的 IPv4 判断
+    if let Some(ipv4) = ip.to_ipv4() {
+        return !ip.is_loopback() && !ip.is_unspecified() && is_public_ipv4(ipv4);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., high, low] = segments;
+        return is_public_ipv4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+    }
+    !(ip.is_multicast()
+        // fc00::/7 唯一本地地址
+        || (segments[0] & 0xfe00) == 0xfc00
+        // fe80::/10 链路本地，fec0::/10 已废弃的站点本地
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] & 0xffc0) == 0xfec0
+        // 2001:db8::/32 文档示例
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// 在阻塞线程池中解码图像字节
+pub async fn decode_image(bytes: Vec<u8>, image_input: &str) -> Result<DynamicImage, McpError> {
+    let decoded = tokio::task::spawn_blocking(move || image::load_from_memory(&bytes))
+        .await
+        .map_err(|e| {
+            McpError::internal_error(tr!("imaging.decode_task_failed", error = e), None)
+        })?;
+    decoded.map_err(|e| {
+        McpError::invalid_params(
+            tr!(
+                "imaging.decode_failed",
+                input = display_image_input(image_input),
+                error = e
+            ),
+            None,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public_ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn private_targets_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!public(ip), "{} 不应视为公网地址", ip);
+        }
+    }
+
+    #[test]
+    fn public_targets_are_allowed() {
+        for ip in [
+            "8.8.8.8",
+            "1.1.1.1",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(public(ip), "{} 应视为公网地址", ip);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    errors::{self, ErrorDetails},
+    history::timestamp_now,
+    i18n::tr,
+    server::OpenRouterServer,
+    sqlite::Database,
+};
+use anyhow::{Result, anyhow};
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{OptionalExtension, Row, params};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+/// 默认的任务数据库文件（相对保存目录）
+pub const DEFAULT_JOBS_FILE: &str = ".nano-banana/jobs.sqlite3";
+
+/// 默认同时执行的后台任务数
+pub const DEFAULT_JOB_WORKERS: usize = 2;
+
+/// 服务器刷新自己任务心跳的间隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 心跳超过该时间没有刷新的任务视为所属进程已退出，可以由其他进程接管
+const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(120);
+
+/// 可以通过 submit_generation_job 异步执行的工具
+pub const JOB_TOOLS: &[&str] = &["generate_image", "edit_image", "batch_generate"];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id          TEXT PRIMARY KEY,
+    tool        TEXT NOT NULL,
+    arguments   TEXT NOT NULL,
+    status      TEXT NOT NULL,
+    result      TEXT,
+    error       TEXT,
+    created_at  TEXT NOT NULL,
+    started_at  TEXT,
+    finished_at TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_jobs_status ON jobs(status);
+";
+
+/// 按顺序执行的结构迁移，已执行的数量记录在 `PRAGMA user_version` 中
+const MIGRATIONS: &[&str] = &[
+    // 工具返回的结构化输出
+    "ALTER TABLE jobs ADD COLUMN structured_result TEXT;",
+    // 任务所属的服务器进程及其心跳，多个进程共用一个保存目录时只接管已退出进程的任务
+    "ALTER TABLE jobs ADD COLUMN owner TEXT; ALTER TABLE jobs ADD COLUMN heartbeat_at TEXT;",
+    // 任务失败时的错误类别等详情
+    "ALTER TABLE jobs ADD COLUMN error_details TEXT;",
+];
+
+pub const STATUS_QUEUED: &str = "queued";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_SUCCEEDED: &str = "succeeded";
+pub const STATUS_FAILED: &str = "failed";
+pub const STATUS_CANCELLED: &str = "cancelled";
+
+/// 一个后台生成任务
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub tool: String,
+    pub arguments: Value,
+    pub status: String,
+    /// 任务成功时工具返回的文本
+    pub result: Option<String>,
+    /// 任务成功时工具返回的结构化输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structured_result: Option<Value>,
+    pub error: Option<String>,
+    /// 任务失败时的错误详情，与同步调用返回的错误一致
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_details: Option<ErrorDetails>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+impl JobRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let arguments: String = row.get("arguments")?;
+        let structured_result: Option<String> = row.get("structured_result")?;
+        let error_details: Option<String> = row.get("error_details")?;
+        Ok(Self {
+            id: row.get("id")?,
+            tool: row.get("tool")?,
+            arguments: serde_json::from_str(&arguments).unwrap_or(Value::Null),
+            status: row.get("status")?,
+            result: row.get("result")?,
+            structured_result: structured_result.and_then(|s| serde_json::from_str(&s).ok()),
+            error: row.get("error")?,
+            error_details: error_details.and_then(|s| serde_json::from_str(&s).ok()),
+            created_at: row.get("created_at")?,
+            started_at: row.get("started_at")?,
+            finished_at: row.get("finished_at")?,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            STATUS_SUCCEEDED | STATUS_FAILED | STATUS_CANCELLED
+        )
+    }
+}
+
+/// 后台任务管理：持久化任务状态，并以有限数量的 worker 执行任务
+#[derive(Clone)]
+pub struct JobManager {
+    /// 基于 SQLite 的任务存储，服务器重启后已完成的结果仍然可以取回
+    store: Database,
+    workers: Arc<Semaphore>,
+    running: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    /// 本进程的标识（进程 ID 加实例 UUID），写入本进程提交或接管的任务
+    owner: String,
+}
+
+impl JobManager {
+    pub fn open(path: &Path, workers: usize) -> Result<Self> {
+        Ok(Self {
+            store: Database::open(path, SCHEMA, MIGRATIONS)?,
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            running: Arc::new(Mutex::new(HashMap::new())),
+            owner: format!("{}-{}", std::process::id(), uuid::Uuid::new_v4().simple()),
+        })
+    }
+
+    async fn insert(&self, tool: &str, arguments: &Value) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let tool = tool.to_string();
+        let arguments = arguments.to_string();
+        let owner = self.owner.clone();
+        let job_id = id.clone();
+        self.store
+            .with_conn(move |conn| {
+                let now = timestamp_now();
+                conn.execute(
+                    "INSERT INTO jobs (id, tool, arguments, status, created_at, owner, heartbeat_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?5)",
+                    params![job_id, tool, arguments, STATUS_QUEUED, now, owner],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(id)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<JobRecord>> {
+        let id = id.to_string();
+        self.store
+            .with_conn(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT * FROM jobs WHERE id = ?1",
+                        [id],
+                        JobRecord::from_row,
+                    )
+                    .optional()?)
+            })
+            .await
+    }
+
+    /// 原子地认领本进程的排队任务并标记为运行中；任务已被取消或被其他进程接管时返回 false
+    async fn mark_running(&self, id: &str) -> Result<bool> {
+        let id = id.to_string();
+        let owner = self.owner.clone();
+        self.store
+            .with_conn(move |conn| {
+                let claimed = conn
+                    .query_row(
+                        "UPDATE jobs SET status = ?1, started_at = ?2, heartbeat_at = ?2
+                         WHERE id = ?3 AND status = ?4 AND owner = ?5 RETURNING id",
+                        params![STATUS_RUNNING, timestamp_now(), id, STATUS_QUEUED, owner],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()?;
+                Ok(claimed.is_some())
+            })
+            .await
+    }
+
+    /// 刷新本进程所有未完成任务的心跳
+    async fn heartbeat(&self) -> Result<()> {
+        let owner = self.owner.clone();
+        self.store
+            .with_conn(move |conn| {
+                conn.execute(
+                    "UPDATE jobs SET heartbeat_at = ?1 WHERE owner = ?2 AND status IN (?3, ?4)",
+                    params![timestamp_now(), owner, STATUS_QUEUED, STATUS_RUNNING],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn finish(
+        &self,
+        id: &str,
+        status: &'static str,
+        result: Option<(String, Option<Value>)>,
+        error: Option<ErrorDetails>,
+    ) -> Result<()> {
+        let id = id.to_string();
+        let (result, structured_result) = match result {
+            Some((text, structured)) => (Some(text), structured.map(|value| value.to_string())),
+            None => (None, None),
+        };
+        let (error, error_details) = match error {
+            Some(details) => (
+                Some(details.message.clone()),
+                serde_json::to_string(&details).ok(),
+            ),
+            None => (None, None),
+        };
+        self.store
+            .with_conn(move |conn| {
+                conn.execute(
+                    "UPDATE jobs SET status = ?1, result = ?2, structured_result = ?3, error = ?4,
+                     error_details = ?5, finished_at = ?6 WHERE id = ?7 AND status = ?8",
+                    params![
+                        status,
+                        result,
+                        structured_result,
+                        error,
+                        error_details,
+                        timestamp_now(),
+                        id,
+                        STATUS_RUNNING
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// 取消任务：排队中的任务直接标记为已取消，运行中的任务发出取消信号
+    pub async fn cancel(&self, id: &str) -> Result<JobRecord> {
+        let job_id = id.to_string();
+        self.store
+            .with_conn(move |conn| {
+                conn.execute(
+                    "UPDATE jobs SET status = ?1, finished_at = ?2 WHERE id = ?3 AND status = ?4",
+                    params![STATUS_CANCELLED, timestamp_now(), job_id, STATUS_QUEUED],
+                )?;
+                Ok(())
+            })
+            .await?;
+        if let Ok(running) = self.running.lock()
+            && let Some(token) = running.get(id)
+        {
+            token.cancel();
+        }
+        self.get(id)
+            .await?
+            .ok_or_else(|| anyhow!(tr!("jobs.not_found", id = id)))
+    }
+
+    /// 接管所属进程已退出（心跳过期）的任务：运行中的任务标记为失败，
+    /// 排队中的任务原子地改为本进程所有，返回接管的排队任务
+    async fn recover(&self) -> Result<Vec<String>> {
+        let owner = self.owner.clone();
+        self.store
+            .with_conn(move |conn| {
+                let now = timestamp_now();
+                let stale_before = (Utc::now()
+                    - chrono::Duration::from_std(HEARTBEAT_STALE_AFTER)?)
+                .to_rfc3339_opts(SecondsFormat::Millis, true);
+                conn.execute(
+                    "UPDATE jobs SET status = ?1, error = ?2, finished_at = ?3
+                     WHERE status = ?4 AND (heartbeat_at IS NULL OR heartbeat_at < ?5)",
+                    params![
+                        STATUS_FAILED,
+                        tr!("jobs.interrupted_by_restart"),
+                        now,
+                        STATUS_RUNNING,
+                        stale_before
+                    ],
+                )?;
+                let mut stmt = conn.prepare(
+                    "UPDATE jobs SET owner = ?1, heartbeat_at = ?2
+                     WHERE status = ?3 AND (heartbeat_at IS NULL OR heartbeat_at < ?4)
+                     RETURNING id, created_at",
+                )?;
+                let mut claimed = stmt
+                    .query_map(params![owner, now, STATUS_QUEUED, stale_before], |row| {
+                        Ok((row.get::<_, String>(1)?, row.get::<_, String>(0)?))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                claimed.sort();
+                Ok(claimed.into_iter().map(|(_, id)| id).collect())
+            })
+            .await
+    }
+}
+
+impl OpenRouterServer {
+    /// 提交一个后台任务并立即返回任务 ID
+    pub(crate) async fn submit_job(
+        &self,
+        jobs: &JobManager,
+        tool: &str,
+        arguments: &Value,
+    ) -> Result<String> {
+        let id = jobs.insert(tool, arguments).await?;
+        self.spawn_job(jobs.clone(), id.clone());
+        Ok(id)
+    }
+
+    /// 定期刷新本进程任务的心跳，并接管已退出进程遗留的任务（启动时立即执行一次）
+    pub(crate) fn resume_jobs(&self) {
+        let Some(jobs) = self.jobs.clone() else {
+            return;
+        };
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = jobs.heartbeat().await {
+                    tracing::warn!("刷新后台任务心跳失败: {}", e);
+                }
+                match jobs.recover().await {
+                    Ok(ids) => {
+                        if !ids.is_empty() {
+                            tracing::info!("接管 {} 个排队中的后台任务", ids.len());
+                        }
+                        for id in ids {
+                            server.spawn_job(jobs.clone(), id);
+                        }
+                    }
+                    Err(e) => tracing::warn!("恢复后台任务失败: {}", e),
+                }
+            }
+        });
+    }
+
+    fn spawn_job(&self, jobs: JobManager, id: String) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.run_job(&jobs, &id).await {
+                tracing::warn!("后台任务 {} 执行失败: {}", id, e);
+            }
+        });
+    }
+
+    async fn run_job(&self, jobs: &JobManager, id: &str) -> Result<()> {
+        let _permit = jobs.workers.clone().acquire_owned().await?;
+        // 先登记取消令牌再标记为运行中：取消请求要么在排队状态下生效，要么能找到令牌
+        let cancellation = CancellationToken::new();
+        if let Ok(mut running) = jobs.running.lock() {
+            running.insert(id.to_string(), cancellation.clone());
+        }
+        let result = self.execute_job(jobs, id, cancellation).await;
+        if let Ok(mut running) = jobs.running.lock() {
+            running.remove(id);
+        }
+        result
+    }
+
+    async fn execute_job(
+        &self,
+        jobs: &JobManager,
+        id: &str,
+        cancellation: CancellationToken,
+    ) -> Result<()> {
+        if !jobs.mark_running(id).await? {
+            // 排队期间已被取消
+            return Ok(());
+        }
+        let record = jobs
+            .get(id)
+            .await?
+            .ok_or_else(|| anyhow!(tr!("jobs.not_found", id = id)))?;
+
+        let result = tokio::select! {
+            _ = cancellation.cancelled() => None,
+            result = self.call_tool_for_job(&record.tool, record.arguments.clone(), cancellation.clone()) => Some(result),
+        };
+
+        match result {
+            Some(Ok(output)) => jobs.finish(id, STATUS_SUCCEEDED, Some(output), None).await,
+            Some(Err(e)) => {
+                jobs.finish(id, STATUS_FAILED, None, Some(errors::details_of(&e)))
+                    .await
+            }
+            None => jobs.finish(id, STATUS_CANCELLED, None, None).await,
+        }
+    }
+}
@@ -1,9 +1,36 @@
+mod background;
+mod batch;
 mod cli;
+mod compose;
 mod config;
+mod describe;
+mod enhance;
+mod errors;
+mod extend;
+mod generation;
+mod history;
+mod i18n;
 mod image_utils;
+mod imaging;
+mod jobs;
+mod metadata;
+mod naming;
+mod output;
+mod pricing;
+mod prompts;
+mod provenance;
+mod roles;
+mod safety;
 mod server;
+mod session;
+mod sqlite;
+mod store;
 mod tools;
+mod transform;
 mod transport;
+mod upscale;
+mod usage;
+mod variations;
 
 use anyhow::Result;
 
@@ -22,6 +49,7 @@ async fn main() -> Result<()> {
     let save_directory = args.save_directory.map(|p| p.to_string_lossy().to_string());
 
     let handler = server::OpenRouterServer::new(save_directory)?;
+    handler.resume_jobs();
     match args.transport {
         cli::TransportType::Stdio => transport::run_stdio(handler).await?,
         cli::TransportType::Sse => transport::run_sse(handler).await?,
@@ -0,0 +1,188 @@
+use crate::{provenance::Provenance, roles::ImageRole};
+use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// 计算字节内容的 SHA-256 十六进制摘要
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// 一次生成/编辑调用的上下文信息，用于写入 sidecar 元数据
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationMetadata {
+    pub generation_id: Option<String>,
+    pub tool: String,
+    pub prompt: String,
+    pub model: String,
+    pub provider: String,
+    pub parameters: Value,
+    pub inputs: Vec<InputDescriptor>,
+    pub input_hash: String,
+    pub usage: Option<Value>,
+    pub response_text: String,
+    pub requested_at: String,
+    pub completed_at: String,
+}
+
+/// 输入图像的描述（不包含完整的 base64 数据）
+#[derive(Debug, Clone, Serialize)]
+pub struct InputDescriptor {
+    pub source: String,
+    pub kind: String,
+    pub mime_type: Option<String>,
+    pub byte_size: Option<usize>,
+    pub sha256: Option<String>,
+    /// 图像在请求中的角色，如 `subject`、`style_reference`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+impl InputDescriptor {
+    /// 根据原始输入和解析后的图像数据构造描述
+    ///
+    /// `resolved` 为 data URL 时会计算解码后内容的哈希；URL 输入只记录地址
+    pub fn new(source: &str, resolved: &str) -> Self {
+        let source_label = if source.starts_with("data:") {
+            format!(
+                "{}…",
+                &source[..source.find(',').unwrap_or(source.len()).min(64)]
+            )
+        } else {
+            source.to_string()
+        };
+
+        if let Some((mime_part, payload)) = resolved.split_once(";base64,")
+            && let Ok(bytes) = general_purpose::STANDARD.decode(payload)
+        {
+            return Self {
+                source: source_label,
+                kind: if source.starts_with("data:") {
+                    "base64".to_string()
+                } else {
+                    "file".to_string()
+                },
+                mime_type: mime_part.strip_prefix("data:").map(str::to_string),
+                byte_size: Some(bytes.len()),
+                sha256: Some(sha256_hex(&bytes)),
+                role: None,
+            };
+        }
+
+        Self {
+            source: source_label,
+            kind: "url".to_string(),
+            mime_type: None,
+            byte_size: None,
+            sha256: None,
+            role: None,
+        }
+    }
+
+    pub fn with_role(mut self, role: Option<ImageRole>) -> Self {
+        self.role = role.map(|role| role.as_str().to_string());
+        self
+    }
+}
+
+impl GenerationMetadata {
+    pub fn new(
+        tool: &str,
+        prompt: &str,
+        model: &str,
+        provider: &str,
+        parameters: Value,
+        inputs: Vec<InputDescriptor>,
+    ) -> Self {
+        let input_hash = Self::compute_input_hash(prompt, model, &parameters, &inputs);
+        let now = Utc::now().to_rfc3339();
+        Self {
+            generation_id: None,
+            tool: tool.to_string(),
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+            provider: provider.to_string(),
+            parameters,
+            inputs,
+            input_hash,
+            usage: None,
+            response_text: String::new(),
+            requested_at: now.clone(),
+            completed_at: now,
+        }
+    }
+
+    /// 需要嵌入到图像文件中的来源信息
+    pub fn provenance(&self) -> Provenance {
+        Provenance {
+            prompt: self.prompt.clone(),
+            model: self.model.clone(),
+            tool: self.tool.clone(),
+            created_at: self.completed_at.clone(),
+        }
+    }
+
+    /// 记录响应结果和完成时间
+    pub fn complete(&mut self, response_text: &str, usage: Option<&Value>) {
+        self.response_text = response_text.to_string();
+        self.usage = usage.cloned();
+        self.completed_at = Utc::now().to_rfc3339();
+    }
+
+    /// 对决定生成结果的全部输入求哈希，相同哈希表示相同的请求
+    fn compute_input_hash(
+        prompt: &str,
+        model: &str,
+        parameters: &Value,
+        inputs: &[InputDescriptor],
+    ) -> String {
+        let input_keys: Vec<&str> = inputs
+            .iter()
+            .map(|i| i.sha256.as_deref().unwrap_or(&i.source))
+            .collect();
+        let canonical = json!({
+            "prompt": prompt,
+            "model": model,
+            "parameters": parameters,
+            "inputs": input_keys,
+        });
+        sha256_hex(canonical.to_string().as_bytes())
+    }
+}
+
+/// 保存后的单张输出图像信息
+#[derive(Debug, Serialize)]
+pub struct OutputDescriptor<'a> {
+    pub file: &'a str,
+    pub index: usize,
+    pub mime_type: &'a str,
+    pub byte_size: usize,
+    pub sha256: &'a str,
+    pub saved_at: String,
+}
+
+/// 在图像旁写入 `<image>.json` sidecar 文件，返回 sidecar 路径
+pub fn write_sidecar(
+    image_path: &Path,
+    metadata: &GenerationMetadata,
+    output: &OutputDescriptor,
+) -> Result<String> {
+    let mut sidecar_name = image_path.file_name().unwrap_or_default().to_os_string();
+    sidecar_name.push(".json");
+    let sidecar_path = image_path.with_file_name(sidecar_name);
+
+    let document = json!({
+        "generator": "nano-banana-mcp",
+        "generator_version": env!("CARGO_PKG_VERSION"),
+        "generation": metadata,
+        "output": output,
+    });
+    let bytes = serde_json::to_vec_pretty(&document)?;
+    crate::image_utils::write_file_atomic(&sidecar_path, &bytes)?;
+
+    Ok(sidecar_path.to_string_lossy().to_string())
+}
@@ -0,0 +1,375 @@
+use crate::i18n::tr;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local};
+use std::path::{Component, Path, PathBuf};
+
+/// 生成图像的默认文件名模板，与早期版本的 `generated_image_1`、`generated_image_2` 一致，
+/// 扩展名按图像实际格式补全
+pub const DEFAULT_GENERATE_TEMPLATE: &str = "generated_image_{index}";
+/// 编辑图像且能确定源文件名时的默认模板
+pub const DEFAULT_EDIT_TEMPLATE: &str = "{source_stem}_edited";
+/// 编辑图像但源图像来自 URL / base64 时的默认模板
+pub const DEFAULT_EDIT_FALLBACK_TEMPLATE: &str = "edited_image_{index}";
+
+/// 带有工具专属后缀（如 `_keyed`）且未配置文件名模板时使用的模板
+pub const DEFAULT_SUFFIXED_TEMPLATE: &str = "{source_stem}";
+
+/// `{prompt_slug}` 的最大字符数
+const PROMPT_SLUG_MAX_CHARS: usize = 48;
+/// 单个路径片段的最大字节数，留出递增序号和扩展名的余量
+const SEGMENT_MAX_BYTES: usize = 120;
+
+/// 模板中可以作为扩展名识别的图像格式，其他 `.xxx` 后缀视为文件名的一部分
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff", "svg",
+];
+
+/// Windows 上的保留设备名，不能作为文件名使用
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 渲染文件名模板所需的上下文
+#[derive(Debug, Clone)]
+pub struct NamingContext {
+    pub template: String,
+    pub model: String,
+    pub prompt: String,
+    pub seed: Option<u64>,
+    pub source_stem: Option<String>,
+    /// 追加在渲染结果的文件名主体之后的后缀
+    pub suffix: Option<String>,
+    pub timestamp: DateTime<Local>,
+}
+
+/// 模板渲染结果：相对保存目录的子目录、文件名主体和扩展名
+#[derive(Debug, PartialEq, Eq)]
+pub struct RenderedName {
+    pub subdirectory: PathBuf,
+    pub stem: String,
+    pub extension: Option<String>,
+}
+
+impl NamingContext {
+    pub fn new(template: impl Into<String>, model: &str, prompt: &str) -> Self {
+        Self {
+            template: template.into(),
+            model: model.to_string(),
+            prompt: prompt.to_string(),
+            seed: None,
+            source_stem: None,
+            suffix: None,
+            timestamp: Local::now(),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_source_stem(mut self, source_stem: Option<String>) -> Self {
+        self.source_stem = source_stem;
+        self
+    }
+
+    pub fn with_suffix(mut self, suffix: Option<String>) -> Self {
+        self.suffix = suffix;
+        self
+    }
+
+    /// 渲染第 `index` 张图像（从 1 开始）的文件名
+    ///
+    /// 支持的占位符: `{date}` `{time}` `{model}` `{prompt_slug}` `{seed}`
+    /// `{index}` `{uuid}` `{source_stem}`。模板中的 `/` 表示子目录。
+    pub fn render(&self, index: usize) -> Result<RenderedName> {
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let end = after.find('}').ok_or_else(|| {
+                anyhow!(tr!("naming.unclosed_placeholder", template = self.template))
+            })?;
+            let value = self.placeholder_value(&after[..end], index)?;
+            // 占位符的值不允许引入额外的目录层级
+            rendered.push_str(&value.replace(['/', '\\'], "-"));
+            rest = &after[end + 1..];
+        }
+        rendered.push_str(rest);
+
+        let mut name = split_rendered_path(&rendered)?;
+        if let Some(suffix) = &self.suffix {
+            name.stem = sanitize_segment(&format!("{}{}", name.stem, suffix));
+        }
+        Ok(name)
+    }
+
+    fn placeholder_value(&self, name: &str, index: usize) -> Result<String> {
+        let value = match name {
+            "date" => self.timestamp.format("%Y-%m-%d").to_string(),
+            "time" => self.timestamp.format("%H%M%S").to_string(),
+            "model" => self
+                .model
+                .rsplit('/')
+                .next()
+                .unwrap_or(&self.model)
+                .to_string(),
+            "prompt_slug" => slugify(&self.prompt, PROMPT_SLUG_MAX_CHARS),
+            "seed" => self
+                .seed
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "noseed".to_string()),
+            "index" => index.to_string(),
+            "uuid" => uuid::Uuid::new_v4().simple().to_string(),
+            "source_stem" => self
+                .source_stem
+                .clone()
+                .unwrap_or_else(|| "image".to_string()),
+            other => return Err(anyhow!(tr!("naming.unknown_placeholder", name = other))),
+        };
+        Ok(value)
+    }
+}
+
+/// 校验模板语法，在启动时尽早暴露配置错误
+pub fn validate_template(template: &str) -> Result<()> {
+    NamingContext::new(template, "model", "prompt").render(1)?;
+    Ok(())
+}
+
+/// 将渲染后的相对路径拆分为子目录和文件名，并逐段清理
+fn split_rendered_path(rendered: &str) -> Result<RenderedName> {
+    let normalized = rendered.replace('\\', "/");
+    let path = Path::new(&normalized);
+
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(segment) => {
+                let segment = sanitize_segment(&segment.to_string_lossy());
+                if !segment.is_empty() {
+                    segments.push(segment);
+                }
+            }
+            Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(tr!("naming.outside_save_dir", path = rendered)));
+            }
+        }
+    }
+
+    let file_name = segments
+        .pop()
+        .ok_or_else(|| anyhow!(tr!("naming.empty_name", path = rendered)))?;
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty()
+                && IMAGE_EXTENSIONS
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(ext)) =>
+        {
+            (stem.to_string(), Some(ext.to_lowercase()))
+        }
+        _ => (file_name, None),
+    };
+
+    Ok(RenderedName {
+        subdirectory: segments.iter().collect(),
+        stem,
+        extension,
+    })
+}
+
+/// 清理单个路径片段，使其在 Windows / macOS / Linux 上都是合法文件名
+pub fn sanitize_segment(segment: &str) -> String {
+    let mut cleaned: String = segment
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows 不允许以空格或点结尾，同时避免生成隐藏文件
+    cleaned = cleaned
+        .trim_matches(|c: char| c == ' ' || c == '.')
+        .to_string();
+
+    if cleaned.len() > SEGMENT_MAX_BYTES {
+        let mut cut = SEGMENT_MAX_BYTES;
+        while !cleaned.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        cleaned.truncate(cut);
+    }
+
+    let base = cleaned.split('.').next().unwrap_or("");
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(base))
+    {
+        cleaned.insert(0, '_');
+    }
+
+    cleaned
+}
+
+/// 将提示词转换为适合作为文件名的短标识
+///
+/// 保留字母数字（包括中文等非 ASCII 字符），其余字符折叠为单个 `-`
+pub fn slugify(text: &str, max_chars: usize) -> String {
+    let mut slug = String::new();
+    let mut pending_dash = false;
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if pending_dash && !slug.is_empty() {
+                slug.push('-');
+            }
+            pending_dash = false;
+            slug.extend(c.to_lowercase());
+            if slug.chars().count() >= max_chars {
+                break;
+            }
+        } else {
+            pending_dash = true;
+        }
+    }
+
+    if slug.is_empty() {
+        "untitled".to_string()
+    } else {
+        slug
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context(template: &str, prompt: &str) -> NamingContext {
+        let mut context = NamingContext::new(template, "google/gemini-2.5-flash-image", prompt);
+        context.timestamp = Local.with_ymd_and_hms(2025, 3, 4, 5, 6, 7).unwrap();
+        context
+    }
+
+    #[test]
+    fn default_templates_keep_legacy_names() {
+        let generate = context(DEFAULT_GENERATE_TEMPLATE, "a cat");
+        assert_eq!(generate.render(1).unwrap().stem, "generated_image_1");
+        assert_eq!(generate.render(2).unwrap().stem, "generated_image_2");
+
+        let fallback = context(DEFAULT_EDIT_FALLBACK_TEMPLATE, "make it blue");
+        assert_eq!(fallback.render(1).unwrap().stem, "edited_image_1");
+
+        let edit = context(DEFAULT_EDIT_TEMPLATE, "make it blue")
+            .with_source_stem(Some("photo".to_string()));
+        let rendered = edit.render(1).unwrap();
+        assert_eq!(rendered.stem, "photo_edited");
+        assert_eq!(rendered.extension, None);
+        assert_eq!(rendered.subdirectory, PathBuf::new());
+    }
+
+    #[test]
+    fn tool_suffix_follows_the_rendered_template() {
+        let default = context(DEFAULT_SUFFIXED_TEMPLATE, "")
+            .with_source_stem(Some("photo".to_string()))
+            .with_suffix(Some("_var_2".to_string()));
+        assert_eq!(default.render(1).unwrap().stem, "photo_var_2");
+
+        let fallback =
+            context(DEFAULT_SUFFIXED_TEMPLATE, "").with_suffix(Some("_keyed".to_string()));
+        assert_eq!(fallback.render(1).unwrap().stem, "image_keyed");
+
+        let configured = context("{date}/{prompt_slug}.png", "a red fox")
+            .with_suffix(Some("_styled".to_string()));
+        let rendered = configured.render(1).unwrap();
+        assert_eq!(rendered.subdirectory, PathBuf::from("2025-03-04"));
+        assert_eq!(rendered.stem, "a-red-fox_styled");
+        assert_eq!(rendered.extension.as_deref(), Some("png"));
+    }
+
+    #[test]
+    fn only_image_extensions_are_split_off() {
+        let rendered = context("{source_stem}_edited", "")
+            .with_source_stem(Some("holiday.2024".to_string()))
+            .render(1)
+            .unwrap();
+        assert_eq!(rendered.stem, "holiday.2024_edited");
+        assert_eq!(rendered.extension, None);
+
+        let rendered = context("cover.JPG", "").render(1).unwrap();
+        assert_eq!(rendered.stem, "cover");
+        assert_eq!(rendered.extension.as_deref(), Some("jpg"));
+    }
+
+    #[test]
+    fn windows_reserved_names_are_prefixed() {
+        assert_eq!(sanitize_segment("CON"), "_CON");
+        assert_eq!(sanitize_segment("nul.png"), "_nul.png");
+        assert_eq!(sanitize_segment("Com1.tar.gz"), "_Com1.tar.gz");
+        assert_eq!(sanitize_segment("console"), "console");
+
+        let rendered = context("aux.png", "").render(1).unwrap();
+        assert_eq!(rendered.stem, "_aux");
+        assert_eq!(rendered.extension.as_deref(), Some("png"));
+    }
+
+    #[test]
+    fn invalid_characters_and_trailing_dots_are_cleaned() {
+        assert_eq!(sanitize_segment("a<b>c:d|e?f*g\"h"), "a_b_c_d_e_f_g_h");
+        assert_eq!(sanitize_segment(" .hidden. "), "hidden");
+    }
+
+    #[test]
+    fn parent_directories_are_rejected() {
+        assert!(context("../escape", "").render(1).is_err());
+        assert!(context("images/../../escape", "").render(1).is_err());
+        assert!(context("/etc/passwd", "").render(1).is_err());
+
+        // 占位符的值不能引入目录层级
+        let rendered = context("{source_stem}_edited", "")
+            .with_source_stem(Some("../secret".to_string()))
+            .render(1)
+            .unwrap();
+        assert_eq!(rendered.subdirectory, PathBuf::new());
+        assert_eq!(rendered.stem, "-secret_edited");
+    }
+
+    #[test]
+    fn long_segments_are_truncated_at_char_boundary() {
+        // 每个字符 3 个字节，120 字节的上限落在字符中间时向前回退
+        let segment = format!("a{}", "猫".repeat(60));
+        let cleaned = sanitize_segment(&segment);
+        assert!(cleaned.len() <= SEGMENT_MAX_BYTES);
+        assert_eq!(cleaned.len(), 118);
+        assert_eq!(cleaned, format!("a{}", "猫".repeat(39)));
+
+        let exact = "x".repeat(SEGMENT_MAX_BYTES + 10);
+        assert_eq!(sanitize_segment(&exact).len(), SEGMENT_MAX_BYTES);
+    }
+
+    #[test]
+    fn date_and_prompt_slug_template() {
+        let context = context(
+            "{date}/{prompt_slug}_{index}.png",
+            "A Cat, on the Moon! 月球上的猫",
+        );
+        let rendered = context.render(3).unwrap();
+        assert_eq!(rendered.subdirectory, PathBuf::from("2025-03-04"));
+        assert_eq!(rendered.stem, "a-cat-on-the-moon-月球上的猫_3");
+        assert_eq!(rendered.extension.as_deref(), Some("png"));
+    }
+
+    #[test]
+    fn unknown_and_unclosed_placeholders_are_errors() {
+        assert!(validate_template("{nope}").is_err());
+        assert!(validate_template("{date").is_err());
+        assert!(validate_template("{model}_{seed}_{uuid}").is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    background::Cutout, errors::ErrorDetails, generation::ImageOutcome, i18n::tr,
+    image_utils::ImageInfo, pricing::CostSource, safety::FinishStatus,
+};
+use rmcp::{
+    model::{CallToolResult, Content, JsonObject},
+    schemars,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// 工具的结构化输出，作为 `structuredContent` 与给人阅读的文本一起返回
+///
+/// 所有工具共用同一个输出结构，不适用的字段省略；工具特有的信息放在 `data` 中
+#[derive(Debug, Default, Serialize, schemars::JsonSchema)]
+pub struct ToolOutput {
+    /// 工具名称
+    pub tool: String,
+    /// 生成 ID（写入生成历史的记录 ID）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// 由 regenerate 重放时，被重放的原始生成 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    /// 上游服务提供方，如 `openrouter`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// 模型返回的文本（已移除内嵌的 base64 图像）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// 输出图像
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<OutputImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageOutput>,
+    /// 上游响应的结束状态
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish: Option<FinishStatus>,
+    /// 批量生成和变体中每一条的结果
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<ItemOutput>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// 工具特有的其他信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    /// 工具执行失败（`isError` 为 true）时的错误信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorDetails>,
+}
+
+/// 一张输出图像
+#[derive(Debug, Default, Serialize, schemars::JsonSchema)]
+pub struct OutputImage {
+    /// 保存路径；未能保存时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// 模型直接返回远程 URL 时的地址（base64 图像不重复返回）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// sidecar 元数据文件路径
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar_path: Option<String>,
+    /// 相同内容此前已保存过，本次复用了存储中的对象
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deduplicated: bool,
+    /// 派生图像的类型，如 `transparent`（本地抠图结果）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl OutputImage {
+    /// 本地处理后保存的图像
+    pub fn local(
+        path: impl Into<String>,
+        image_type: &str,
+        byte_size: usize,
+        size: (u32, u32),
+    ) -> Self {
+        Self {
+            path: Some(path.into()),
+            mime_type: Some(format!("image/{}", image_type)),
+            width: Some(size.0),
+            height: Some(size.1),
+            byte_size: Some(byte_size),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&ImageInfo> for OutputImage {
+    fn from(info: &ImageInfo) -> Self {
+        Self {
+            path: info.saved_path.clone(),
+            url: (!info.url.starts_with("data:")).then(|| info.url.clone()),
+            mime_type: info.mime_type.clone(),
+            width: info.width,
+            height: info.height,
+            byte_size: info.byte_size,
+            sha256: info.sha256.clone(),
+            sidecar_path: info.sidecar_path.clone(),
+            deduplicated: info.deduplicated,
+            variant: None,
+        }
+    }
+}
+
+/// token 使用量和费用
+#[derive(Debug, Default, Serialize, schemars::JsonSchema)]
+pub struct UsageOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_tokens: Option<u64>,
+    /// 费用（美元）：上游返回的 `usage.cost`，或按模型单价估算
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// 费用的来源
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_source: Option<CostSource>,
+}
+
+impl UsageOutput {
+    /// 从上游响应的 `usage` 字段提取，没有任何可用字段时返回 None
+    pub fn from_usage(usage: Option<&Value>) -> Option<Self> {
+        let usage = usage?;
+        let tokens = |key: &str| usage.get(key).and_then(Value::as_u64);
+        let output = Self {
+            prompt_tokens: tokens("prompt_tokens"),
+            completion_tokens: tokens("completion_tokens"),
+            total_tokens: tokens("total_tokens"),
+            cost: usage.get("cost").and_then(Value::as_f64),
+            cost_source: usage
+                .get("cost_source")
+                .and_then(|source| serde_json::from_value(source.clone()).ok())
+                .or_else(|| usage.get("cost").map(|_| CostSource::Upstream)),
+        };
+        (output.prompt_tokens.is_some()
+            || output.completion_tokens.is_some()
+            || output.total_tokens.is_some()
+            || output.cost.is_some())
+        .then_some(output)
+    }
+}
+
+/// 多条生成中一条的结果
+#[derive(Debug, Default, Serialize, schemars::JsonSchema)]
+pub struct ItemOutput {
+    /// 序号（从 1 开始）
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<OutputImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageOutput>,
+    /// 这一条失败时的错误信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ToolOutput {
+    pub fn new(tool: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// 一次图像生成/编辑的结果
+    pub fn from_outcome(tool: &str, provider: &str, outcome: &ImageOutcome) -> Self {
+        Self {
+            tool: tool.to_string(),
+            generation_id: Some(outcome.generation_id.clone()),
+            model: Some(outcome.model.clone()),
+            provider: Some(provider.to_string()),
+            text: Some(outcome.text.clone()),
+            images: outcome.images.iter().map(OutputImage::from).collect(),
+            usage: UsageOutput::from_usage(outcome.usage.as_ref()),
+            finish: (!outcome.finish.is_empty()).then(|| outcome.finish.clone()),
+            warnings: outcome
+                .rephrased_prompt
+                .iter()
+                .map(|prompt| tr!("output.rephrased_prompt", prompt = prompt))
+                .chain(
+                    outcome
+                        .images
+                        .iter()
+                        .filter(|image| image.saved_path.is_none())
+                        .map(|image| match image.debug_info.as_str() {
+                            "" => tr!("output.not_saved"),
+                            reason => tr!("output.not_saved_reason", reason = reason),
+                        }),
+                )
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_provider(mut self, provider: impl Into<String>) -> Self {
+        self.provider = Some(provider.into());
+        self
+    }
+
+    pub fn with_usage(mut self, usage: Option<&Value>) -> Self {
+        self.usage = UsageOutput::from_usage(usage);
+        self
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn with_error(mut self, error: ErrorDetails) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn warn(&mut self, warning: impl Into<String>) {
+        self.warnings.push(warning.into());
+    }
+
+    /// 把本地抠图的结果加入输出图像，失败的记为警告
+    pub fn push_cutouts(&mut self, cutouts: &[Cutout]) {
+        if cutouts.is_empty() {
+            self.warn(tr!("output.no_image_for_cutout"));
+        }
+        for (index, cutout) in cutouts.iter().enumerate() {
+            match &cutout.result {
+                Ok(path) => {
+                    let size = image::image_dimensions(path).ok();
+                    self.images.push(OutputImage {
+                        path: Some(path.clone()),
+                        mime_type: Some("image/png".to_string()),
+                        width: size.map(|(width, _)| width),
+                        height: size.map(|(_, height)| height),
+                        byte_size: std::fs::metadata(path)
+                            .ok()
+                            .map(|metadata| metadata.len() as usize),
+                        variant: Some("transparent".to_string()),
+                        ..Default::default()
+                    })
+                }
+                Err(error) => self.warn(tr!(
+                    "output.cutout_failed",
+                    index = index + 1,
+                    error = error
+                )),
+            }
+        }
+    }
+
+    /// 与文本响应一起组成工具结果
+    pub fn into_result(self, text: String) -> CallToolResult {
+        let mut result = CallToolResult::success(vec![Content::text(text)]);
+        result.structured_content = serde_json::to_value(self).ok();
+        result
+    }
+}
+
+impl ItemOutput {
+    /// 一条成功的生成
+    pub fn from_outcome(index: usize, prompt: Option<String>, outcome: &ImageOutcome) -> Self {
+        Self {
+            index,
+            prompt,
+            generation_id: Some(outcome.generation_id.clone()),
+            images: outcome.images.iter().map(OutputImage::from).collect(),
+            usage: UsageOutput::from_usage(outcome.usage.as_ref()),
+            error: None,
+        }
+    }
+
+    /// 一条失败的生成
+    pub fn failed(index: usize, prompt: Option<String>, error: impl Into<String>) -> Self {
+        Self {
+            index,
+            prompt,
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+}
+
+/// 所有工具声明的输出 schema
+pub fn schema() -> Arc<JsonObject> {
+    rmcp::handler::server::tool::cached_schema_for_type::<ToolOutput>()
+}
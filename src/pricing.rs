@@ -0,0 +1,312 @@
+use crate::{i18n::tr, server::OpenRouterServer};
+use anyhow::{Result, anyhow};
+use rmcp::schemars;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+/// 从上游 models 接口获取的价格的缓存时间
+const PRICING_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// 获取价格失败后，再次尝试前的等待时间
+const PRICING_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// 获取上游价格的超时时间，避免估算费用拖慢工具调用
+const PRICING_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 模型单价（美元），与 OpenRouter models 接口的 `pricing` 字段一致
+///
+/// 上游以字符串表示价格，本地价格表也可以直接写数字；缺少的项按 0 计算
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelPrice {
+    /// 每个输入 token
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub prompt: f64,
+    /// 每个输出 token
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub completion: f64,
+    /// 每张输入图像
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub image: f64,
+    /// 每次请求
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub request: f64,
+}
+
+impl ModelPrice {
+    /// 按 token 数和输入图像数估算一次调用的费用
+    ///
+    /// OpenRouter 对按路由动态计价的模型返回 -1，这时无法估算
+    pub fn estimate(
+        &self,
+        prompt_tokens: u64,
+        completion_tokens: u64,
+        input_images: u64,
+    ) -> Option<f64> {
+        if [self.prompt, self.completion, self.image, self.request]
+            .iter()
+            .any(|price| *price < 0.0)
+        {
+            return None;
+        }
+        Some(
+            self.prompt * prompt_tokens as f64
+                + self.completion * completion_tokens as f64
+                + self.image * input_images as f64
+                + self.request,
+        )
+    }
+}
+
+fn deserialize_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(number) => Ok(number.as_f64().unwrap_or_default()),
+        Value::String(text) => text.trim().parse().map_err(serde::de::Error::custom),
+        Value::Null => Ok(0.0),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a number or string, got {}",
+            other
+        ))),
+    }
+}
+
+/// 费用的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CostSource {
+    /// 上游在 `usage.cost` 中返回的实际费用
+    Upstream,
+    /// 按本地价格表估算
+    PriceTable,
+    /// 按上游 models 接口的价格估算
+    ProviderPricing,
+}
+
+impl CostSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostSource::Upstream => "upstream",
+            CostSource::PriceTable => "price_table",
+            CostSource::ProviderPricing => "provider_pricing",
+        }
+    }
+
+    /// 给人阅读的说明
+    pub fn label(&self) -> String {
+        match self {
+            CostSource::Upstream => tr!("pricing.source.upstream"),
+            CostSource::PriceTable => tr!("pricing.source.price_table"),
+            CostSource::ProviderPricing => tr!("pricing.source.provider_pricing"),
+        }
+    }
+}
+
+/// 读取本地价格表：以模型 ID 为键的 JSON 对象，值的格式与 OpenRouter 的 `pricing` 相同
+pub fn load_price_table(path: &Path) -> Result<HashMap<String, ModelPrice>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        anyhow!(tr!(
+            "pricing.table_read_failed",
+            path = path.display(),
+            error = e
+        ))
+    })?;
+    serde_json::from_str(&text).map_err(|e| {
+        anyhow!(tr!(
+            "pricing.table_invalid",
+            path = path.display(),
+            error = e
+        ))
+    })
+}
+
+/// 模型价格：本地价格表优先，其次是缓存的上游价格
+#[derive(Clone, Default)]
+pub struct Pricing {
+    table: Arc<HashMap<String, ModelPrice>>,
+    cache: Arc<RwLock<Option<CachedPrices>>>,
+    /// 保证同一时间只有一个请求在获取上游价格
+    refresh: Arc<Mutex<()>>,
+}
+
+struct CachedPrices {
+    fetched_at: Instant,
+    /// 获取失败时为空，在 `PRICING_RETRY_AFTER` 之后重试
+    prices: Option<HashMap<String, ModelPrice>>,
+}
+
+impl CachedPrices {
+    fn is_fresh(&self) -> bool {
+        let ttl = if self.prices.is_some() {
+            PRICING_TTL
+        } else {
+            PRICING_RETRY_AFTER
+        };
+        self.fetched_at.elapsed() < ttl
+    }
+
+    fn get(&self, model: &str) -> Option<ModelPrice> {
+        self.prices.as_ref()?.get(model).cloned()
+    }
+}
+
+impl Pricing {
+    pub fn new(table: HashMap<String, ModelPrice>) -> Self {
+        Self {
+            table: Arc::new(table),
+            cache: Arc::default(),
+            refresh: Arc::default(),
+        }
+    }
+}
+
+impl OpenRouterServer {
+    /// 查找模型单价及其来源
+    pub(crate) async fn model_price(&self, model: &str) -> Option<(ModelPrice, CostSource)> {
+        if let Some(price) = self.pricing.table.get(model) {
+            return Some((price.clone(), CostSource::PriceTable));
+        }
+        if let Some(cached) = self.cached_price(model).await {
+            return cached;
+        }
+
+        // 只让一个请求获取价格，其余请求等待它写入缓存；获取期间不持有缓存的锁
+        let _refresh = self.pricing.refresh.lock().await;
+        if let Some(cached) = self.cached_price(model).await {
+            return cached;
+        }
+        let prices = match self.fetch_provider_pricing().await {
+            Ok(prices) => {
+                tracing::info!("已获取 {} 个模型的价格", prices.len());
+                Some(prices)
+            }
+            Err(e) => {
+                tracing::warn!("获取模型价格失败，暂时无法估算费用: {}", e);
+                None
+            }
+        };
+        let cached = CachedPrices {
+            fetched_at: Instant::now(),
+            prices,
+        };
+        let price = cached
+            .get(model)
+            .map(|price| (price, CostSource::ProviderPricing));
+        *self.pricing.cache.write().await = Some(cached);
+        price
+    }
+
+    /// 缓存未过期时返回其中的价格（外层为 None 表示需要重新获取）
+    async fn cached_price(&self, model: &str) -> Option<Option<(ModelPrice, CostSource)>> {
+        let cache = self.pricing.cache.read().await;
+        let cached = cache.as_ref().filter(|cached| cached.is_fresh())?;
+        Some(
+            cached
+                .get(model)
+                .map(|price| (price, CostSource::ProviderPricing)),
+        )
+    }
+
+    /// 调用上游 models 接口，读取每个模型的 `pricing`
+    async fn fetch_provider_pricing(&self) -> Result<HashMap<String, ModelPrice>> {
+        let url = format!("{}/models", self.config.base_url);
+        let response = self
+            .client
+            .get(&url)
+            .timeout(PRICING_FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        let body = response.json::<Value>().await?;
+        let models = body
+            .get("data")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!(tr!("pricing.unexpected_models_response")))?;
+        Ok(models
+            .iter()
+            .filter_map(|model| {
+                let id = model.get("id")?.as_str()?;
+                let price = serde_json::from_value(model.get("pricing")?.clone()).ok()?;
+                Some((id.to_string(), price))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: Value) -> ModelPrice {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("应能估算费用");
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn estimate_sums_tokens_images_and_request() {
+        let price = parse(serde_json::json!({
+            "prompt": "0.0000003",
+            "completion": "0.0000025",
+            "image": "0.001238",
+            "request": "0.0001",
+        }));
+        assert_close(
+            price.estimate(1000, 1290, 2),
+            0.0003 + 0.003225 + 0.002476 + 0.0001,
+        );
+        // 没有输入图像时不计图像费用，请求费用总会计入
+        assert_close(price.estimate(0, 0, 0), 0.0001);
+        // 每张输入图像的费用按张数线性累加
+        let one = price.estimate(0, 0, 1).unwrap();
+        let three = price.estimate(0, 0, 3).unwrap();
+        assert_close(Some(three - one), 2.0 * 0.001238);
+    }
+
+    #[test]
+    fn estimate_keeps_sub_cent_precision() {
+        // 单价很小时结果不能被截断为 0，输出按 6 位小数显示
+        let price = parse(serde_json::json!({ "completion": "0.00000004" }));
+        let cost = price.estimate(0, 1290, 0);
+        assert_close(cost, 0.0000516);
+        assert_eq!(format!("{:.6}", cost.unwrap()), "0.000052");
+    }
+
+    #[test]
+    fn dynamic_prices_cannot_be_estimated() {
+        let price = parse(serde_json::json!({ "prompt": "-1", "completion": "0.000001" }));
+        assert_eq!(price.estimate(10, 10, 0), None);
+        let price = parse(serde_json::json!({ "image": -1 }));
+        assert_eq!(price.estimate(0, 0, 0), None);
+    }
+
+    #[test]
+    fn prices_accept_strings_numbers_and_missing_fields() {
+        let price = parse(serde_json::json!({
+            "prompt": 0.5,
+            "completion": " 2 ",
+            "image": null,
+        }));
+        assert_eq!(price.prompt, 0.5);
+        assert_eq!(price.completion, 2.0);
+        assert_eq!(price.image, 0.0);
+        assert_eq!(price.request, 0.0);
+        assert!(
+            serde_json::from_value::<ModelPrice>(serde_json::json!({ "prompt": "free" })).is_err()
+        );
+        assert!(
+            serde_json::from_value::<ModelPrice>(serde_json::json!({ "prompt": [1] })).is_err()
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use crate::i18n::tr;
+use crate::tools::GenerateImageArgs;
+use anyhow::{Context, Result, anyhow};
+use rmcp::{
+    model::{JsonObject, Prompt, PromptArgument},
+    schemars,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 默认的提示词模板目录（相对保存目录）
+pub const DEFAULT_PROMPTS_DIR: &str = ".nano-banana/prompts";
+
+/// 单个模板文件的大小上限
+const MAX_TEMPLATE_BYTES: u64 = 64 * 1024;
+
+/// `.json` 模板文件的内容
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateFile {
+    /// 提示词名称，默认使用文件名
+    name: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    /// 带 `{参数名}` 占位符的提示词，`{{` 和 `}}` 表示字面量花括号
+    template: String,
+    #[serde(default)]
+    arguments: Vec<TemplateArgument>,
+    /// 固定传给 generate_image 的其他参数，如 `seed`、`transparent_background`
+    #[serde(default)]
+    options: Map<String, Value>,
+}
+
+/// 模板参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateArgument {
+    pub name: String,
+    pub description: Option<String>,
+    /// 未给出时，没有默认值的参数为必填
+    pub required: Option<bool>,
+    /// 调用方未提供时使用的值
+    pub default: Option<String>,
+}
+
+impl TemplateArgument {
+    fn is_required(&self) -> bool {
+        self.required.unwrap_or(self.default.is_none())
+    }
+}
+
+/// 模板文本的一个片段
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// 一个参数化的提示词模板，渲染为 generate_image 的调用参数
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub arguments: Vec<TemplateArgument>,
+    pub options: Map<String, Value>,
+    segments: Vec<Segment>,
+}
+
+impl PromptTemplate {
+    /// 读取模板文件：`.json` 为完整定义，`.txt` / `.md` 的全部内容即为模板，参数从占位符推断
+    fn from_file(path: &Path) -> Result<Self> {
+        let size = fs::metadata(path)?.len();
+        if size > MAX_TEMPLATE_BYTES {
+            return Err(anyhow!(tr!(
+                "prompts.file_too_large",
+                size = size,
+                max = MAX_TEMPLATE_BYTES
+            )));
+        }
+        let content = fs::read_to_string(path)?;
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let file = if is_extension(path, "json") {
+            serde_json::from_str(&content).context(tr!("prompts.invalid_json"))?
+        } else {
+            TemplateFile {
+                name: None,
+                title: None,
+                description: None,
+                template: content.trim().to_string(),
+                arguments: Vec::new(),
+                options: Map::new(),
+            }
+        };
+        Self::new(file, stem)
+    }
+
+    fn new(file: TemplateFile, default_name: String) -> Result<Self> {
+        let name = file.name.unwrap_or(default_name).trim().to_string();
+        if name.is_empty() {
+            return Err(anyhow!(tr!("prompts.empty_name")));
+        }
+        if file.template.trim().is_empty() {
+            return Err(anyhow!(tr!("prompts.empty_template")));
+        }
+        if file.options.contains_key("prompt") {
+            return Err(anyhow!(tr!("prompts.prompt_in_options")));
+        }
+
+        let segments = parse_template(&file.template)?;
+        let mut arguments = file.arguments;
+        for (index, argument) in arguments.iter().enumerate() {
+            if arguments[..index].iter().any(|a| a.name == argument.name) {
+                return Err(anyhow!(tr!(
+                    "prompts.duplicate_argument",
+                    name = argument.name
+                )));
+            }
+        }
+        // 模板中出现但没有声明的占位符作为必填参数
+        for segment in &segments {
+            if let Segment::Placeholder(placeholder) = segment
+                && !arguments.iter().any(|a| &a.name == placeholder)
+            {
+                arguments.push(TemplateArgument {
+                    name: placeholder.clone(),
+                    description: None,
+                    required: Some(true),
+                    default: None,
+                });
+            }
+        }
+
+        let template = Self {
+            name,
+            title: file.title,
+            description: file.description,
+            arguments,
+            options: file.options,
+            segments,
+        };
+        // 用占位值试渲染一次，提前发现 options 与 generate_image 参数不匹配的问题
+        template.generate_arguments("-".to_string())?;
+        Ok(template)
+    }
+
+    /// 转换为 prompts/list 中的描述
+    pub fn to_prompt(&self) -> Prompt {
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|argument| PromptArgument {
+                name: argument.name.clone(),
+                title: None,
+                description: match (&argument.description, &argument.default) {
+                    (Some(description), Some(default)) => Some(tr!(
+                        "prompts.argument_with_default",
+                        description = description,
+                        default = default
+                    )),
+                    (None, Some(default)) => {
+                        Some(tr!("prompts.argument_default", default = default))
+                    }
+                    (description, None) => description.clone(),
+                },
+                required: Some(argument.is_required()),
+            })
+            .collect::<Vec<_>>();
+        let mut prompt = Prompt::new(
+            &self.name,
+            self.description.as_deref(),
+            (!arguments.is_empty()).then_some(arguments),
+        );
+        prompt.title = self.title.clone();
+        prompt
+    }
+
+    /// 用调用方提供的参数渲染提示词文本
+    pub fn render(&self, values: Option<&JsonObject>) -> Result<String> {
+        let mut resolved = BTreeMap::new();
+        let mut missing = Vec::new();
+        for argument in &self.arguments {
+            let value = values
+                .and_then(|values| values.get(&argument.name))
+                .map(value_to_string)
+                .filter(|value| !value.trim().is_empty())
+                .or_else(|| argument.default.clone());
+            match value {
+                Some(value) => {
+                    resolved.insert(argument.name.as_str(), value);
+                }
+                None if argument.is_required() => missing.push(argument.name.as_str()),
+                None => {
+                    resolved.insert(argument.name.as_str(), String::new());
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(anyhow!(tr!(
+                "prompts.missing_arguments",
+                names = missing.join(", ")
+            )));
+        }
+
+        let rendered: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.as_str(),
+                Segment::Placeholder(name) => resolved[name.as_str()].trim(),
+            })
+            .collect();
+        Ok(rendered.trim().to_string())
+    }
+
+    /// 渲染结果对应的 generate_image 参数
+    pub fn generate_arguments(&self, prompt: String) -> Result<Value> {
+        let schema = schemars::schema_for!(GenerateImageArgs);
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(unknown) = self
+            .options
+            .keys()
+            .find(|key| properties.is_some_and(|properties| !properties.contains_key(*key)))
+        {
+            return Err(anyhow!(tr!("prompts.unknown_option", name = unknown)));
+        }
+        let mut arguments = self.options.clone();
+        arguments.insert("prompt".to_string(), Value::String(prompt));
+        let arguments = Value::Object(arguments);
+        serde_json::from_value::<GenerateImageArgs>(arguments.clone())
+            .map_err(|e| anyhow!(tr!("prompts.invalid_options", error = e)))?;
+        Ok(arguments)
+    }
+}
+
+/// 本地提示词模板目录，每次请求时重新读取，修改模板后无需重启
+#[derive(Debug, Clone)]
+pub struct PromptLibrary {
+    dir: PathBuf,
+}
+
+impl PromptLibrary {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 读取目录中的全部模板，按名称排序；无效的文件记录警告后跳过
+    pub fn load_all(&self) -> Vec<PromptTemplate> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("无法读取提示词模板目录 {}: {}", self.dir.display(), e);
+                }
+                return Vec::new();
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && ["json", "txt", "md"]
+                        .iter()
+                        .any(|extension| is_extension(path, extension))
+            })
+            .collect();
+        paths.sort();
+
+        let mut templates: Vec<PromptTemplate> = Vec::with_capacity(paths.len());
+        for path in paths {
+            match PromptTemplate::from_file(&path) {
+                Ok(template) if templates.iter().any(|t| t.name == template.name) => {
+                    tracing::warn!(
+                        "提示词模板 {} 与已有模板重名（{}），已忽略",
+                        path.display(),
+                        template.name
+                    );
+                }
+                Ok(template) => templates.push(template),
+                Err(e) => tracing::warn!("跳过无效的提示词模板 {}: {}", path.display(), e),
+            }
+        }
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        templates
+    }
+
+    pub fn get(&self, name: &str) -> Option<PromptTemplate> {
+        self.load_all()
+            .into_iter()
+            .find(|template| template.name == name)
+    }
+}
+
+/// 把模板拆分为字面量和占位符
+fn parse_template(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => {
+                            return Err(anyhow!(tr!("prompts.unclosed_placeholder", name = name)));
+                        }
+                    }
+                }
+                let name = name.trim().to_string();
+                if name.is_empty() || name.contains('{') {
+                    return Err(anyhow!(tr!("prompts.invalid_placeholder", name = name)));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Placeholder(name));
+            }
+            '}' => return Err(anyhow!(tr!("prompts.stray_brace"))),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn is_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn template(file: Value) -> Result<PromptTemplate> {
+        PromptTemplate::new(serde_json::from_value(file).unwrap(), "test".to_string())
+    }
+
+    fn args(values: Value) -> JsonObject {
+        values.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = template(json!({"template": "{{logo}} for {brand} }}"})).unwrap();
+        assert_eq!(
+            template
+                .render(Some(&args(json!({"brand": "Acme"}))))
+                .unwrap(),
+            "{logo} for Acme }"
+        );
+        assert_eq!(template.arguments.len(), 1);
+    }
+
+    #[test]
+    fn unbalanced_braces_are_errors() {
+        assert!(parse_template("a {subject").is_err());
+        assert!(parse_template("a } b").is_err());
+        assert!(parse_template("a {} b").is_err());
+        assert!(parse_template("a { } b").is_err());
+        assert!(parse_template("a {x{y} b").is_err());
+    }
+
+    #[test]
+    fn undeclared_placeholders_become_required_arguments() {
+        let template = template(json!({
+            "template": "{subject} in {style}, {subject} again",
+            "arguments": [{"name": "style", "default": "watercolor"}]
+        }))
+        .unwrap();
+        let names: Vec<_> = template.arguments.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["style", "subject"]);
+        assert!(!template.arguments[0].is_required());
+        assert!(template.arguments[1].is_required());
+
+        assert!(template.render(None).is_err());
+        assert!(
+            template
+                .render(Some(&args(json!({"subject": "  "}))))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn defaults_fill_missing_and_blank_values() {
+        let template = template(json!({
+            "template": "{subject} in {style}{extra}",
+            "arguments": [
+                {"name": "style", "default": "watercolor"},
+                {"name": "extra", "required": false}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            template
+                .render(Some(&args(json!({"subject": "a fox"}))))
+                .unwrap(),
+            "a fox in watercolor"
+        );
+        assert_eq!(
+            template
+                .render(Some(&args(
+                    json!({"subject": "a fox", "style": " ", "extra": ", at night"})
+                )))
+                .unwrap(),
+            "a fox in watercolor, at night"
+        );
+        assert_eq!(
+            template
+                .render(Some(&args(json!({"subject": 42, "style": "oil"}))))
+                .unwrap(),
+            "42 in oil"
+        );
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        assert!(template(json!({"template": "  "})).is_err());
+        assert!(template(json!({"template": "x", "options": {"prompt": "y"}})).is_err());
+        assert!(template(json!({"template": "x", "options": {"no_such_option": 1}})).is_err());
+        assert!(
+            template(json!({
+                "template": "{a}",
+                "arguments": [{"name": "a"}, {"name": "a"}]
+            }))
+            .is_err()
+        );
+    }
+}
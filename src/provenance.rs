@@ -0,0 +1,622 @@
+use crate::i18n::tr;
+use anyhow::{Result, anyhow};
+
+/// 嵌入到图像中的生成来源标记
+pub const GENERATOR_MARKER: &str = "generated by nano-banana-mcp";
+
+/// XMP 中使用的自定义命名空间
+const XMP_NAMESPACE: &str = "https://nano-banana-mcp/ns/1.0/";
+/// JPEG APP1 段中 XMP 数据的标识头
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// JPEG 单个段的最大负载长度（不含长度字段本身）
+const JPEG_SEGMENT_MAX: usize = 65533;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// 需要写入图像的生成来源信息
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub prompt: String,
+    pub model: String,
+    pub tool: String,
+    pub created_at: String,
+}
+
+/// 支持嵌入元数据的图像格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+/// 根据文件头识别图像格式
+pub fn detect_format(bytes: &[u8]) -> Option<ImageFormat> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        Some(ImageFormat::Png)
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageFormat::Jpeg)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(ImageFormat::Webp)
+    } else {
+        None
+    }
+}
+
+/// 将来源信息嵌入图像字节，PNG 使用 tEXt/iTXt，JPEG/WebP 使用 XMP
+pub fn embed(bytes: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    match detect_format(bytes) {
+        Some(ImageFormat::Png) => embed_png(bytes, provenance),
+        Some(ImageFormat::Jpeg) => embed_jpeg(bytes, provenance),
+        Some(ImageFormat::Webp) => embed_webp(bytes, provenance),
+        None => Err(anyhow!(tr!("provenance.unsupported_embed"))),
+    }
+}
+
+/// 从图像字节中读取文本元数据，返回 (键, 值) 列表
+pub fn read(bytes: &[u8]) -> Result<Vec<(String, String)>> {
+    match detect_format(bytes) {
+        Some(ImageFormat::Png) => read_png(bytes),
+        Some(ImageFormat::Jpeg) => Ok(read_jpeg_xmp(bytes)
+            .map(|xmp| parse_xmp(&xmp))
+            .unwrap_or_default()),
+        Some(ImageFormat::Webp) => Ok(read_webp_xmp(bytes)
+            .map(|xmp| parse_xmp(&xmp))
+            .unwrap_or_default()),
+        None => Err(anyhow!(tr!("provenance.unrecognized_format"))),
+    }
+}
+
+// ---------- PNG ----------
+
+fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+fn png_text_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    data.extend_from_slice(text.as_bytes());
+    png_chunk(b"tEXt", &data)
+}
+
+fn png_itxt_chunk(keyword: &str, text: &str) -> Vec<u8> {
+    // keyword \0 compression_flag compression_method language \0 translated_keyword \0 text
+    let mut data = keyword.as_bytes().to_vec();
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(text.as_bytes());
+    png_chunk(b"iTXt", &data)
+}
+
+/// PNG 数据块：(类型, 数据, 块起始偏移)
+type PngChunk<'a> = ([u8; 4], &'a [u8], usize);
+
+/// 遍历 PNG 数据块
+fn png_chunks(bytes: &[u8]) -> Result<Vec<PngChunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 12 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into()?) as usize;
+        let chunk_type: [u8; 4] = bytes[offset + 4..offset + 8].try_into()?;
+        let data_end = offset + 8 + length;
+        if data_end + 4 > bytes.len() {
+            return Err(anyhow!(tr!("provenance.png_chunk_out_of_bounds")));
+        }
+        chunks.push((chunk_type, &bytes[offset + 8..data_end], offset));
+        offset = data_end + 4;
+        if &chunk_type == b"IEND" {
+            break;
+        }
+    }
+    Ok(chunks)
+}
+
+fn embed_png(bytes: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    let iend_offset = png_chunks(bytes)?
+        .iter()
+        .find(|(chunk_type, _, _)| chunk_type == b"IEND")
+        .map(|(_, _, offset)| *offset)
+        .ok_or_else(|| anyhow!(tr!("provenance.png_missing_iend")))?;
+
+    let mut output = Vec::with_capacity(bytes.len() + provenance.prompt.len() + 256);
+    output.extend_from_slice(&bytes[..iend_offset]);
+    output.extend(png_text_chunk(
+        "Software",
+        &format!("nano-banana-mcp {}", env!("CARGO_PKG_VERSION")),
+    ));
+    output.extend(png_text_chunk("Comment", GENERATOR_MARKER));
+    output.extend(png_text_chunk("Creation Time", &provenance.created_at));
+    output.extend(png_itxt_chunk("nano-banana:model", &provenance.model));
+    output.extend(png_itxt_chunk("nano-banana:tool", &provenance.tool));
+    output.extend(png_itxt_chunk("Description", &provenance.prompt));
+    output.extend_from_slice(&bytes[iend_offset..]);
+    Ok(output)
+}
+
+fn read_png(bytes: &[u8]) -> Result<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+    for (chunk_type, data, _) in png_chunks(bytes)? {
+        match &chunk_type {
+            b"tEXt" => {
+                if let Some(split) = data.iter().position(|b| *b == 0) {
+                    // tEXt 使用 Latin-1 编码
+                    let keyword: String = data[..split].iter().map(|b| *b as char).collect();
+                    let text: String = data[split + 1..].iter().map(|b| *b as char).collect();
+                    entries.push((keyword, text));
+                }
+            }
+            b"iTXt" => {
+                if let Some((keyword, text)) = parse_itxt(data) {
+                    if keyword == "XML:com.adobe.xmp" {
+                        entries.extend(parse_xmp(&text));
+                    } else {
+                        entries.push((keyword, text));
+                    }
+                }
+            }
+            b"zTXt" => {
+                if let Some(split) = data.iter().position(|b| *b == 0) {
+                    let keyword = String::from_utf8_lossy(&data[..split]).to_string();
+                    entries.push((keyword, tr!("provenance.compressed_text")));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_itxt(data: &[u8]) -> Option<(String, String)> {
+    let keyword_end = data.iter().position(|b| *b == 0)?;
+    let keyword = String::from_utf8_lossy(&data[..keyword_end]).to_string();
+    let compressed = *data.get(keyword_end + 1)? != 0;
+    let rest = data.get(keyword_end + 3..)?;
+    let language_end = rest.iter().position(|b| *b == 0)?;
+    let rest = &rest[language_end + 1..];
+    let translated_end = rest.iter().position(|b| *b == 0)?;
+    let text = &rest[translated_end + 1..];
+    if compressed {
+        return Some((keyword, tr!("provenance.compressed_text")));
+    }
+    Some((keyword, String::from_utf8_lossy(text).to_string()))
+}
+
+// ---------- XMP ----------
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn build_xmp(provenance: &Provenance, max_len: usize) -> String {
+    let render = |prompt: &str| {
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+                "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+                "<rdf:Description rdf:about=\"\"",
+                " xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"",
+                " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+                " xmlns:nb=\"{ns}\"",
+                " xmp:CreatorTool=\"nano-banana-mcp {version}\"",
+                " xmp:CreateDate=\"{created}\"",
+                " nb:Generator=\"{marker}\"",
+                " nb:Model=\"{model}\"",
+                " nb:Tool=\"{tool}\">",
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{prompt}</rdf:li></rdf:Alt></dc:description>",
+                "<nb:Prompt>{prompt}</nb:Prompt>",
+                "</rdf:Description></rdf:RDF></x:xmpmeta>",
+                "<?xpacket end=\"w\"?>"
+            ),
+            ns = XMP_NAMESPACE,
+            version = env!("CARGO_PKG_VERSION"),
+            created = xml_escape(&provenance.created_at),
+            marker = GENERATOR_MARKER,
+            model = xml_escape(&provenance.model),
+            tool = xml_escape(&provenance.tool),
+            prompt = xml_escape(prompt),
+        )
+    };
+
+    let mut xmp = render(&provenance.prompt);
+    if xmp.len() > max_len {
+        // 提示词过长时截断，保证 XMP 能放进单个 JPEG 段
+        let overflow = xmp.len() - max_len;
+        let mut keep = provenance.prompt.len().saturating_sub(overflow + 16);
+        while !provenance.prompt.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        xmp = render(&format!("{}…", &provenance.prompt[..keep]));
+    }
+    xmp
+}
+
+/// 从 XMP 中提取本服务写入的字段
+fn parse_xmp(xmp: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    for (key, attribute) in [
+        ("generator", "nb:Generator"),
+        ("model", "nb:Model"),
+        ("tool", "nb:Tool"),
+        ("created_at", "xmp:CreateDate"),
+        ("creator_tool", "xmp:CreatorTool"),
+    ] {
+        let pattern = format!("{}=\"", attribute);
+        if let Some(start) = xmp.find(&pattern) {
+            let value = &xmp[start + pattern.len()..];
+            if let Some(end) = value.find('"') {
+                entries.push((key.to_string(), xml_unescape(&value[..end])));
+            }
+        }
+    }
+    if let Some(start) = xmp.find("<nb:Prompt>")
+        && let Some(end) = xmp[start..].find("</nb:Prompt>")
+    {
+        let value = &xmp[start + "<nb:Prompt>".len()..start + end];
+        entries.push(("prompt".to_string(), xml_unescape(value)));
+    }
+    entries
+}
+
+// ---------- JPEG ----------
+
+fn embed_jpeg(bytes: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    let xmp = build_xmp(provenance, JPEG_SEGMENT_MAX - JPEG_XMP_HEADER.len());
+    let payload_len = JPEG_XMP_HEADER.len() + xmp.len() + 2;
+
+    // 插入到 SOI 以及紧随其后的 APP0(JFIF) 段之后
+    let mut insert_at = 2;
+    if bytes.len() > 6 && bytes[2] == 0xFF && bytes[3] == 0xE0 {
+        let length = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+        insert_at = (4 + length).min(bytes.len());
+    }
+
+    let mut output = Vec::with_capacity(bytes.len() + payload_len + 2);
+    output.extend_from_slice(&bytes[..insert_at]);
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&(payload_len as u16).to_be_bytes());
+    output.extend_from_slice(JPEG_XMP_HEADER);
+    output.extend_from_slice(xmp.as_bytes());
+    output.extend_from_slice(&bytes[insert_at..]);
+    Ok(output)
+}
+
+fn read_jpeg_xmp(bytes: &[u8]) -> Option<String> {
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF {
+        let marker = bytes[offset + 1];
+        // SOS 之后是压缩数据，不再包含元数据段
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        let segment = bytes.get(offset + 4..offset + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(JPEG_XMP_HEADER) {
+            return Some(String::from_utf8_lossy(&segment[JPEG_XMP_HEADER.len()..]).to_string());
+        }
+        offset += 2 + length;
+    }
+    None
+}
+
+// ---------- WebP ----------
+
+fn webp_chunks(bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let fourcc: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap_or_default();
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap_or_default())
+            as usize;
+        let Some(data) = bytes.get(offset + 8..offset + 8 + size) else {
+            break;
+        };
+        chunks.push((fourcc, data));
+        offset += 8 + size + (size & 1);
+    }
+    chunks
+}
+
+fn webp_chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 9);
+    chunk.extend_from_slice(fourcc);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// 读取简单格式 WebP（VP8 / VP8L）的画布尺寸和是否带透明通道
+fn webp_simple_dimensions(fourcc: &[u8; 4], data: &[u8]) -> Option<(u32, u32, bool)> {
+    match fourcc {
+        b"VP8 " => {
+            if data.len() < 10 || data[3..6] != [0x9D, 0x01, 0x2A] {
+                return None;
+            }
+            let width = u16::from_le_bytes([data[6], data[7]]) as u32 & 0x3FFF;
+            let height = u16::from_le_bytes([data[8], data[9]]) as u32 & 0x3FFF;
+            Some((width, height, false))
+        }
+        b"VP8L" => {
+            if data.len() < 5 || data[0] != 0x2F {
+                return None;
+            }
+            let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            let width = (bits & 0x3FFF) + 1;
+            let height = ((bits >> 14) & 0x3FFF) + 1;
+            let alpha = (bits >> 28) & 1 == 1;
+            Some((width, height, alpha))
+        }
+        _ => None,
+    }
+}
+
+fn embed_webp(bytes: &[u8], provenance: &Provenance) -> Result<Vec<u8>> {
+    let chunks = webp_chunks(bytes);
+    let first = chunks
+        .first()
+        .ok_or_else(|| anyhow!(tr!("provenance.webp_empty")))?;
+
+    let mut body = Vec::with_capacity(bytes.len() + 1024);
+    body.extend_from_slice(b"WEBP");
+
+    if &first.0 == b"VP8X" {
+        // 扩展格式：设置 XMP 标志位并替换已有的 XMP 块
+        let mut header = first.1.to_vec();
+        if header.len() < 10 {
+            return Err(anyhow!(tr!("provenance.webp_size")));
+        }
+        header[0] |= 0x04;
+        body.extend(webp_chunk(b"VP8X", &header));
+        for (fourcc, data) in chunks.iter().skip(1) {
+            if fourcc != b"XMP " {
+                body.extend(webp_chunk(fourcc, data));
+            }
+        }
+    } else {
+        // 简单格式：需要补充 VP8X 头才能携带 XMP
+        // VP8X 中记录的是宽高减一，尺寸为 0 的图像无法表示
+        let (canvas_width, canvas_height, alpha) = webp_simple_dimensions(&first.0, first.1)
+            .and_then(|(width, height, alpha)| {
+                Some((width.checked_sub(1)?, height.checked_sub(1)?, alpha))
+            })
+            .ok_or_else(|| anyhow!(tr!("provenance.webp_size")))?;
+        let mut header = vec![0u8; 10];
+        header[0] = 0x04 | if alpha { 0x10 } else { 0 };
+        header[4..7].copy_from_slice(&canvas_width.to_le_bytes()[..3]);
+        header[7..10].copy_from_slice(&canvas_height.to_le_bytes()[..3]);
+        body.extend(webp_chunk(b"VP8X", &header));
+        for (fourcc, data) in &chunks {
+            body.extend(webp_chunk(fourcc, data));
+        }
+    }
+
+    let xmp = build_xmp(provenance, usize::MAX);
+    body.extend(webp_chunk(b"XMP ", xmp.as_bytes()));
+
+    let mut output = Vec::with_capacity(body.len() + 8);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend(body);
+    Ok(output)
+}
+
+fn read_webp_xmp(bytes: &[u8]) -> Option<String> {
+    webp_chunks(bytes)
+        .into_iter()
+        .find(|(fourcc, _)| fourcc == b"XMP ")
+        .map(|(_, data)| String::from_utf8_lossy(data).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat as EncodeFormat, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    fn provenance(prompt: &str) -> Provenance {
+        Provenance {
+            prompt: prompt.to_string(),
+            model: "google/gemini-2.5-flash-image".to_string(),
+            tool: "generate_image".to_string(),
+            created_at: "2025-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn encode(image: DynamicImage, format: EncodeFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    fn value<'a>(entries: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// 只有一个 VP8 块的简单格式 WebP，块内只包含读取尺寸所需的帧头
+    fn simple_vp8_webp(width: u16, height: u16) -> Vec<u8> {
+        let mut frame = vec![0x10, 0x02, 0x00, 0x9D, 0x01, 0x2A];
+        frame.extend_from_slice(&width.to_le_bytes());
+        frame.extend_from_slice(&height.to_le_bytes());
+        let mut body = b"WEBP".to_vec();
+        body.extend(webp_chunk(b"VP8 ", &frame));
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn vp8x_header(bytes: &[u8]) -> Vec<u8> {
+        let chunks = webp_chunks(bytes);
+        assert_eq!(&chunks[0].0, b"VP8X");
+        chunks[0].1.to_vec()
+    }
+
+    fn canvas_size(header: &[u8]) -> (u32, u32) {
+        let width = u32::from_le_bytes([header[4], header[5], header[6], 0]) + 1;
+        let height = u32::from_le_bytes([header[7], header[8], header[9], 0]) + 1;
+        (width, height)
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let png = encode(
+            DynamicImage::ImageRgb8(RgbImage::new(4, 3)),
+            EncodeFormat::Png,
+        );
+        let embedded = embed(&png, &provenance("一只猫 & <狗>")).unwrap();
+        let entries = read(&embedded).unwrap();
+        assert_eq!(value(&entries, "Comment"), Some(GENERATOR_MARKER));
+        assert_eq!(value(&entries, "Description"), Some("一只猫 & <狗>"));
+        assert_eq!(
+            value(&entries, "nano-banana:model"),
+            Some("google/gemini-2.5-flash-image")
+        );
+        assert_eq!(value(&entries, "nano-banana:tool"), Some("generate_image"));
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let jpeg = encode(
+            DynamicImage::ImageRgb8(RgbImage::new(4, 3)),
+            EncodeFormat::Jpeg,
+        );
+        let embedded = embed(&jpeg, &provenance("a \"quoted\" prompt")).unwrap();
+        let entries = read(&embedded).unwrap();
+        assert_eq!(value(&entries, "generator"), Some(GENERATOR_MARKER));
+        assert_eq!(value(&entries, "prompt"), Some("a \"quoted\" prompt"));
+        assert_eq!(value(&entries, "tool"), Some("generate_image"));
+        assert_eq!(value(&entries, "created_at"), Some("2025-01-01T00:00:00Z"));
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+
+    #[test]
+    fn webp_round_trip() {
+        let webp = encode(
+            DynamicImage::ImageRgba8(RgbaImage::new(4, 3)),
+            EncodeFormat::WebP,
+        );
+        let embedded = embed(&webp, &provenance("sunset")).unwrap();
+        let entries = read(&embedded).unwrap();
+        assert_eq!(value(&entries, "prompt"), Some("sunset"));
+        assert_eq!(
+            value(&entries, "model"),
+            Some("google/gemini-2.5-flash-image")
+        );
+
+        // 再次嵌入时替换已有的 XMP 块，而不是追加第二个
+        let again = embed(&embedded, &provenance("sunrise")).unwrap();
+        let xmp_chunks = webp_chunks(&again)
+            .into_iter()
+            .filter(|(fourcc, _)| fourcc == b"XMP ")
+            .count();
+        assert_eq!(xmp_chunks, 1);
+        assert_eq!(value(&read(&again).unwrap(), "prompt"), Some("sunrise"));
+    }
+
+    #[test]
+    fn long_prompt_is_truncated_to_one_jpeg_segment() {
+        let max_len = JPEG_SEGMENT_MAX - JPEG_XMP_HEADER.len();
+        let prompt = "猫".repeat(40_000);
+        let xmp = build_xmp(&provenance(&prompt), max_len);
+        assert!(xmp.len() <= max_len);
+
+        let stored = parse_xmp(&xmp)
+            .into_iter()
+            .find(|(key, _)| key == "prompt")
+            .map(|(_, value)| value)
+            .unwrap();
+        assert!(stored.ends_with('…'));
+        assert!(prompt.starts_with(stored.trim_end_matches('…')));
+
+        let jpeg = encode(
+            DynamicImage::ImageRgb8(RgbImage::new(4, 3)),
+            EncodeFormat::Jpeg,
+        );
+        let embedded = embed(&jpeg, &provenance(&prompt)).unwrap();
+        assert_eq!(
+            value(&read(&embedded).unwrap(), "prompt"),
+            Some(stored.as_str())
+        );
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+
+    #[test]
+    fn simple_vp8_is_upgraded_to_vp8x() {
+        let embedded = embed(&simple_vp8_webp(320, 200), &provenance("lossy")).unwrap();
+        let header = vp8x_header(&embedded);
+        assert_eq!(header.len(), 10);
+        assert_eq!(header[0], 0x04);
+        assert_eq!(canvas_size(&header), (320, 200));
+        assert!(
+            webp_chunks(&embedded)
+                .iter()
+                .any(|(fourcc, _)| fourcc == b"VP8 ")
+        );
+        assert_eq!(value(&read(&embedded).unwrap(), "prompt"), Some("lossy"));
+
+        let riff_size = u32::from_le_bytes(embedded[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size + 8, embedded.len());
+    }
+
+    #[test]
+    fn zero_sized_vp8_is_rejected() {
+        for (width, height) in [(0, 200), (320, 0), (0, 0)] {
+            let error = embed(&simple_vp8_webp(width, height), &provenance("empty")).unwrap_err();
+            assert_eq!(error.to_string(), tr!("provenance.webp_size"));
+        }
+    }
+
+    #[test]
+    fn simple_vp8l_is_upgraded_to_vp8x() {
+        let webp = encode(
+            DynamicImage::ImageRgba8(RgbaImage::new(7, 5)),
+            EncodeFormat::WebP,
+        );
+        assert_eq!(&webp_chunks(&webp)[0].0, b"VP8L");
+        let embedded = embed(&webp, &provenance("lossless")).unwrap();
+        let header = vp8x_header(&embedded);
+        assert_eq!(header[0] & 0x04, 0x04);
+        assert_eq!(canvas_size(&header), (7, 5));
+        let decoded = image::load_from_memory(&embedded).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (7, 5));
+    }
+
+    #[test]
+    fn truncated_vp8x_header_is_an_error() {
+        let mut body = b"WEBP".to_vec();
+        body.extend(webp_chunk(b"VP8X", &[0, 0, 0, 0]));
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        assert!(embed(&bytes, &provenance("x")).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::i18n::tr;
+use anyhow::{Result, anyhow};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+
+/// 输入图像在编辑请求中的角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageRole {
+    /// 要编辑的主体图像
+    Subject,
+    /// 只借鉴画风（色彩、光照、材质、笔触），不借鉴内容
+    StyleReference,
+    /// 遮罩：白色区域为要编辑的部分，黑色区域保持不变
+    Mask,
+    /// 角色参考：保持人物或角色的外貌、服装一致
+    CharacterReference,
+}
+
+impl ImageRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageRole::Subject => "subject",
+            ImageRole::StyleReference => "style_reference",
+            ImageRole::Mask => "mask",
+            ImageRole::CharacterReference => "character_reference",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            ImageRole::Subject => {
+                "the SUBJECT image to edit. Keep its content and composition unless the \
+                 instruction says otherwise."
+            }
+            ImageRole::StyleReference => {
+                "a STYLE REFERENCE. Use only its visual style (color palette, lighting, medium, \
+                 brushwork, texture); do not copy its objects, people or layout."
+            }
+            ImageRole::Mask => {
+                "a MASK for the subject image. White areas mark the region to edit; black areas \
+                 must stay unchanged. Do not draw the mask itself in the output."
+            }
+            ImageRole::CharacterReference => {
+                "a CHARACTER REFERENCE. Keep this character's identity, face, hairstyle and \
+                 outfit consistent in the output."
+            }
+        }
+    }
+}
+
+/// 放在第 `index`（从 1 开始）张图像之前的标注文本
+pub fn image_label(index: usize, role: Option<ImageRole>) -> String {
+    match role {
+        Some(role) => format!("Image {} is {}", index, role.description()),
+        None => format!("Image {}:", index),
+    }
+}
+
+/// 检查角色列表与图像一一对应
+pub fn validate_roles(roles: &[Option<ImageRole>], image_count: usize) -> Result<()> {
+    if roles.len() != image_count {
+        return Err(anyhow!(tr!(
+            "roles.count_mismatch",
+            roles = roles.len(),
+            images = image_count
+        )));
+    }
+    let count = |target: ImageRole| roles.iter().filter(|role| **role == Some(target)).count();
+    if count(ImageRole::Subject) > 1 {
+        return Err(anyhow!(tr!("roles.multiple_subjects")));
+    }
+    if count(ImageRole::Mask) > 1 {
+        return Err(anyhow!(tr!("roles.multiple_masks")));
+    }
+    Ok(())
+}
+
+/// 风格迁移发送给模型的指令，`hint` 为用户的补充要求
+pub fn style_transfer_instruction(style_count: usize, hint: Option<&str>) -> String {
+    let references = if style_count > 1 {
+        "the style reference images"
+    } else {
+        "the style reference image"
+    };
+    let mut instruction = format!(
+        "Redraw the subject image in the visual style of {references}: match the color \
+         palette, lighting, medium, brushwork and texture. Keep the subject image's composition, \
+         content, poses and identities unchanged, and do not copy any objects or people from \
+         {references}. Return only the restyled image."
+    );
+    if let Some(hint) = hint.map(str::trim).filter(|hint| !hint.is_empty()) {
+        instruction.push_str(&format!(" Additional instructions: {}", hint));
+    }
+    instruction
+}
@@ -0,0 +1,366 @@
+use crate::{
+    errors::{ErrorDetails, ErrorKind, ToolError},
+    i18n::tr,
+};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// 表示内容被安全策略拦截的结束原因（OpenAI 规范化的 `content_filter` 和 Gemini 原生的结束原因）
+const BLOCKED_REASONS: &[&str] = &[
+    "CONTENT_FILTER",
+    "SAFETY",
+    "PROHIBITED_CONTENT",
+    "BLOCKLIST",
+    "SPII",
+    "RECITATION",
+    "IMAGE_SAFETY",
+    "IMAGE_PROHIBITED_CONTENT",
+    "IMAGE_RECITATION",
+];
+
+/// 明确违规、改写提示词也不应绕过的拦截原因
+const HARD_BLOCK_REASONS: &[&str] = &[
+    "PROHIBITED_CONTENT",
+    "BLOCKLIST",
+    "SPII",
+    "IMAGE_PROHIBITED_CONTENT",
+];
+
+/// 表示输出因长度限制被截断的结束原因
+const TRUNCATED_REASONS: &[&str] = &["LENGTH", "MAX_TOKENS"];
+
+/// 上游响应的结束状态：结束原因、提示词拦截原因和被标记的安全类别
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct FinishStatus {
+    /// 规范化的结束原因，如 `stop`、`length`、`content_filter`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    /// 模型提供方原始的结束原因，如 Gemini 的 `STOP`、`IMAGE_SAFETY`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub native_finish_reason: Option<String>,
+    /// 提示词被拦截的原因（Gemini `promptFeedback.blockReason`）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_reason: Option<String>,
+    /// 被标记的安全类别：Gemini 评为中等或高风险、或直接拦截的类别，以及 OpenRouter 审核给出的原因
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flagged: Vec<SafetyFlag>,
+}
+
+/// 一个被标记的安全类别
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SafetyFlag {
+    /// 类别，如 `HARM_CATEGORY_DANGEROUS_CONTENT` 或 OpenRouter 审核的 `violence`
+    pub category: String,
+    /// 风险概率：`NEGLIGIBLE`、`LOW`、`MEDIUM`、`HIGH`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probability: Option<String>,
+}
+
+impl FinishStatus {
+    /// 从 chat/completions 响应、Gemini 原生响应或 OpenRouter 错误体中读取结束状态
+    pub fn from_response(response: &Value) -> Self {
+        let choice = response.pointer("/choices/0");
+        let candidate = response.pointer("/candidates/0");
+        let text = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+
+        let mut status = Self {
+            finish_reason: text(choice.and_then(|c| c.get("finish_reason"))),
+            native_finish_reason: text(choice.and_then(|c| c.get("native_finish_reason")))
+                .or_else(|| text(candidate.and_then(|c| c.get("finishReason")))),
+            block_reason: text(response.pointer("/promptFeedback/blockReason")),
+            flagged: Vec::new(),
+        };
+        for ratings in [
+            candidate.and_then(|c| c.get("safetyRatings")),
+            response.pointer("/promptFeedback/safetyRatings"),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_array)
+        {
+            status
+                .flagged
+                .extend(ratings.iter().filter_map(flagged_rating));
+        }
+        // OpenRouter 审核拦截的错误体: {"error": {"metadata": {"reasons": [...]}}}
+        if let Some(reasons) = response
+            .pointer("/error/metadata/reasons")
+            .and_then(Value::as_array)
+        {
+            status
+                .flagged
+                .extend(
+                    reasons
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|reason| SafetyFlag {
+                            category: reason.to_string(),
+                            probability: None,
+                        }),
+                );
+        }
+        status
+    }
+
+    /// 没有读到任何状态信息
+    pub fn is_empty(&self) -> bool {
+        self.finish_reason.is_none()
+            && self.native_finish_reason.is_none()
+            && self.block_reason.is_none()
+            && self.flagged.is_empty()
+    }
+
+    /// 提示词或输出被安全策略拦截
+    pub fn is_blocked(&self) -> bool {
+        self.block_reason.is_some()
+            || !self.flagged.is_empty()
+            || self
+                .reasons()
+                .any(|reason| BLOCKED_REASONS.contains(&reason.as_str()))
+    }
+
+    /// 输出因长度限制被截断
+    pub fn is_truncated(&self) -> bool {
+        self.reasons()
+            .any(|reason| TRUNCATED_REASONS.contains(&reason.as_str()))
+    }
+
+    /// 拦截看起来是误判：没有明确违规的原因，也没有任何类别被标记为中等以上风险
+    pub fn likely_false_positive(&self) -> bool {
+        self.is_blocked()
+            && self.flagged.is_empty()
+            && !self
+                .reasons()
+                .any(|reason| HARD_BLOCK_REASONS.contains(&reason.as_str()))
+    }
+
+    /// 给人阅读的原因：优先使用属于 `known` 的原因，其次使用最具体的原始原因
+    ///
+    /// 上游的规范化原因和原始原因可能不一致（如 `content_filter` 搭配 `STOP`），不能只看其中一个
+    fn reason_label(&self, known: &[&str]) -> String {
+        let reasons = [
+            &self.block_reason,
+            &self.native_finish_reason,
+            &self.finish_reason,
+        ];
+        reasons
+            .iter()
+            .copied()
+            .flatten()
+            .find(|reason| known.contains(&reason.to_uppercase().as_str()))
+            .or_else(|| reasons.into_iter().flatten().next())
+            .cloned()
+            .unwrap_or_else(|| tr!("safety.unknown_reason"))
+    }
+
+    /// 模型没有返回图像时的错误：被拦截、输出被截断或模型只回复了文本
+    pub fn no_image_error(&self, text: &str) -> ToolError {
+        let error = if self.is_blocked() {
+            let categories = self
+                .flagged
+                .iter()
+                .map(|flag| flag.category.as_str())
+                .collect::<Vec<_>>();
+            let message = if categories.is_empty() {
+                tr!(
+                    "safety.blocked",
+                    reason = self.reason_label(BLOCKED_REASONS),
+                    text = text
+                )
+            } else {
+                tr!(
+                    "safety.blocked_categories",
+                    reason = self.reason_label(BLOCKED_REASONS),
+                    categories = categories.join(", "),
+                    text = text
+                )
+            };
+            ToolError::policy_blocked(message)
+        } else if self.is_truncated() {
+            ToolError::no_image(tr!(
+                "safety.truncated",
+                reason = self.reason_label(TRUNCATED_REASONS)
+            ))
+        } else {
+            ToolError::no_image(tr!(
+                "safety.no_image",
+                reason = self.reason_label(&[]),
+                text = text
+            ))
+        };
+        error.with_finish(self.clone())
+    }
+
+    /// 大写的结束原因和拦截原因，便于与常量比较
+    fn reasons(&self) -> impl Iterator<Item = String> + '_ {
+        [
+            &self.finish_reason,
+            &self.native_finish_reason,
+            &self.block_reason,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|reason| reason.to_uppercase())
+    }
+}
+
+/// 改写提示词后重试可能成功：请求被安全策略拦截，且拦截看起来是误判
+pub fn rephrase_may_help(error: &ErrorDetails) -> bool {
+    error.kind == ErrorKind::PolicyBlocked
+        && error
+            .finish
+            .as_ref()
+            .is_some_and(FinishStatus::likely_false_positive)
+}
+
+/// Gemini 的一条安全评级，被拦截或风险为中等以上时视为被标记
+fn flagged_rating(rating: &Value) -> Option<SafetyFlag> {
+    let category = rating.get("category").and_then(Value::as_str)?;
+    let probability = rating.get("probability").and_then(Value::as_str);
+    let blocked = rating
+        .get("blocked")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    (blocked || matches!(probability, Some("MEDIUM" | "HIGH"))).then(|| SafetyFlag {
+        category: category.to_string(),
+        probability: probability.map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors;
+    use rmcp::ErrorData as McpError;
+    use serde_json::json;
+
+    fn chat_response(finish_reason: &str, native_finish_reason: &str) -> Value {
+        json!({
+            "choices": [{
+                "finish_reason": finish_reason,
+                "native_finish_reason": native_finish_reason,
+                "message": {"role": "assistant", "content": "I can't draw that."}
+            }]
+        })
+    }
+
+    /// 经过 MCP 错误往返后的错误详情，与 generation 中判断是否改写重试时看到的一致
+    fn round_trip(error: ToolError) -> ErrorDetails {
+        errors::details_of(&McpError::from(error))
+    }
+
+    #[test]
+    fn finish_reasons_are_classified() {
+        let blocked = FinishStatus::from_response(&chat_response("content_filter", "STOP"));
+        assert!(blocked.is_blocked());
+        assert!(!blocked.is_truncated());
+
+        let image_safety = FinishStatus::from_response(&chat_response("stop", "IMAGE_SAFETY"));
+        assert!(image_safety.is_blocked());
+
+        let truncated = FinishStatus::from_response(&chat_response("length", "MAX_TOKENS"));
+        assert!(!truncated.is_blocked());
+        assert!(truncated.is_truncated());
+
+        let stopped = FinishStatus::from_response(&chat_response("stop", "STOP"));
+        assert!(!stopped.is_blocked());
+        assert!(!stopped.is_truncated());
+
+        let prompt_blocked =
+            FinishStatus::from_response(&json!({"promptFeedback": {"blockReason": "SAFETY"}}));
+        assert!(prompt_blocked.is_blocked());
+        assert!(FinishStatus::from_response(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn no_image_errors_follow_the_finish_status() {
+        let blocked = FinishStatus::from_response(&chat_response("content_filter", "STOP"));
+        let error = blocked.no_image_error("text");
+        assert_eq!(error.kind, ErrorKind::PolicyBlocked);
+        // 规范化原因与原始原因不一致时，使用属于拦截原因的那一个
+        assert!(error.message.contains("content_filter"));
+        assert_eq!(error.finish.as_ref(), Some(&blocked));
+
+        let truncated = FinishStatus::from_response(&chat_response("length", "MAX_TOKENS"));
+        assert_eq!(truncated.no_image_error("").kind, ErrorKind::NoImage);
+
+        let text_only = FinishStatus::from_response(&chat_response("stop", "STOP"));
+        let error = text_only.no_image_error("Here is a description");
+        assert_eq!(error.kind, ErrorKind::NoImage);
+        assert!(error.message.contains("Here is a description"));
+    }
+
+    #[test]
+    fn false_positives_exclude_hard_blocks_and_flagged_categories() {
+        let soft = FinishStatus::from_response(&chat_response("stop", "IMAGE_SAFETY"));
+        assert!(soft.likely_false_positive());
+
+        let prohibited = FinishStatus::from_response(&chat_response("stop", "PROHIBITED_CONTENT"));
+        assert!(prohibited.is_blocked());
+        assert!(!prohibited.likely_false_positive());
+
+        let blocklist =
+            FinishStatus::from_response(&json!({"promptFeedback": {"blockReason": "BLOCKLIST"}}));
+        assert!(!blocklist.likely_false_positive());
+
+        let moderated = FinishStatus::from_response(&json!({
+            "error": {"code": 403, "metadata": {"reasons": ["violence"]}}
+        }));
+        assert!(moderated.is_blocked());
+        assert_eq!(moderated.flagged[0].category, "violence");
+        assert!(!moderated.likely_false_positive());
+
+        assert!(!FinishStatus::default().likely_false_positive());
+    }
+
+    #[test]
+    fn rephrasing_is_only_tried_for_likely_false_positives() {
+        let soft = FinishStatus::from_response(&chat_response("content_filter", "IMAGE_SAFETY"));
+        assert!(rephrase_may_help(&round_trip(soft.no_image_error(""))));
+
+        let hard =
+            FinishStatus::from_response(&chat_response("content_filter", "PROHIBITED_CONTENT"));
+        assert!(!rephrase_may_help(&round_trip(hard.no_image_error(""))));
+
+        // 没有结束状态的拦截（如上游 403）无法判断是否误判，不重试
+        assert!(!rephrase_may_help(&round_trip(ToolError::from_status(
+            403, "blocked"
+        ))));
+        let truncated = FinishStatus::from_response(&chat_response("length", "MAX_TOKENS"));
+        assert!(!rephrase_may_help(&round_trip(
+            truncated.no_image_error("")
+        )));
+    }
+
+    /// 模型只回复了文本、但某个类别被评为 MEDIUM 时按拦截处理：
+    /// 中等风险的评级说明模型是因为安全原因才没有出图，改写重试也不应绕过
+    #[test]
+    fn medium_rating_on_a_text_reply_is_policy_blocked() {
+        let response = json!({
+            "candidates": [{
+                "finishReason": "STOP",
+                "content": {"parts": [{"text": "I can't create that image."}]},
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"},
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "MEDIUM"}
+                ]
+            }]
+        });
+        let finish = FinishStatus::from_response(&response);
+        assert_eq!(
+            finish.flagged,
+            vec![SafetyFlag {
+                category: "HARM_CATEGORY_DANGEROUS_CONTENT".to_string(),
+                probability: Some("MEDIUM".to_string()),
+            }]
+        );
+        assert!(finish.is_blocked());
+        assert!(!finish.likely_false_positive());
+
+        let error = finish.no_image_error("I can't create that image.");
+        assert_eq!(error.kind, ErrorKind::PolicyBlocked);
+        assert!(error.message.contains("HARM_CATEGORY_DANGEROUS_CONTENT"));
+        assert!(!rephrase_may_help(&round_trip(error)));
+    }
+}
@@ -1,10 +1,23 @@
 use crate::config::OpenRouterConfig;
+use crate::errors;
+use crate::history::{self, HistoryStore};
+use crate::i18n::{self, tr};
+use crate::imaging;
+use crate::jobs::{self, JobManager};
+use crate::pricing::{self, Pricing};
+use crate::prompts::{self, PromptLibrary};
+use crate::session::SessionStore;
+use crate::usage::{self, UsageLedger};
 use anyhow::Result;
 use rmcp::{
-    ServerHandler,
-    handler::server::router::tool::ToolRouter,
-    model::{ServerCapabilities, ServerInfo},
-    tool_handler,
+    ErrorData as McpError, RoleServer, ServerHandler,
+    handler::server::{router::tool::ToolRouter, tool::ToolCallContext},
+    model::{
+        CallToolRequestParam, CallToolResult, GetPromptRequestParam, GetPromptResult,
+        ListPromptsResult, ListToolsResult, PaginatedRequestParam, PromptMessage,
+        PromptMessageRole, ServerCapabilities, ServerInfo,
+    },
+    service::RequestContext,
 };
 
 #[derive(Clone)]
@@ -12,7 +25,19 @@ pub struct OpenRouterServer {
     pub(crate) tool_router: ToolRouter<Self>,
     pub(crate) config: OpenRouterConfig,
     pub(crate) client: reqwest::Client,
+    /// 下载远程输入图像的客户端，不带 API 密钥请求头，避免把密钥发送给第三方站点
+    pub(crate) download_client: reqwest::Client,
     pub(crate) save_directory: std::sync::Arc<tokio::sync::RwLock<String>>,
+    pub(crate) history: Option<HistoryStore>,
+    pub(crate) sessions: SessionStore,
+    pub(crate) jobs: Option<JobManager>,
+    pub(crate) prompts: PromptLibrary,
+    pub(crate) pricing: Pricing,
+    pub(crate) usage: Option<UsageLedger>,
+    /// 当前 MCP 连接的会话 ID，用于按会话统计用量
+    pub(crate) session_id: String,
+    /// API 密钥的标识（哈希），用于按密钥统计用量
+    pub(crate) api_key_id: String,
 }
 
 impl OpenRouterServer {
@@ -21,23 +46,25 @@ impl OpenRouterServer {
         let client = reqwest::Client::builder()
             .default_headers(config.get_headers())
             .build()?;
+        let download_client = imaging::download_client(config.allow_private_downloads)?;
+        crate::store::init_encoding_cache(config.dedupe);
 
         let save_dir = if let Some(cmd_save_dir) = save_directory {
             let path = std::path::Path::new(&cmd_save_dir);
             if !path.is_absolute() {
-                return Err(anyhow::anyhow!(
-                    "命令行参数 --save-directory 必须是绝对路径，当前提供: {}",
-                    cmd_save_dir
-                ));
+                return Err(anyhow::anyhow!(tr!(
+                    "config.save_dir_arg_not_absolute",
+                    path = cmd_save_dir
+                )));
             }
             cmd_save_dir
         } else if let Ok(env_save_dir) = std::env::var("MCP_SAVE_DIRECTORY") {
             let path = std::path::Path::new(&env_save_dir);
             if !path.is_absolute() {
-                return Err(anyhow::anyhow!(
-                    "环境变量 MCP_SAVE_DIRECTORY 必须是绝对路径，当前设置: {}",
-                    env_save_dir
-                ));
+                return Err(anyhow::anyhow!(tr!(
+                    "config.save_dir_env_not_absolute",
+                    path = env_save_dir
+                )));
             }
             env_save_dir
         } else {
@@ -54,31 +81,194 @@ impl OpenRouterServer {
             std::fs::create_dir_all(path)?;
         }
         if !path.is_dir() {
-            return Err(anyhow::anyhow!(
-                "保存目录路径 '{}' 不是一个有效的目录",
-                save_dir
-            ));
+            return Err(anyhow::anyhow!(tr!(
+                "config.save_dir_not_directory",
+                path = save_dir
+            )));
         }
+        crate::image_utils::cleanup_stale_temp_files(path);
+
+        let history = if config.history_enabled {
+            let history_path = config
+                .history_db
+                .as_ref()
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| path.join(history::DEFAULT_HISTORY_FILE));
+            match HistoryStore::open(&history_path) {
+                Ok(store) => {
+                    tracing::info!("生成历史数据库: {}", store.path().display());
+                    Some(store)
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "无法打开生成历史数据库 {}，历史记录已禁用: {}",
+                        history_path.display(),
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let jobs_path = path.join(jobs::DEFAULT_JOBS_FILE);
+        let jobs = match JobManager::open(&jobs_path, config.job_workers) {
+            Ok(manager) => Some(manager),
+            Err(e) => {
+                tracing::warn!(
+                    "无法打开任务数据库 {}，后台任务已禁用: {}",
+                    jobs_path.display(),
+                    e
+                );
+                None
+            }
+        };
+
+        let prompts_dir = config
+            .prompts_dir
+            .as_ref()
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| path.join(prompts::DEFAULT_PROMPTS_DIR));
+        tracing::info!("提示词模板目录: {}", prompts_dir.display());
+        let prompts = PromptLibrary::new(prompts_dir);
+        tracing::info!("消息语言: {}", i18n::locale().as_str());
+
+        let price_table = match &config.price_table {
+            Some(table_path) => {
+                let table = pricing::load_price_table(std::path::Path::new(table_path))?;
+                tracing::info!("本地价格表: {}（{} 个模型）", table_path, table.len());
+                table
+            }
+            None => Default::default(),
+        };
+
+        let usage_path = path.join(usage::DEFAULT_USAGE_FILE);
+        let usage = match UsageLedger::open(&usage_path) {
+            Ok(ledger) => Some(ledger),
+            Err(e) => {
+                tracing::warn!(
+                    "无法打开用量数据库 {}，用量统计已禁用: {}",
+                    usage_path.display(),
+                    e
+                );
+                None
+            }
+        };
+        let api_key_id = usage::api_key_id(&config.api_key);
 
         Ok(Self {
             tool_router: Self::create_tool_router(),
             config,
             client,
+            download_client,
             save_directory: std::sync::Arc::new(tokio::sync::RwLock::new(save_dir)),
+            history,
+            sessions: SessionStore::default(),
+            jobs,
+            prompts,
+            pricing: Pricing::new(price_table),
+            usage,
+            session_id: uuid::Uuid::new_v4().to_string(),
+            api_key_id,
         })
     }
+
+    /// 为新的 MCP 连接创建处理器，共享所有状态，只有会话 ID 不同
+    pub fn new_session(&self) -> Self {
+        Self {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            ..self.clone()
+        }
+    }
 }
 
-#[tool_handler]
 impl ServerHandler for OpenRouterServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
-			instructions: Some("nano banana MCP - 提供 OpenRouter API 访问 google/gemini-2.5-flash-image模型。支持多种图像输入格式：URL、base64、本地文件路径。可用工具: generate_image, edit_image。模型和保存目录只能通过命令行参数或环境变量设置。".into()),
-			capabilities: ServerCapabilities::builder()
-				.enable_tools()
-				.enable_resources()
-				.build(),
-			..Default::default()
-		}
+            instructions: Some(tr!("server.instructions")),
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .enable_prompts()
+                .build(),
+            ..Default::default()
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult::with_all_items(self.tool_router.list_all()))
+    }
+
+    /// 执行工具并按错误类别区分工具错误（`isError`）和协议错误
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let tool = request.name.clone();
+        let context = ToolCallContext::new(self, request, context);
+        match self.tool_router.call(context).await {
+            Ok(result) => Ok(result),
+            Err(error) => errors::into_tool_result(&tool, error),
+        }
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        let library = self.prompts.clone();
+        let templates = tokio::task::spawn_blocking(move || library.load_all())
+            .await
+            .map_err(|e| McpError::internal_error(tr!("prompts.load_failed", error = e), None))?;
+        Ok(ListPromptsResult::with_all_items(
+            templates.iter().map(|t| t.to_prompt()).collect(),
+        ))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        let library = self.prompts.clone();
+        let name = request.name.clone();
+        let template = tokio::task::spawn_blocking(move || library.get(&name))
+            .await
+            .map_err(|e| McpError::internal_error(tr!("prompts.load_failed", error = e), None))?
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    tr!(
+                        "prompts.not_found",
+                        name = request.name,
+                        dir = self.prompts.dir().display()
+                    ),
+                    None,
+                )
+            })?;
+        let invalid = |e: anyhow::Error| {
+            McpError::invalid_params(
+                tr!("prompts.template_error", name = template.name, error = e),
+                None,
+            )
+        };
+        let prompt = template
+            .render(request.arguments.as_ref())
+            .map_err(invalid)?;
+        let arguments = template.generate_arguments(prompt).map_err(invalid)?;
+        let text = tr!(
+            "prompts.call_generate_image",
+            arguments = serde_json::to_string_pretty(&arguments).unwrap_or_default()
+        );
+        Ok(GetPromptResult {
+            description: template.description.clone(),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
     }
 }
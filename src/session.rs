@@ -0,0 +1,156 @@
+use crate::{errors::ToolError, i18n::tr};
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// 单个会话允许的最大轮数（一问一答为一轮）
+pub const MAX_SESSION_TURNS: usize = 20;
+/// 单个会话上下文（含图像数据）的最大字节数，超出时丢弃最早的轮次
+pub const MAX_SESSION_BYTES: usize = 32 * 1024 * 1024;
+/// 同时保留的最大会话数，超出时淘汰最久未使用的会话
+pub const MAX_SESSIONS: usize = 16;
+/// 会话空闲超过该时长后自动过期
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// 一轮对话：用户消息和模型回复
+#[derive(Debug, Clone)]
+pub struct SessionTurn {
+    pub user: Value,
+    pub assistant: Value,
+    bytes: usize,
+}
+
+impl SessionTurn {
+    pub fn new(user: Value, assistant: Value) -> Self {
+        let bytes = user.to_string().len() + assistant.to_string().len();
+        Self {
+            user,
+            assistant,
+            bytes,
+        }
+    }
+}
+
+/// 多轮编辑会话，保存发送给模型的完整消息历史
+#[derive(Debug)]
+pub struct EditSession {
+    pub id: String,
+    pub model: String,
+    pub turns: Vec<SessionTurn>,
+    /// 因超出大小限制被丢弃的早期轮数
+    pub dropped_turns: usize,
+    pub generation_ids: Vec<String>,
+    last_used: Instant,
+}
+
+impl EditSession {
+    fn new(id: String, model: &str) -> Self {
+        Self {
+            id,
+            model: model.to_string(),
+            turns: Vec::new(),
+            dropped_turns: 0,
+            generation_ids: Vec::new(),
+            last_used: Instant::now(),
+        }
+    }
+
+    /// 按顺序展开为 `messages` 数组中的上下文部分
+    pub fn context(&self) -> Vec<Value> {
+        self.turns
+            .iter()
+            .flat_map(|turn| [turn.user.clone(), turn.assistant.clone()])
+            .collect()
+    }
+
+    pub fn total_turns(&self) -> usize {
+        self.dropped_turns + self.turns.len()
+    }
+
+    /// 检查是否还能继续对话
+    pub fn ensure_capacity(&self) -> Result<()> {
+        if self.total_turns() >= MAX_SESSION_TURNS {
+            return Err(anyhow!(tr!(
+                "session.max_turns",
+                id = self.id,
+                max = MAX_SESSION_TURNS
+            )));
+        }
+        Ok(())
+    }
+
+    /// 追加一轮对话，超出大小限制时丢弃最早的轮次，返回本次丢弃的轮数
+    pub fn push_turn(&mut self, turn: SessionTurn, generation_id: &str) -> usize {
+        self.turns.push(turn);
+        self.generation_ids.push(generation_id.to_string());
+        self.last_used = Instant::now();
+
+        let mut dropped = 0;
+        while self.turns.len() > 1 && self.bytes() > MAX_SESSION_BYTES {
+            self.turns.remove(0);
+            dropped += 1;
+        }
+        self.dropped_turns += dropped;
+        dropped
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.turns.iter().map(|turn| turn.bytes).sum()
+    }
+}
+
+/// 进程内的编辑会话存储
+#[derive(Clone, Default)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Arc<Mutex<EditSession>>>>>,
+}
+
+impl SessionStore {
+    /// 创建新会话；会话数达到上限时淘汰最久未使用的空闲会话
+    pub async fn create(&self, model: &str) -> Result<Arc<Mutex<EditSession>>> {
+        let mut sessions = self.sessions.lock().await;
+        prune_expired(&mut sessions);
+
+        while sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .filter_map(|(id, session)| {
+                    session.try_lock().ok().map(|s| (id.clone(), s.last_used))
+                })
+                .min_by_key(|(_, last_used)| *last_used)
+                .map(|(id, _)| id)
+                .ok_or_else(|| anyhow!(tr!("session.too_many", max = MAX_SESSIONS)))?;
+            tracing::info!("会话数达到上限，淘汰会话: {}", oldest);
+            sessions.remove(&oldest);
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let session = Arc::new(Mutex::new(EditSession::new(id.clone(), model)));
+        sessions.insert(id, session.clone());
+        Ok(session)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Arc<Mutex<EditSession>>> {
+        let mut sessions = self.sessions.lock().await;
+        prune_expired(&mut sessions);
+        sessions
+            .get(id)
+            .cloned()
+            .ok_or_else(|| ToolError::input_not_found(tr!("session.not_found", id = id)).into())
+    }
+
+    pub async fn remove(&self, id: &str) -> Option<Arc<Mutex<EditSession>>> {
+        self.sessions.lock().await.remove(id)
+    }
+}
+
+/// 移除空闲超时的会话；正在处理请求的会话不会被移除
+fn prune_expired(sessions: &mut HashMap<String, Arc<Mutex<EditSession>>>) {
+    sessions.retain(|_, session| match session.try_lock() {
+        Ok(s) => s.last_used.elapsed() < SESSION_IDLE_TIMEOUT,
+        Err(_) => true,
+    });
+}
@@ -0,0 +1,59 @@
+use crate::i18n::tr;
+use anyhow::{Result, anyhow};
+use rusqlite::Connection;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// 生成历史、后台任务和用量记录共用的 SQLite 连接
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// 打开（必要时创建）数据库，建立表结构并执行尚未应用的迁移
+    ///
+    /// 迁移按顺序执行，已执行的数量记录在 `PRAGMA user_version` 中
+    pub fn open(path: &Path, schema: &str, migrations: &[&str]) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "busy_timeout", 5000)?;
+        conn.execute_batch(schema)?;
+        migrate(&conn, migrations)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// 在阻塞线程池中执行数据库操作，避免阻塞异步运行时
+    pub async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| anyhow!(tr!("sqlite.lock_poisoned")))?;
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+/// 执行尚未应用的结构迁移
+fn migrate(conn: &Connection, migrations: &[&str]) -> Result<()> {
+    let applied: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in migrations.iter().enumerate().skip(applied.max(0) as usize) {
+        conn.execute_batch(&format!(
+            "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+            migration,
+            index + 1
+        ))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use crate::{i18n::tr, image_utils};
+use anyhow::{Result, anyhow};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// 内容寻址存储在保存目录下的根目录名
+pub const STORE_DIR_NAME: &str = ".store";
+
+/// 输入编码缓存允许占用的最大内存（字节）
+const ENCODING_CACHE_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// 按 SHA-256 寻址的图像存储，位于 `<保存目录>/.store/sha256/<前两位>/<哈希>.<扩展名>`
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+/// 写入存储后的结果
+#[derive(Debug)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    /// 相同内容此前已存在于存储中
+    pub deduplicated: bool,
+}
+
+impl ContentStore {
+    pub fn new(save_directory: &Path) -> Self {
+        Self {
+            root: save_directory.join(STORE_DIR_NAME).join("sha256"),
+        }
+    }
+
+    fn object_path(&self, sha256: &str, extension: &str) -> PathBuf {
+        self.root
+            .join(&sha256[..2])
+            .join(format!("{}.{}", sha256, extension))
+    }
+
+    /// 把内容放入存储（若尚不存在），返回存储对象路径以及是否为重复内容
+    pub fn put(&self, bytes: &[u8], sha256: &str, extension: &str) -> Result<(PathBuf, bool)> {
+        let object_path = self.object_path(sha256, extension);
+        if object_path.is_file() {
+            return Ok((object_path, true));
+        }
+        let parent = object_path
+            .parent()
+            .ok_or_else(|| anyhow!(tr!("store.invalid_path")))?;
+        fs::create_dir_all(parent)?;
+        // 并发写入相同内容时，后完成的重命名覆盖的是完全相同的字节，结果一致
+        image_utils::write_file_atomic(&object_path, bytes)?;
+        Ok((object_path, false))
+    }
+
+    /// 在目标目录中创建一个不与现有文件冲突的输出文件
+    ///
+    /// 内容先写入存储，输出文件优先以硬链接指向存储对象；
+    /// 文件系统不支持硬链接时退化为写入独立副本
+    pub fn save(
+        &self,
+        bytes: &[u8],
+        sha256: &str,
+        directory: &Path,
+        base_name: &str,
+        extension: &str,
+    ) -> Result<StoredFile> {
+        let (object_path, deduplicated) = self.put(bytes, sha256, extension)?;
+        fs::create_dir_all(directory)?;
+
+        for counter in 1..=image_utils::MAX_SAVE_ATTEMPTS {
+            let candidate = directory.join(image_utils::incremental_filename(
+                base_name, extension, counter,
+            ));
+            if candidate.exists() {
+                continue;
+            }
+            match fs::hard_link(&object_path, &candidate) {
+                Ok(()) => {
+                    return Ok(StoredFile {
+                        path: candidate,
+                        deduplicated,
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    tracing::debug!("硬链接失败，改为写入副本: {}", e);
+                    let path =
+                        image_utils::write_new_file_atomic(directory, base_name, extension, bytes)?;
+                    return Ok(StoredFile { path, deduplicated });
+                }
+            }
+        }
+
+        Err(anyhow!(tr!(
+            "save.no_available_name",
+            name = base_name,
+            extension = extension,
+            attempts = image_utils::MAX_SAVE_ATTEMPTS
+        )))
+    }
+}
+
+/// 输入图像 base64 编码的缓存键：规范化路径 + 修改时间 + 文件大小
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EncodingKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// 进程内的输入编码缓存，避免重复读取和编码同一个大文件
+///
+/// 按最近使用顺序淘汰：命中的条目移到队尾，超出容量时从队首移除
+struct EncodingCache {
+    entries: HashMap<EncodingKey, Arc<str>>,
+    /// 从最久未使用到最近使用
+    order: VecDeque<EncodingKey>,
+    total_bytes: usize,
+    max_bytes: usize,
+}
+
+impl EncodingCache {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            total_bytes: 0,
+            max_bytes,
+        }
+    }
+
+    fn get(&mut self, key: &EncodingKey) -> Option<Arc<str>> {
+        let hit = self.entries.get(key)?.clone();
+        self.touch(key);
+        Some(hit)
+    }
+
+    fn insert(&mut self, key: EncodingKey, data_url: Arc<str>) {
+        if data_url.len() > self.max_bytes {
+            return;
+        }
+        // 同一路径的旧版本（mtime 变化）不会再命中，一并移除
+        let stale: Vec<EncodingKey> = self
+            .order
+            .iter()
+            .filter(|k| k.path == key.path)
+            .cloned()
+            .collect();
+        for stale_key in stale {
+            self.remove(&stale_key);
+        }
+        while self.total_bytes + data_url.len() > self.max_bytes {
+            let Some(least_recent) = self.order.front().cloned() else {
+                break;
+            };
+            self.remove(&least_recent);
+        }
+        self.total_bytes += data_url.len();
+        self.order.push_back(key.clone());
+        self.entries.insert(key, data_url);
+    }
+
+    /// 把条目移到最近使用的位置
+    fn touch(&mut self, key: &EncodingKey) {
+        if let Some(position) = self.order.iter().position(|k| k == key)
+            && let Some(key) = self.order.remove(position)
+        {
+            self.order.push_back(key);
+        }
+    }
+
+    fn remove(&mut self, key: &EncodingKey) {
+        if let Some(removed) = self.entries.remove(key) {
+            self.total_bytes -= removed.len();
+        }
+        self.order.retain(|k| k != key);
+    }
+}
+
+static ENCODING_CACHE: OnceLock<Option<Mutex<EncodingCache>>> = OnceLock::new();
+
+/// 设置是否缓存输入编码，只在启动时调用一次；与内容寻址存储一起由 `--dedupe` 开启
+pub fn init_encoding_cache(enabled: bool) {
+    let _ = ENCODING_CACHE
+        .set(enabled.then(|| Mutex::new(EncodingCache::new(ENCODING_CACHE_MAX_BYTES))));
+}
+
+fn encoding_cache() -> Option<&'static Mutex<EncodingCache>> {
+    ENCODING_CACHE.get()?.as_ref()
+}
+
+/// 读取本地文件并编码为 data URL；开启缓存时按路径和修改时间缓存结果
+pub fn cached_data_url(path: &Path, encode: impl FnOnce() -> Result<String>) -> Result<Arc<str>> {
+    let Some(cache) = encoding_cache() else {
+        return Ok(encode()?.into());
+    };
+    let metadata = fs::metadata(path)?;
+    let key = EncodingKey {
+        path: fs::canonicalize(path)?,
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    };
+
+    if let Ok(mut cache) = cache.lock()
+        && let Some(hit) = cache.get(&key)
+    {
+        return Ok(hit);
+    }
+
+    let data_url: Arc<str> = encode()?.into();
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(key, data_url.clone());
+    }
+    Ok(data_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(path: &str, len: u64) -> EncodingKey {
+        EncodingKey {
+            path: PathBuf::from(path),
+            modified: None,
+            len,
+        }
+    }
+
+    #[test]
+    fn encoding_cache_evicts_least_recently_used() {
+        let mut cache = EncodingCache::new(10);
+        cache.insert(key("/a.png", 1), "aaaa".into());
+        cache.insert(key("/b.png", 1), "bbbb".into());
+        // 命中 a 后，容量不足时应淘汰 b 而不是更早插入的 a
+        assert_eq!(cache.get(&key("/a.png", 1)).as_deref(), Some("aaaa"));
+        cache.insert(key("/c.png", 1), "cccc".into());
+
+        assert!(cache.get(&key("/b.png", 1)).is_none());
+        assert_eq!(cache.get(&key("/a.png", 1)).as_deref(), Some("aaaa"));
+        assert_eq!(cache.get(&key("/c.png", 1)).as_deref(), Some("cccc"));
+        assert_eq!(cache.total_bytes, 8);
+    }
+
+    #[test]
+    fn encoding_cache_replaces_old_versions_and_skips_oversized_entries() {
+        let mut cache = EncodingCache::new(10);
+        cache.insert(key("/a.png", 1), "old".into());
+        cache.insert(key("/a.png", 2), "new".into());
+        assert!(cache.get(&key("/a.png", 1)).is_none());
+        assert_eq!(cache.get(&key("/a.png", 2)).as_deref(), Some("new"));
+
+        cache.insert(key("/big.png", 1), "x".repeat(11).into());
+        assert!(cache.get(&key("/big.png", 1)).is_none());
+        assert_eq!(cache.total_bytes, 3);
+    }
+
+    #[test]
+    fn repeated_content_is_hard_linked_to_one_object() {
+        let root = tempfile::tempdir().unwrap();
+        let store = ContentStore::new(root.path());
+        let sha256 = "ab".repeat(32);
+        let output_dir = root.path().join("2025-01-31");
+
+        let first = store
+            .save(b"png bytes", &sha256, &output_dir, "cat", "png")
+            .unwrap();
+        let second = store
+            .save(b"png bytes", &sha256, &output_dir, "cat", "png")
+            .unwrap();
+        assert!(!first.deduplicated);
+        assert!(second.deduplicated);
+        assert_eq!(first.path, output_dir.join("cat.png"));
+        assert_eq!(second.path, output_dir.join("cat_2.png"));
+
+        let object = store.object_path(&sha256, "png");
+        assert_eq!(fs::read(&object).unwrap(), b"png bytes");
+        assert_eq!(fs::read(&second.path).unwrap(), b"png bytes");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = fs::metadata(&object).unwrap().ino();
+            assert_eq!(fs::metadata(&first.path).unwrap().ino(), inode);
+            assert_eq!(fs::metadata(&second.path).unwrap().ino(), inode);
+            assert_eq!(fs::metadata(&object).unwrap().nlink(), 3);
+        }
+    }
+}
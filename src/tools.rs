@@ -1,320 +1,2580 @@
-use crate::{image_utils, server::OpenRouterServer};
+use crate::{
+    background::{self, ChromaKeyOptions, Cutout, KeyColor},
+    batch::{self, BatchProgress},
+    compose::{self, ComposeLayout, ComposeOptions},
+    describe::{self, DescribeMode},
+    errors::{self, ErrorKind, ToolError},
+    extend::{ExtendPadding, ExtendPlan},
+    generation::{self, ImageOutcome, ImageRequest},
+    history::{self, HistoryQuery, HistoryRecord, HistoryStore},
+    i18n::{self, tr},
+    image_utils::{self, ImageInfo},
+    imaging::{self, OutputFormat},
+    jobs::{self, JobManager, JobRecord},
+    output::{self, ItemOutput, OutputImage, ToolOutput},
+    pricing::CostSource,
+    provenance,
+    roles::{self, ImageRole},
+    safety::FinishStatus,
+    server::OpenRouterServer,
+    session::{self, EditSession, SessionTurn},
+    transform::{self, TransformOp},
+    upscale::{self, UpscaleBackend},
+    usage::{self, UsageQuery, UsageTotals},
+    variations::{self, VariationStrength},
+};
 use anyhow::Result;
 use rmcp::{
-    ErrorData as McpError,
+    ErrorData as McpError, RoleServer,
     handler::server::wrapper::Parameters,
     model::{CallToolResult, Content},
-    schemars, tool, tool_router,
+    schemars,
+    service::RequestContext,
+    tool, tool_router,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_json::json;
+use std::collections::BTreeMap;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Deserialize, schemars::JsonSchema)]
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct GenerateImageArgs {
     #[schemars(example = &"一只可爱的小猫穿着宇航服在月球上行走，科幻风格")]
     pub prompt: String,
+    /// 随机种子（可选），相同种子便于复现结果
+    pub seed: Option<u64>,
+    /// 先用文本模型改写提示词，再用改写结果生成图像
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enhance: Option<bool>,
+    /// 生成纯色背景后在本地抠图，额外保存带透明通道的 PNG
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparent_background: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct EditImageArgs {
+    #[schemars(example = &"请将这张图片编辑成一张科幻风格的海报")]
+    pub instruction: String,
+    #[schemars(example = &"https://example.com/image.jpg")]
+    #[schemars(example = &"C:\\Images\\photo.png")]
+    #[schemars(example = &"data:image/jpeg;base64,/9j/4AAQ...")]
+    pub images: Vec<String>,
+    /// 随机种子（可选），相同种子便于复现结果
+    pub seed: Option<u64>,
+    /// 每张图像的角色（可选），与 images 一一对应：subject、style_reference、mask、character_reference，不需要标注的图像用 null 占位
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<Option<ImageRole>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct StyleTransferArgs {
+    /// 要重绘的主体图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"photo.jpg")]
+    pub image: String,
+    /// 风格参考图像，至少一张
+    #[schemars(example = &"starry_night.jpg")]
+    pub style_images: Vec<String>,
+    /// 补充要求（可选），如 "保留人物的表情"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// 随机种子（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReadImageMetadataArgs {
+    #[schemars(example = &"generated_image.png")]
+    #[schemars(example = &"/home/user/images/generated_image.png")]
+    pub image: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SearchHistoryArgs {
+    /// 在提示词和模型响应中搜索的文本
+    #[schemars(example = &"宇航服")]
+    pub query: Option<String>,
+    /// 起始时间，YYYY-MM-DD 或 RFC 3339
+    #[schemars(example = &"2025-01-01")]
+    pub since: Option<String>,
+    /// 结束时间，YYYY-MM-DD 或 RFC 3339
+    #[schemars(example = &"2025-01-31")]
+    pub until: Option<String>,
+    /// 模型名称（模糊匹配）
+    pub model: Option<String>,
+    /// 状态：success 或 error
+    #[schemars(example = &"success")]
+    pub status: Option<String>,
+    /// 工具名称，如 generate_image
+    pub tool: Option<String>,
+    /// 最多返回的记录数（默认 20，最大 200）
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct GetGenerationArgs {
+    /// 生成 ID
+    pub id: String,
 }
 
-#[derive(Debug, Deserialize, schemars::JsonSchema)]
-pub struct EditImageArgs {
-    #[schemars(example = &"请将这张图片编辑成一张科幻风格的海报")]
-    pub instruction: String,
-    #[schemars(example = &"https://example.com/image.jpg")]
-    #[schemars(example = &"C:\\Images\\photo.png")]
-    #[schemars(example = &"data:image/jpeg;base64,/9j/4AAQ...")]
-    pub images: Vec<String>,
-}
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UsageReportArgs {
+    /// 起始时间，YYYY-MM-DD 或 RFC 3339
+    #[schemars(example = &"2025-01-01")]
+    pub since: Option<String>,
+    /// 结束时间，YYYY-MM-DD 或 RFC 3339
+    #[schemars(example = &"2025-01-31")]
+    pub until: Option<String>,
+    /// 只统计当前会话（默认 false）
+    pub current_session: Option<bool>,
+    /// 每个分组最多返回的行数（默认 30，最大 366）
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct RegenerateArgs {
+    /// 要重放的生成 ID
+    pub id: String,
+    /// 覆盖原提示词 / 编辑指令
+    pub prompt: Option<String>,
+    /// 覆盖原输入图像
+    pub images: Option<Vec<String>>,
+    /// 覆盖原随机种子
+    pub seed: Option<u64>,
+    /// 覆盖原模型
+    #[schemars(example = &"google/gemini-2.5-flash-image-preview")]
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct StartEditSessionArgs {
+    #[schemars(example = &"把背景换成海边日落")]
+    pub instruction: String,
+    /// 初始图像（URL / base64 / 本地路径），可以为空，从文本开始创作
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// 随机种子（可选），相同种子便于复现结果
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ContinueEditSessionArgs {
+    /// start_edit_session 返回的会话 ID
+    pub session_id: String,
+    #[schemars(example = &"再把天空调得更暖一些")]
+    pub instruction: String,
+    /// 本轮额外附加的图像（可选）
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// 随机种子（可选）
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct EndSessionArgs {
+    /// 要结束的会话 ID
+    pub session_id: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct BatchGenerateArgs {
+    /// 提示词列表，与 template 二选一
+    pub prompts: Option<Vec<String>>,
+    /// 提示词模板，用 {变量名} 引用 variables 中的变量
+    #[schemars(example = &"一个{color}的{product}，白色背景，产品摄影")]
+    pub template: Option<String>,
+    /// 变量矩阵，模板按所有取值组合展开
+    pub variables: Option<BTreeMap<String, Vec<String>>>,
+    /// 随机种子（可选），应用于每一条
+    pub seed: Option<u64>,
+    /// 本次批量的并发数（可选，不超过服务器配置的上限）
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CreateVariationsArgs {
+    /// 原图（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"product.png")]
+    pub image: String,
+    /// 变体数量（默认 4，最多 8）
+    pub count: Option<u32>,
+    /// 变化程度：subtle、medium（默认）或 strong
+    pub strength: Option<VariationStrength>,
+    /// 变化方向提示（可选）
+    #[schemars(example = &"尝试不同的配色")]
+    pub hint: Option<String>,
+    /// 随机种子（可选），第 N 个变体使用 seed + N - 1
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct DescribeImageArgs {
+    /// 图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"generated_image.png")]
+    pub image: String,
+    /// 输出模式：caption（默认）、detailed、alt_text 或 json
+    pub mode: Option<DescribeMode>,
+    /// alt_text 模式的最大字符数（默认 125）
+    pub max_length: Option<usize>,
+    /// 输出语言（可选），如 "简体中文" 或 "English"
+    #[schemars(example = &"简体中文")]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct EnhancePromptArgs {
+    #[schemars(example = &"月球上的猫")]
+    pub prompt: String,
+    /// 期望的风格（可选）
+    #[schemars(example = &"赛博朋克插画")]
+    pub style: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ComposeImagesArgs {
+    /// 要拼接的图像（URL / base64 / 本地路径 / 保存目录中的文件名），按顺序排列
+    #[schemars(example = &["before.png", "after.png"])]
+    pub images: Vec<String>,
+    /// 布局：grid（默认）、side_by_side 或 stacked
+    pub layout: Option<ComposeLayout>,
+    /// 网格列数（仅 grid，默认按图像数量自动计算）
+    pub columns: Option<u32>,
+    /// 网格行数（仅 grid，默认按图像数量自动计算）
+    pub rows: Option<u32>,
+    /// 图像之间及四周的间距（像素，默认 16）
+    pub gutter: Option<u32>,
+    /// 背景色：#RRGGBB、#RRGGBBAA 或 transparent（默认 #FFFFFF）
+    #[schemars(example = &"#202020")]
+    pub background: Option<String>,
+    /// 每张图像下方的标题（可选），按顺序对应，空字符串表示该图没有标题
+    pub captions: Option<Vec<String>>,
+    /// 标题字号（像素，默认 32）
+    pub caption_size: Option<u32>,
+    /// 输出文件名（不含扩展名，默认 composite）
+    #[schemars(example = &"before_after")]
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct TransformImageArgs {
+    /// 输入图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"generated_image.png")]
+    pub image: String,
+    /// 按顺序执行的操作列表，例如 [{"op": "crop", "aspect": "16:9"}, {"op": "resize", "width": 1280}]
+    pub operations: Vec<TransformOp>,
+    /// 输出格式：png、jpeg 或 webp（默认与输入相同，无法确定时为 png）
+    pub format: Option<OutputFormat>,
+    /// JPEG 质量 1-100（默认 90）
+    pub quality: Option<u8>,
+    /// 输出文件名（不含扩展名，默认 {原文件名}_transformed）
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RemoveBackgroundArgs {
+    /// 输入图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"product.png")]
+    pub image: String,
+    /// 让模型绘制的背景色：green（默认）、magenta 或 blue，应选择主体中没有的颜色
+    pub key_color: Option<KeyColor>,
+    /// 颜色容差 0-200（默认 60），背景残留时调大，主体被误删时调小
+    pub tolerance: Option<f32>,
+    /// 边缘羽化半径 0-20 像素（默认 1）
+    pub feather: Option<f32>,
+    /// 随机种子（可选）
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UpscaleImageArgs {
+    /// 输入图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"generated_image.png")]
+    pub image: String,
+    /// 放大倍数：2（默认）或 4
+    pub factor: Option<u32>,
+    /// 放大后端：local 或 model（默认使用 --upscale-backend 的配置）
+    pub backend: Option<UpscaleBackend>,
+    /// local 后端是否在重采样后锐化（默认 true）
+    pub sharpen: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ExtendImageArgs {
+    /// 输入图像（URL / base64 / 本地路径 / 保存目录中的文件名）
+    #[schemars(example = &"portrait.png")]
+    pub image: String,
+    /// 目标宽高比（如 "16:9"），原图居中，两侧或上下扩展；与 padding 二选一
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect: Option<String>,
+    /// 各边扩展的像素数（top / right / bottom / left）；与 aspect 二选一
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<ExtendPadding>,
+    /// 对新区域内容的描述（可选），如 "延伸成海边的沙滩"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// 是否把原图像素覆盖回原位置，保证原区域完全不变（默认 true）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_original: Option<bool>,
+    /// 随机种子（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct SubmitGenerationJobArgs {
+    /// 要在后台执行的工具：generate_image、edit_image 或 batch_generate
+    #[schemars(example = &"batch_generate")]
+    pub tool: String,
+    /// 该工具的参数，与直接调用时相同
+    pub arguments: Value,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct JobIdArgs {
+    /// submit_generation_job 返回的任务 ID
+    pub job_id: String,
+}
+
+/// regenerate 重放历史记录时使用的模型，以及被重放的原始生成 ID
+struct Replay {
+    parent: String,
+    model: String,
+}
+
+#[tool_router]
+impl OpenRouterServer {
+    #[tool(
+        description = "文本生成图像",
+        output_schema = output::schema()
+    )]
+    async fn generate_image(
+        &self,
+        Parameters(args): Parameters<GenerateImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        self.generate_image_with(args, None).await
+    }
+
+    #[tool(
+        description = "去除图像背景：让模型把背景替换为纯色（键控色），再在本地抠图，保存带透明通道的 PNG（{原文件名}_transparent.png），适合贴纸和商品抠图",
+        output_schema = output::schema()
+    )]
+    async fn remove_background(
+        &self,
+        Parameters(args): Parameters<RemoveBackgroundArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        self.remove_background_with(args, None).await
+    }
+
+    #[tool(
+        description = "使用图像模型编辑或分析图像（支持多张图像）。图像可以是：1) URL链接 2) base64编码数据 3) 本地文件路径",
+        output_schema = output::schema()
+    )]
+    async fn edit_image(
+        &self,
+        Parameters(args): Parameters<EditImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        self.edit_image_with(args, None).await
+    }
+
+    #[tool(
+        description = "风格迁移：保持主体图像的构图和内容，按一张或多张风格参考图的色彩、光照、媒介和笔触重绘。结果命名为 {原文件名}_styled",
+        output_schema = output::schema()
+    )]
+    async fn style_transfer(
+        &self,
+        Parameters(args): Parameters<StyleTransferArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        self.style_transfer_with(args, None).await
+    }
+
+    #[tool(
+        description = "搜索 generate_image / edit_image 的生成历史，可按文本、时间范围、模型、状态和工具过滤",
+        output_schema = output::schema()
+    )]
+    async fn search_history(
+        &self,
+        Parameters(args): Parameters<SearchHistoryArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let history = self.require_history()?;
+        let query = HistoryQuery {
+            text: args.query.filter(|q| !q.trim().is_empty()),
+            since: args
+                .since
+                .as_deref()
+                .map(|s| history::parse_time_bound(s, false))
+                .transpose()
+                .map_err(errors::invalid_params)?,
+            until: args
+                .until
+                .as_deref()
+                .map(|s| history::parse_time_bound(s, true))
+                .transpose()
+                .map_err(errors::invalid_params)?,
+            model: args.model,
+            status: args.status,
+            tool: args.tool,
+            limit: args.limit.unwrap_or(20),
+        };
+        let records = history
+            .search(query)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("history.query_failed", error = e), None))?;
+
+        let mut response_text = tr!("response.history_count", count = records.len());
+        for record in &records {
+            let prompt: String = record.prompt.chars().take(80).collect();
+            response_text.push_str(&tr!(
+                "response.history_record",
+                created_at = record.created_at,
+                id = record.id,
+                tool = record.tool,
+                model = record.model,
+                status = record.status,
+                duration = record.latency_ms,
+                prompt = prompt
+            ));
+            if let Some(parent_id) = &record.parent_id {
+                response_text.push_str(&tr!("response.replayed_from", id = parent_id));
+            }
+            for path in &record.saved_paths {
+                response_text.push_str(&tr!("response.history_output", path = path));
+            }
+        }
+
+        let output = ToolOutput::new("search_history").with_data(json!({ "records": records }));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "根据生成 ID 获取一条完整的生成历史记录",
+        output_schema = output::schema()
+    )]
+    async fn get_generation(
+        &self,
+        Parameters(args): Parameters<GetGenerationArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let history = self.require_history()?;
+        let record = history
+            .get(&args.id)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("history.query_failed", error = e), None))?
+            .ok_or_else(|| {
+                McpError::from(ToolError::input_not_found(tr!(
+                    "history.not_found",
+                    id = args.id
+                )))
+            })?;
+
+        let record_json = serde_json::to_string_pretty(&record).map_err(|e| {
+            McpError::internal_error(tr!("history.serialize_failed", error = e), None)
+        })?;
+        let response_text = tr!(
+            "response.generation_record",
+            id = record.id,
+            json = record_json
+        );
+        let output = ToolOutput::new("get_generation")
+            .with_data(serde_json::to_value(&record).unwrap_or_default());
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "用量与费用报告：汇总上游调用的 token 和费用（美元），按日期（UTC）、会话和 API 密钥分组。费用优先使用上游返回的 usage.cost，否则按模型单价估算",
+        output_schema = output::schema()
+    )]
+    async fn usage_report(
+        &self,
+        Parameters(args): Parameters<UsageReportArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let ledger = self
+            .usage
+            .as_ref()
+            .ok_or_else(|| McpError::invalid_request(tr!("usage.unavailable"), None))?;
+        let query = UsageQuery {
+            since: args
+                .since
+                .as_deref()
+                .map(|s| history::parse_time_bound(s, false))
+                .transpose()
+                .map_err(errors::invalid_params)?,
+            until: args
+                .until
+                .as_deref()
+                .map(|s| history::parse_time_bound(s, true))
+                .transpose()
+                .map_err(errors::invalid_params)?,
+            session_id: args
+                .current_session
+                .unwrap_or(false)
+                .then(|| self.session_id.clone()),
+            limit: args.limit.unwrap_or(usage::DEFAULT_REPORT_LIMIT),
+        };
+        let report = ledger
+            .report(query)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("usage.query_failed", error = e), None))?;
+
+        let mut response_text = tr!(
+            "response.usage_report",
+            session = self.session_id,
+            api_key = self.api_key_id
+        );
+        push_usage_totals(
+            &mut response_text,
+            &tr!("response.usage_total"),
+            &report.totals,
+        );
+        for (title, groups, current) in [
+            (tr!("response.usage_by_day"), &report.by_day, None),
+            (
+                tr!("response.usage_by_session"),
+                &report.by_session,
+                Some(&self.session_id),
+            ),
+            (
+                tr!("response.usage_by_api_key"),
+                &report.by_api_key,
+                Some(&self.api_key_id),
+            ),
+        ] {
+            if groups.is_empty() {
+                continue;
+            }
+            response_text.push_str(&tr!("response.usage_group", title = title));
+            for group in groups {
+                let key = if current == Some(&group.key) {
+                    tr!("response.usage_current", key = group.key)
+                } else {
+                    group.key.clone()
+                };
+                push_usage_totals(&mut response_text, &key, &group.totals);
+            }
+        }
+
+        let output = ToolOutput::new("usage_report").with_data(json!({
+            "session_id": self.session_id,
+            "api_key_id": self.api_key_id,
+            "report": report,
+        }));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "批量文本生成图像：传入提示词列表，或提示词模板加变量矩阵（按所有组合展开）。以有限并发执行，逐条推送进度，单条失败不影响其他条目",
+        output_schema = output::schema()
+    )]
+    async fn batch_generate(
+        &self,
+        Parameters(args): Parameters<BatchGenerateArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let progress = context
+            .meta
+            .get_progress_token()
+            .map(|token| BatchProgress {
+                peer: context.peer.clone(),
+                token,
+            });
+        self.batch_generate_with(args, progress, context.ct.clone())
+            .await
+    }
+
+    #[tool(
+        description = "基于一张图像生成 N 个变体，可指定变化程度和方向提示。各变体并发生成，保存为 {原文件名}_var_N",
+        output_schema = output::schema()
+    )]
+    async fn create_variations(
+        &self,
+        Parameters(args): Parameters<CreateVariationsArgs>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let count = args.count.unwrap_or(variations::DEFAULT_VARIATION_COUNT);
+        if count == 0 || count > variations::MAX_VARIATION_COUNT {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "variations.count_range",
+                    max = variations::MAX_VARIATION_COUNT,
+                    value = count
+                ),
+                None,
+            ));
+        }
+        self.ensure_image_readable(&args.image).await?;
+
+        let strength = args.strength.unwrap_or_default();
+        let requests = (1..=count)
+            .map(|index| {
+                let seed = args
+                    .seed
+                    .map(|seed| seed.wrapping_add(u64::from(index - 1)));
+                let variation = Variation {
+                    image: &args.image,
+                    strength,
+                    hint: args.hint.as_deref(),
+                    index,
+                    count,
+                    seed,
+                };
+                self.variation_request(&variation, None)
+            })
+            .collect();
+        let progress = context
+            .meta
+            .get_progress_token()
+            .map(|token| BatchProgress {
+                peer: context.peer.clone(),
+                token,
+            });
+        let results = self
+            .run_batch(
+                requests,
+                self.config.batch_concurrency,
+                progress,
+                context.ct.clone(),
+            )
+            .await;
+
+        let succeeded = results.iter().filter(|r| r.outcome.is_ok()).count();
+        let mut response_text = tr!(
+            "response.variations",
+            image = display_image_input(&args.image),
+            model = self.config.model,
+            strength = strength.as_str()
+        );
+        if let Some(hint) = &args.hint {
+            response_text.push_str(&tr!("response.variation_hint", hint = hint));
+        }
+        response_text.push_str(&tr!(
+            "response.variation_summary",
+            succeeded = succeeded,
+            failed = results.len() - succeeded,
+            total = results.len()
+        ));
+        let mut output = ToolOutput::new("create_variations")
+            .with_model(self.config.model.clone())
+            .with_provider(self.config.provider())
+            .with_data(json!({
+                "strength": strength.as_str(),
+                "succeeded": succeeded,
+                "failed": results.len() - succeeded,
+            }));
+        for result in &results {
+            output.items.push(match &result.outcome {
+                Ok(outcome) => ItemOutput::from_outcome(result.index, None, outcome),
+                Err(error) => ItemOutput::failed(result.index, None, error.clone()),
+            });
+            match &result.outcome {
+                Ok(outcome) => {
+                    response_text.push_str(&tr!(
+                        "response.variation_ok",
+                        index = result.index,
+                        id = outcome.generation_id
+                    ));
+                    for image in &outcome.images {
+                        match &image.saved_path {
+                            Some(path) => {
+                                response_text.push_str(&tr!("response.saved_to_item", path = path))
+                            }
+                            None => response_text.push_str(&tr!("response.not_saved_item")),
+                        }
+                    }
+                }
+                Err(error) => {
+                    response_text.push_str(&tr!(
+                        "response.variation_failed",
+                        index = result.index,
+                        error = error
+                    ));
+                }
+            }
+        }
+
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "使用视觉模型描述图像。模式: caption（一句话标题）、detailed（详细描述）、alt_text（长度受限的无障碍替代文本）、json（包含 caption/tags/objects/colors 的结构化结果）",
+        output_schema = output::schema()
+    )]
+    async fn describe_image(
+        &self,
+        Parameters(args): Parameters<DescribeImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let mode = args.mode.unwrap_or_default();
+        let max_chars = args
+            .max_length
+            .unwrap_or(describe::DEFAULT_ALT_TEXT_MAX_CHARS);
+        if max_chars == 0 || max_chars > describe::MAX_ALT_TEXT_CHARS {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "describe.max_length_range",
+                    max = describe::MAX_ALT_TEXT_CHARS,
+                    value = max_chars
+                ),
+                None,
+            ));
+        }
+        self.ensure_image_readable(&args.image).await?;
+
+        let outcome = self
+            .describe(&args.image, mode, max_chars, args.language.as_deref())
+            .await?;
+
+        let mut response_text = tr!(
+            "response.describe",
+            model = outcome.model,
+            image = display_image_input(&args.image),
+            mode = mode.as_str()
+        );
+        match mode {
+            DescribeMode::Json => {
+                response_text.push_str(&format!("\n\n```json\n{}\n```", outcome.text))
+            }
+            DescribeMode::AltText => response_text.push_str(&tr!(
+                "response.describe_length",
+                length = outcome.text.chars().count(),
+                max = max_chars,
+                text = outcome.text
+            )),
+            _ => response_text.push_str(&format!("\n\n{}", outcome.text)),
+        }
+        push_usage(&mut response_text, outcome.usage.as_ref());
+
+        let mut output = ToolOutput::new("describe_image")
+            .with_model(outcome.model.clone())
+            .with_provider(self.config.provider())
+            .with_usage(outcome.usage.as_ref());
+        // json 模式的结果同时以对象形式放在 data 中
+        if mode == DescribeMode::Json
+            && let Ok(description) = serde_json::from_str::<Value>(&outcome.text)
+        {
+            output = output.with_data(description);
+        }
+        output.text = Some(outcome.text);
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "在本地把多张图像拼接为一张（不调用模型）：grid 网格、side_by_side 并排或 stacked 堆叠，可设置间距、背景色和标题，结果保存到保存目录",
+        output_schema = output::schema()
+    )]
+    async fn compose_images(
+        &self,
+        Parameters(args): Parameters<ComposeImagesArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.images.is_empty() || args.images.len() > compose::MAX_COMPOSE_IMAGES {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "compose.image_count_range",
+                    max = compose::MAX_COMPOSE_IMAGES,
+                    value = args.images.len()
+                ),
+                None,
+            ));
+        }
+        let layout = args.layout.unwrap_or_default();
+        let (columns, rows) =
+            compose::layout_dimensions(layout, args.images.len(), args.columns, args.rows)
+                .map_err(errors::invalid_params)?;
+        let gutter = args.gutter.unwrap_or(compose::DEFAULT_GUTTER);
+        if gutter > compose::MAX_GUTTER {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "compose.spacing_too_large",
+                    max = compose::MAX_GUTTER,
+                    value = gutter
+                ),
+                None,
+            ));
+        }
+        let background = imaging::parse_color(args.background.as_deref().unwrap_or("#FFFFFF"))
+            .map_err(errors::invalid_params)?;
+        let captions = args.captions.unwrap_or_default();
+        if captions.len() > args.images.len() {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "compose.too_many_captions",
+                    captions = captions.len(),
+                    images = args.images.len()
+                ),
+                None,
+            ));
+        }
+        let caption_size = args.caption_size.unwrap_or(compose::DEFAULT_CAPTION_SIZE);
+        let (min_size, max_size) = compose::CAPTION_SIZE_RANGE;
+        if !(min_size..=max_size).contains(&caption_size) {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "compose.caption_size_range",
+                    min = min_size,
+                    max = max_size,
+                    value = caption_size
+                ),
+                None,
+            ));
+        }
+
+        let mut images = Vec::with_capacity(args.images.len());
+        for image in &args.images {
+            images.push(self.load_image(image).await?);
+        }
+
+        let options = ComposeOptions {
+            layout,
+            columns,
+            rows,
+            gutter,
+            background,
+            captions,
+            caption_size,
+        };
+        let caption_font = self.config.caption_font.clone();
+        let composed = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let font = if options.has_captions() {
+                Some(compose::load_caption_font(caption_font.as_deref())?)
+            } else {
+                None
+            };
+            let missing_glyphs = font
+                .as_ref()
+                .is_some_and(|font| compose::has_missing_glyphs(font, &options.captions));
+            let canvas = compose::compose(&images, &options, font.as_ref())?;
+            let (width, height) = canvas.dimensions();
+            let bytes = imaging::encode_png(&image::DynamicImage::ImageRgba8(canvas))?;
+            Ok((bytes, width, height, missing_glyphs))
+        })
+        .await
+        .map_err(|e| McpError::internal_error(tr!("compose.task_failed", error = e), None))?;
+        let (bytes, width, height, missing_glyphs) = composed.map_err(errors::invalid_params)?;
+
+        let stem = args.filename.as_deref().unwrap_or("composite");
+        let saved_path = self.save_local_image(&bytes, "png", stem).await?;
+
+        let layout_text = match layout {
+            ComposeLayout::Grid => format!("{} ({}×{})", layout.as_str(), columns, rows),
+            _ => layout.as_str().to_string(),
+        };
+        let mut response_text = tr!(
+            "response.compose",
+            layout = layout_text,
+            count = args.images.len(),
+            width = width,
+            height = height,
+            path = saved_path
+        );
+        let mut output = ToolOutput::new("compose_images").with_data(json!({
+            "layout": layout.as_str(),
+            "columns": columns,
+            "rows": rows,
+            "input_count": args.images.len(),
+        }));
+        output.images.push(OutputImage::local(
+            saved_path,
+            "png",
+            bytes.len(),
+            (width, height),
+        ));
+        if missing_glyphs {
+            let warning = tr!("compose.missing_glyphs");
+            response_text.push_str(&format!("\n⚠️ {}", warning));
+            output.warn(warning);
+        }
+
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "在本地对一张图像依次执行确定性的操作（不调用模型）：crop 裁剪（矩形或宽高比）、resize 缩放、rotate 旋转、flip 翻转、pad 填充或 letterbox 到宽高比，并可转换输出格式",
+        output_schema = output::schema()
+    )]
+    async fn transform_image(
+        &self,
+        Parameters(args): Parameters<TransformImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.operations.len() > transform::MAX_TRANSFORM_OPS {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "transform.too_many_ops",
+                    max = transform::MAX_TRANSFORM_OPS,
+                    value = args.operations.len()
+                ),
+                None,
+            ));
+        }
+        let quality = args.quality.unwrap_or(imaging::DEFAULT_JPEG_QUALITY);
+        if !(1..=100).contains(&quality) {
+            return Err(McpError::invalid_params(
+                tr!("transform.quality_range", value = quality),
+                None,
+            ));
+        }
+
+        let bytes = self.load_image_bytes(&args.image).await?;
+        let format = args.format.unwrap_or_else(|| {
+            image::guess_format(&bytes)
+                .ok()
+                .and_then(OutputFormat::from_image_format)
+                .unwrap_or_default()
+        });
+        let source = imaging::decode_image(bytes, &args.image).await?;
+        let (source_width, source_height) = (source.width(), source.height());
+
+        let operations = args.operations.clone();
+        let transformed = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let image = transform::apply_all(source, &operations)?;
+            let bytes = imaging::encode_image(&image, format, quality)?;
+            Ok((bytes, image.width(), image.height()))
+        })
+        .await
+        .map_err(|e| McpError::internal_error(tr!("imaging.task_failed", error = e), None))?;
+        let (bytes, width, height) = transformed.map_err(errors::invalid_params)?;
+
+        let stem = match (&args.filename, generation::source_stem(&args.image)) {
+            (Some(filename), _) => filename.clone(),
+            (None, Some(source_stem)) => format!("{}_transformed", source_stem),
+            (None, None) => "transformed_image".to_string(),
+        };
+        let saved_path = self
+            .save_local_image(&bytes, format.image_type(), &stem)
+            .await?;
+
+        let mut response_text = tr!(
+            "response.transform",
+            image = display_image_input(&args.image),
+            width = source_width,
+            height = source_height,
+            new_width = width,
+            new_height = height,
+            format = format.image_type()
+        );
+        if args.operations.is_empty() {
+            response_text.push_str(&tr!("response.transform_no_ops"));
+        } else {
+            response_text.push_str(&tr!("response.transform_ops"));
+            for (index, op) in args.operations.iter().enumerate() {
+                response_text.push_str(&format!("\n{}. {}", index + 1, op.summary()));
+            }
+        }
+        response_text.push_str(&tr!("response.saved_to", path = saved_path));
+
+        let mut output = ToolOutput::new("transform_image").with_data(json!({
+            "source_width": source_width,
+            "source_height": source_height,
+            "operations": args
+                .operations
+                .iter()
+                .map(TransformOp::summary)
+                .collect::<Vec<_>>(),
+        }));
+        output.images.push(OutputImage::local(
+            saved_path,
+            format.image_type(),
+            bytes.len(),
+            (width, height),
+        ));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "扩展图像画布（outpainting）：按目标宽高比或各边像素数在本地构建更大的画布，由图像模型填充新区域，结果对齐到画布尺寸并保留原图像素",
+        output_schema = output::schema()
+    )]
+    async fn extend_image(
+        &self,
+        Parameters(args): Parameters<ExtendImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let source = self.load_image(&args.image).await?;
+        let plan = ExtendPlan::new(
+            source.width(),
+            source.height(),
+            args.aspect.as_deref(),
+            args.padding.as_ref(),
+        )
+        .map_err(errors::invalid_params)?;
+        let preserve_original = args.preserve_original.unwrap_or(true);
+
+        let source = std::sync::Arc::new(source);
+        let canvas_source = source.clone();
+        let canvas = tokio::task::spawn_blocking(move || {
+            imaging::encode_png(&plan.build_canvas(&canvas_source))
+        })
+        .await
+        .map_err(|e| McpError::internal_error(tr!("extend.canvas_failed", error = e), None))?
+        .map_err(|e| McpError::internal_error(tr!("extend.canvas_failed", error = e), None))?;
+        let canvas_url = imaging::png_data_url(&canvas);
+
+        let cropped = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let processor = {
+            let cropped = cropped.clone();
+            generation::OutputProcessor::new(move |output| {
+                let original = preserve_original.then_some(source.as_ref());
+                let (output, was_cropped) = plan.fit_output(output, original);
+                if was_cropped {
+                    cropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+                Ok(output)
+            })
+        };
+
+        let instruction = plan.instruction(args.prompt.as_deref());
+        let request = ImageRequest::new(
+            "extend_image",
+            &instruction,
+            &self.config.model,
+            serde_json::to_value(&args).unwrap_or_default(),
+        )
+        .with_images(vec![canvas_url])
+        .with_seed(args.seed)
+        .with_source_name(generation::source_stem(&args.image))
+        .with_name_suffix("_extended")
+        .with_output_processor(processor);
+        let outcome = self.execute_image_request(request).await?;
+
+        let mut response_text = tr!(
+            "response.extend",
+            model = outcome.model,
+            image = display_image_input(&args.image),
+            width = plan.width,
+            height = plan.height,
+            canvas_width = plan.canvas_width,
+            canvas_height = plan.canvas_height,
+            x = plan.x,
+            y = plan.y
+        );
+        if let Some(prompt) = &args.prompt {
+            response_text.push_str(&tr!("response.extend_prompt", prompt = prompt));
+        }
+        response_text.push_str(&tr!("response.text", text = outcome.text));
+        let mut output =
+            ToolOutput::from_outcome("extend_image", &self.config.provider(), &outcome).with_data(
+                json!({
+                    "source_width": plan.width,
+                    "source_height": plan.height,
+                    "canvas_width": plan.canvas_width,
+                    "canvas_height": plan.canvas_height,
+                    "x": plan.x,
+                    "y": plan.y,
+                    "preserve_original": preserve_original,
+                }),
+            );
+        let cropped = cropped.load(std::sync::atomic::Ordering::Relaxed);
+        if cropped > 0 {
+            let warning = tr!(
+                "extend.cropped_outputs",
+                count = cropped,
+                width = plan.canvas_width,
+                height = plan.canvas_height
+            );
+            response_text.push_str(&format!("\n\n⚠️ {}", warning));
+            output.warn(warning);
+        }
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "把图像放大 2 倍或 4 倍。默认后端在本地使用 Lanczos 重采样加锐化，也可以配置为由上游图像模型放大。结果保存在原图旁边，命名为 {原文件名}_x2.png",
+        output_schema = output::schema()
+    )]
+    async fn upscale_image(
+        &self,
+        Parameters(args): Parameters<UpscaleImageArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let factor = args.factor.unwrap_or(upscale::DEFAULT_UPSCALE_FACTOR);
+        if !upscale::UPSCALE_FACTORS.contains(&factor) {
+            return Err(McpError::invalid_params(
+                tr!("upscale.invalid_factor", value = factor),
+                None,
+            ));
+        }
+        let backend = args.backend.unwrap_or(self.config.upscale_backend);
+
+        let source = self.load_image(&args.image).await?;
+        let (source_width, source_height) = (source.width(), source.height());
+        let outcome = self
+            .upscale(
+                &args.image,
+                source,
+                factor,
+                backend,
+                args.sharpen.unwrap_or(true),
+            )
+            .await?;
+        let (width, height) = (outcome.image.width(), outcome.image.height());
+
+        // 本地文件保存在原图旁边，其他输入保存到保存目录
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        let (directory, stem) = match (
+            generation::source_stem(&args.image),
+            image_utils::resolve_local_image_path(&args.image, &current_save_dir),
+        ) {
+            (Some(stem), Some(path)) => (
+                path.parent()
+                    .map(std::path::Path::to_path_buf)
+                    .unwrap_or_else(|| current_save_dir.clone().into()),
+                stem,
+            ),
+            _ => (current_save_dir.into(), "image".to_string()),
+        };
+        let image = outcome.image;
+        let (saved_path, byte_size) = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+            let bytes = imaging::encode_png(&image)?;
+            let path = image_utils::write_new_file_atomic(
+                &directory,
+                &format!("{}_x{}", stem, factor),
+                "png",
+                &bytes,
+            )?;
+            Ok((path, bytes.len()))
+        })
+        .await
+        .map_err(|e| McpError::internal_error(tr!("imaging.save_failed", error = e), None))?
+        .map_err(|e| McpError::internal_error(tr!("imaging.save_failed", error = e), None))?;
+
+        let mut response_text = tr!(
+            "response.upscale",
+            image = display_image_input(&args.image),
+            backend = outcome.backend.as_str()
+        );
+        if let Some(model) = &outcome.model {
+            response_text.push_str(&tr!("response.model_line", model = model));
+        }
+        response_text.push_str(&tr!(
+            "response.upscale_size",
+            width = source_width,
+            height = source_height,
+            new_width = width,
+            new_height = height,
+            factor = factor,
+            path = saved_path.display()
+        ));
+        push_usage(&mut response_text, outcome.usage.as_ref());
+
+        let mut output = ToolOutput::new("upscale_image")
+            .with_usage(outcome.usage.as_ref())
+            .with_data(json!({
+                "backend": outcome.backend.as_str(),
+                "factor": factor,
+                "source_width": source_width,
+                "source_height": source_height,
+            }));
+        if let Some(model) = outcome.model {
+            output = output
+                .with_model(model)
+                .with_provider(self.config.provider());
+        }
+        output.images.push(OutputImage::local(
+            saved_path.display().to_string(),
+            "png",
+            byte_size,
+            (width, height),
+        ));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "使用文本模型把简短的提示词改写为细节丰富的图像生成提示词，返回原始和改写后的提示词。generate_image 的 enhance 参数会自动执行这一步",
+        output_schema = output::schema()
+    )]
+    async fn enhance_prompt(
+        &self,
+        Parameters(args): Parameters<EnhancePromptArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.prompt.trim().is_empty() {
+            return Err(McpError::invalid_params(tr!("enhance.empty_prompt"), None));
+        }
+        let enhanced = self
+            .enhance_prompt_text(&args.prompt, args.style.as_deref())
+            .await?;
+
+        let mut response_text = tr!(
+            "response.enhance",
+            model = enhanced.model,
+            original = enhanced.original,
+            enhanced = enhanced.enhanced
+        );
+        push_usage(&mut response_text, enhanced.usage.as_ref());
+
+        let output = ToolOutput::new("enhance_prompt")
+            .with_model(enhanced.model.clone())
+            .with_provider(self.config.provider())
+            .with_usage(enhanced.usage.as_ref())
+            .with_data(json!({
+                "original_prompt": enhanced.original,
+                "enhanced_prompt": enhanced.enhanced,
+            }));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "提交后台生成任务并立即返回任务 ID，适合耗时较长、可能超过客户端超时的批量或慢速模型请求。之后用 get_job_status 轮询、get_job_result 取回结果",
+        output_schema = output::schema()
+    )]
+    async fn submit_generation_job(
+        &self,
+        Parameters(args): Parameters<SubmitGenerationJobArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let jobs = self.require_jobs()?;
+        validate_job_arguments(&args.tool, &args.arguments)?;
+        let job_id = self
+            .submit_job(jobs, &args.tool, &args.arguments)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("jobs.submit_failed", error = e), None))?;
+
+        let response_text = tr!(
+            "response.job_submitted",
+            id = job_id,
+            tool = args.tool,
+            status = jobs::STATUS_QUEUED
+        );
+        let output = ToolOutput::new("submit_generation_job").with_data(json!({
+            "job_id": job_id,
+            "tool": args.tool,
+            "status": jobs::STATUS_QUEUED,
+        }));
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "查询后台任务的状态",
+        output_schema = output::schema()
+    )]
+    async fn get_job_status(
+        &self,
+        Parameters(args): Parameters<JobIdArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.find_job(&args.job_id).await?;
+        Ok(job_output("get_job_status", &job).into_result(format_job_status(&job)))
+    }
+
+    #[tool(
+        description = "取回已完成的后台任务的结果",
+        output_schema = output::schema()
+    )]
+    async fn get_job_result(
+        &self,
+        Parameters(args): Parameters<JobIdArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.find_job(&args.job_id).await?;
+        match job.status.as_str() {
+            jobs::STATUS_SUCCEEDED => {
+                let response_text = tr!(
+                    "response.job_result",
+                    id = job.id,
+                    result = job.result.unwrap_or_default()
+                );
+                // 返回任务中工具的结构化输出；早期任务没有保存时只返回任务 ID
+                let mut result = CallToolResult::success(vec![Content::text(response_text)]);
+                result.structured_content = job.structured_result.or_else(|| {
+                    serde_json::to_value(
+                        ToolOutput::new("get_job_result").with_data(json!({ "job_id": job.id })),
+                    )
+                    .ok()
+                });
+                Ok(result)
+            }
+            // 按任务中工具的错误类别返回，与同步调用一致；早期任务没有保存类别时作为内部错误
+            jobs::STATUS_FAILED => {
+                let error = match job.error_details {
+                    Some(details) => ToolError::from_details(details),
+                    None => ToolError::internal(tr!(
+                        "jobs.failed",
+                        id = job.id,
+                        error = job.error.unwrap_or_default()
+                    )),
+                };
+                errors::into_tool_result(&job.tool, error.into())
+            }
+            jobs::STATUS_CANCELLED => Err(McpError::invalid_request(
+                tr!("jobs.cancelled", id = job.id),
+                None,
+            )),
+            _ => Err(McpError::invalid_request(
+                tr!("jobs.not_finished", id = job.id, status = job.status),
+                None,
+            )),
+        }
+    }
+
+    #[tool(
+        description = "取消排队中或运行中的后台任务",
+        output_schema = output::schema()
+    )]
+    async fn cancel_job(
+        &self,
+        Parameters(args): Parameters<JobIdArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let job = self.find_job(&args.job_id).await?;
+        if job.is_finished() {
+            return Err(McpError::invalid_request(
+                tr!("jobs.already_finished", id = job.id, status = job.status),
+                None,
+            ));
+        }
+        let job =
+            self.require_jobs()?.cancel(&job.id).await.map_err(|e| {
+                McpError::internal_error(tr!("jobs.cancel_failed", error = e), None)
+            })?;
+
+        let response_text = tr!(
+            "response.job_cancel_requested",
+            status = format_job_status(&job)
+        );
+        Ok(job_output("cancel_job", &job).into_result(response_text))
+    }
+
+    #[tool(
+        description = "根据生成 ID 重放一次历史生成：按原工具的参数重新执行（去背景、风格迁移、变体等保持原来的处理和命名），可覆盖提示词、输入图像、种子或模型。新结果在历史中关联到原记录",
+        output_schema = output::schema()
+    )]
+    async fn regenerate(
+        &self,
+        Parameters(args): Parameters<RegenerateArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let history = self.require_history()?;
+        let parent = history
+            .get(&args.id)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("history.query_failed", error = e), None))?
+            .ok_or_else(|| {
+                McpError::from(ToolError::input_not_found(tr!(
+                    "history.not_found",
+                    id = args.id
+                )))
+            })?;
+
+        let replay = Replay {
+            parent: parent.id.clone(),
+            model: args.model.unwrap_or_else(|| parent.model.clone()),
+        };
+        let images = args.images.unwrap_or_else(|| recorded_inputs(&parent));
+        let invalid_record = |e: serde_json::Error| {
+            McpError::invalid_params(
+                tr!("regenerate.invalid_record", id = parent.id, error = e),
+                None,
+            )
+        };
+
+        // 按原工具的参数重放，使命名、抠图和变体等工具专属的处理保持一致
+        match parent.tool.as_str() {
+            "generate_image" | "batch_generate" => {
+                if !images.is_empty() {
+                    return Err(McpError::invalid_params(
+                        tr!("regenerate.images_not_supported", tool = parent.tool),
+                        None,
+                    ));
+                }
+                let mut recorded: GenerateImageArgs =
+                    serde_json::from_value(parent.arguments.clone()).map_err(invalid_record)?;
+                match args.prompt {
+                    Some(prompt) => recorded.prompt = prompt,
+                    // 沿用当时改写后的提示词，不再重新改写
+                    None => {
+                        if let Some(enhanced) = parent
+                            .arguments
+                            .get("enhanced_prompt")
+                            .and_then(Value::as_str)
+                        {
+                            recorded.prompt = enhanced.to_string();
+                            recorded.enhance = None;
+                        }
+                    }
+                }
+                recorded.seed = args.seed.or(recorded.seed);
+                self.generate_image_with(recorded, Some(&replay)).await
+            }
+            "edit_image" => {
+                let mut recorded: EditImageArgs =
+                    serde_json::from_value(parent.arguments.clone()).map_err(invalid_record)?;
+                if images.is_empty() {
+                    return Err(McpError::invalid_params(
+                        tr!("regenerate.no_inputs", id = parent.id),
+                        None,
+                    ));
+                }
+                // 替换了输入图像时，只有数量一致才沿用原来的角色
+                recorded.roles = recorded.roles.filter(|roles| roles.len() == images.len());
+                recorded.images = images;
+                recorded.instruction = args.prompt.unwrap_or(recorded.instruction);
+                recorded.seed = args.seed.or(recorded.seed);
+                self.edit_image_with(recorded, Some(&replay)).await
+            }
+            "style_transfer" => {
+                let mut recorded: StyleTransferArgs =
+                    serde_json::from_value(parent.arguments.clone()).map_err(invalid_record)?;
+                let mut images = images.into_iter();
+                recorded.image = images.next().ok_or_else(|| {
+                    McpError::invalid_params(tr!("regenerate.no_inputs", id = parent.id), None)
+                })?;
+                recorded.style_images = images.collect();
+                recorded.prompt = args.prompt.or(recorded.prompt);
+                recorded.seed = args.seed.or(recorded.seed);
+                self.style_transfer_with(recorded, Some(&replay)).await
+            }
+            "remove_background" => {
+                if args.prompt.is_some() {
+                    return Err(McpError::invalid_params(
+                        tr!("regenerate.prompt_not_supported", tool = parent.tool),
+                        None,
+                    ));
+                }
+                let mut recorded: RemoveBackgroundArgs =
+                    serde_json::from_value(parent.arguments.clone()).map_err(invalid_record)?;
+                recorded.image = single_input(&parent, images)?;
+                recorded.seed = args.seed.or(recorded.seed);
+                self.remove_background_with(recorded, Some(&replay)).await
+            }
+            "create_variations" => {
+                let recorded: RecordedVariation =
+                    serde_json::from_value(parent.arguments.clone()).map_err(invalid_record)?;
+                let image = single_input(&parent, images)?;
+                // 覆盖的提示词作为新的变化方向
+                let hint = args.prompt.or(recorded.hint);
+                let variation = Variation {
+                    image: &image,
+                    strength: recorded.strength,
+                    hint: hint.as_deref(),
+                    index: recorded.variation_index,
+                    count: recorded.variation_count.unwrap_or(recorded.variation_index),
+                    seed: args.seed.or(recorded.seed),
+                };
+                self.replay_variation(&variation, &replay).await
+            }
+            other => Err(McpError::invalid_params(
+                tr!("regenerate.unsupported_tool", tool = other),
+                None,
+            )),
+        }
+    }
+
+    #[tool(
+        description = "开始一个多轮编辑会话：服务器保留包含图像的对话上下文，之后用 continue_edit_session 在上一轮结果的基础上继续修改",
+        output_schema = output::schema()
+    )]
+    async fn start_edit_session(
+        &self,
+        Parameters(args): Parameters<StartEditSessionArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        for image in &args.images {
+            self.ensure_image_readable(image).await?;
+        }
+        let session = self
+            .sessions
+            .create(&self.config.model)
+            .await
+            .map_err(errors::invalid_params)?;
+        let mut session = session.lock().await;
+
+        let mut arguments = serde_json::to_value(&args).unwrap_or_default();
+        arguments["session_id"] = json!(session.id);
+        let request = ImageRequest::new(
+            "start_edit_session",
+            &args.instruction,
+            &session.model,
+            arguments,
+        )
+        .with_images(args.images.clone())
+        .with_seed(args.seed);
+        let outcome = match self.execute_image_request(request).await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                self.sessions.remove(&session.id).await;
+                return Err(e);
+            }
+        };
+        let dropped = session.push_turn(
+            SessionTurn::new(
+                outcome.user_message.clone(),
+                outcome.assistant_message.clone(),
+            ),
+            &outcome.generation_id,
+        );
+
+        let mut response_text = tr!(
+            "response.session_started",
+            id = session.id,
+            model = outcome.model,
+            instruction = args.instruction,
+            count = args.images.len(),
+            text = outcome.text
+        );
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        push_session_status(&mut response_text, &session, dropped);
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
 
-#[tool_router]
-impl OpenRouterServer {
-    #[tool(description = "文本生成图像")]
-    async fn generate_image(
+        let output = session_output(
+            ToolOutput::from_outcome("start_edit_session", &self.config.provider(), &outcome),
+            &session,
+            dropped,
+        );
+        Ok(output.into_result(response_text))
+    }
+
+    #[tool(
+        description = "在多轮编辑会话中继续编辑：模型能看到此前每一轮的指令和输出图像",
+        output_schema = output::schema()
+    )]
+    async fn continue_edit_session(
         &self,
-        Parameters(args): Parameters<GenerateImageArgs>,
+        Parameters(args): Parameters<ContinueEditSessionArgs>,
     ) -> Result<CallToolResult, McpError> {
-        let url = format!("{}/chat/completions", self.config.base_url);
-        let model = self.config.model.clone();
-        let content = vec![json!({
-            "type": "text",
-            "text": args.prompt
-        })];
-        let request_body = json!({
-            "model": model,
-            "messages": [{
-                "role": "user",
-                "content": content
-            }],
-            "max_tokens": 1000,
-            "temperature": 0.7
-        });
+        let session = self
+            .sessions
+            .get(&args.session_id)
+            .await
+            .map_err(errors::invalid_params)?;
+        // 同一会话的请求串行执行，保证上下文顺序
+        let mut session = session.lock().await;
+        session.ensure_capacity().map_err(errors::invalid_params)?;
+        for image in &args.images {
+            self.ensure_image_readable(image).await?;
+        }
 
-        match self.client.post(&url).json(&request_body).send().await {
-            Ok(response) => {
-                let status = response.status();
-                if !status.is_success() {
-                    let error_text = response
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "无法获取错误详情".to_string());
-                    return Err(McpError::internal_error(
-                        format!("API 请求失败，状态码: {}, 错误: {}", status, error_text),
-                        None,
-                    ));
-                }
+        let request = ImageRequest::new(
+            "continue_edit_session",
+            &args.instruction,
+            &session.model,
+            serde_json::to_value(&args).unwrap_or_default(),
+        )
+        .with_images(args.images.clone())
+        .with_seed(args.seed)
+        .with_context(session.context());
+        let outcome = self.execute_image_request(request).await?;
+        let dropped = session.push_turn(
+            SessionTurn::new(
+                outcome.user_message.clone(),
+                outcome.assistant_message.clone(),
+            ),
+            &outcome.generation_id,
+        );
 
-                match response.json::<serde_json::Value>().await {
-                    Ok(response_data) => {
-                        let (content, images_array) = extract_text_and_images(&response_data)?;
-
-                        let current_save_dir = {
-                            let save_dir = self.save_directory.read().await;
-                            save_dir.clone()
-                        };
-                        let saved_images = image_utils::save_response_images(
-                            &images_array,
-                            Some(&current_save_dir),
-                            Some("generated_image"),
-                            false,
-                        );
-
-                        let mut response_text = format!(
-                            "**模型:** {}\n**提示词:** {}\n**保存目录:** {}\n**响应:** {}",
-                            model, args.prompt, current_save_dir, content
-                        );
-                        if !images_array.is_empty() {
-                            response_text.push_str(&format!(
-                                "\n\n**生成的图像:** {} 张图像",
-                                images_array.len()
-                            ));
-                            for (index, img_info) in saved_images.iter().enumerate() {
-                                response_text.push_str(&format!(
-                                    "\n- 图像 {}: {}...",
-                                    index + 1,
-                                    &img_info.url[..std::cmp::min(50, img_info.url.len())]
-                                ));
-                                if let Some(saved_path) = &img_info.saved_path {
-                                    response_text
-                                        .push_str(&format!("\n  已保存到: {}", saved_path));
-                                } else {
-                                    response_text
-                                        .push_str("\n  ⚠️ 未保存到文件");
-                                }
-                                if !img_info.debug_info.is_empty() {
-                                    response_text
-                                        .push_str(&format!("\n  [调试] {}", img_info.debug_info));
-                                }
-                            }
-                        }
+        let mut response_text = tr!(
+            "response.session_turn",
+            id = session.id,
+            model = outcome.model,
+            instruction = args.instruction
+        );
+        if !args.images.is_empty() {
+            response_text.push_str(&tr!("response.extra_images", count = args.images.len()));
+        }
+        response_text.push_str(&tr!("response.text", text = outcome.text));
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        push_session_status(&mut response_text, &session, dropped);
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
 
-                        if let Some(usage) = response_data.get("usage")
-                            && let (
-                                Some(prompt_tokens),
-                                Some(completion_tokens),
-                                Some(total_tokens),
-                            ) = (
-                                usage.get("prompt_tokens").and_then(|t| t.as_u64()),
-                                usage.get("completion_tokens").and_then(|t| t.as_u64()),
-                                usage.get("total_tokens").and_then(|t| t.as_u64()),
-                            )
-                        {
-                            response_text.push_str(&format!("\n\n**使用统计:**\n- 提示词tokens: {}\n- 完成tokens: {}\n- 总tokens: {}", prompt_tokens, completion_tokens, total_tokens));
-                        }
+        let output = session_output(
+            ToolOutput::from_outcome("continue_edit_session", &self.config.provider(), &outcome),
+            &session,
+            dropped,
+        );
+        Ok(output.into_result(response_text))
+    }
 
-                        Ok(CallToolResult::success(vec![Content::text(response_text)]))
-                    }
-                    Err(e) => Err(McpError::internal_error(
-                        format!("解析响应失败: {}", e),
-                        None,
-                    )),
-                }
+    #[tool(
+        description = "结束多轮编辑会话并释放其保存的上下文",
+        output_schema = output::schema()
+    )]
+    async fn end_session(
+        &self,
+        Parameters(args): Parameters<EndSessionArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let session = self
+            .sessions
+            .remove(&args.session_id)
+            .await
+            .ok_or_else(|| {
+                McpError::from(ToolError::input_not_found(tr!(
+                    "session.not_found",
+                    id = args.session_id
+                )))
+            })?;
+        let session = session.lock().await;
+
+        let mut response_text = tr!(
+            "response.session_ended",
+            id = session.id,
+            turns = session.total_turns()
+        );
+        if !session.generation_ids.is_empty() {
+            response_text.push_str(&tr!("response.generation_ids"));
+            for generation_id in &session.generation_ids {
+                response_text.push_str(&format!("\n- {}", generation_id));
             }
-            Err(e) => Err(McpError::internal_error(format!("请求失败: {}", e), None)),
         }
+
+        let output = ToolOutput::new("end_session").with_data(json!({
+            "session_id": session.id,
+            "total_turns": session.total_turns(),
+            "generation_ids": session.generation_ids,
+        }));
+        Ok(output.into_result(response_text))
     }
 
     #[tool(
-        description = "使用图像模型编辑或分析图像（支持多张图像）。图像可以是：1) URL链接 2) base64编码数据 3) 本地文件路径"
+        description = "读取图像文件中嵌入的生成来源元数据（提示词、模型、时间等）。支持 PNG / JPEG / WebP，可传入文件路径或保存目录中的文件名",
+        output_schema = output::schema()
     )]
-    async fn edit_image(
+    async fn read_image_metadata(
         &self,
-        Parameters(args): Parameters<EditImageArgs>,
+        Parameters(args): Parameters<ReadImageMetadataArgs>,
     ) -> Result<CallToolResult, McpError> {
-        if args.images.is_empty() {
-            return Err(McpError::internal_error(
-                "❌ 编辑图像时必须传入至少一张图片！\n\n请提供以下格式之一的图片：\n- URL链接 (http:// 或 https://)\n- base64编码数据 (data:image/...)\n- 本地文件路径\n\n示例：\n- URL: https://example.com/image.jpg\n- 本地文件: C:\\Images\\photo.png\n- base64: data:image/jpeg;base64,/9j/4AAQ...",
-                None,
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        let path = image_utils::resolve_local_image_path(&args.image, &current_save_dir)
+            .ok_or_else(|| {
+                McpError::from(ToolError::input_not_found(tr!(
+                    "metadata.file_not_found",
+                    image = args.image
+                )))
+            })?;
+        let bytes = std::fs::read(&path).map_err(|e| {
+            McpError::internal_error(tr!("metadata.read_file_failed", error = e), None)
+        })?;
+        let entries = provenance::read(&bytes)
+            .map_err(|e| McpError::invalid_params(tr!("metadata.read_failed", error = e), None))?;
+
+        let mut response_text = tr!("response.file", path = path.display());
+        if entries.is_empty() {
+            response_text.push_str(&tr!("response.no_metadata"));
+        } else {
+            let generated_by_us = entries
+                .iter()
+                .any(|(_, value)| value == provenance::GENERATOR_MARKER);
+            response_text.push_str(&tr!(
+                "response.generated_by",
+                value = if generated_by_us {
+                    tr!("common.yes")
+                } else {
+                    tr!("common.no")
+                }
             ));
+            for (key, value) in &entries {
+                response_text.push_str(&format!("\n- {}: {}", key, value));
+            }
         }
 
-        let url = format!("{}/chat/completions", self.config.base_url);
-        let model = self.config.model.clone();
-        let mut content = vec![json!({
-            "type": "text",
-            "text": args.instruction
-        })];
-
-        for image_input in &args.images {
-            match image_utils::detect_and_process_image_input(image_input) {
-                Ok(image_content) => match image_content.content_type.as_str() {
-                    "url" => {
-                        content.push(json!({
-                            "type": "image_url",
-                            "image_url": {"url": image_content.data}
-                        }));
-                    }
-                    "base64" => {
-                        content.push(json!({
-                            "type": "image_url",
-                            "image_url": {"url": image_content.data}
-                        }));
-                    }
-                    _ => {
-                        content.push(json!({
-                            "type": "image_url",
-                            "image_url": {"url": image_content.data}
-                        }));
-                    }
-                },
-                Err(_) => {
-                    let current_save_dir = {
-                        let save_dir = self.save_directory.read().await;
-                        save_dir.clone()
-                    };
-                    match image_utils::find_image_in_save_directory(image_input, &current_save_dir)
-                    {
-                        Ok(image_content) => {
-                            content.push(json!({
-                                "type": "image_url",
-                                "image_url": {"url": image_content.data}
-                            }));
-                        }
-                        Err(_) => {
-                            content.push(json!({
-                                "type": "image_url",
-                                "image_url": {"url": image_input}
-                            }));
+        let mut sidecar_name = path.file_name().unwrap_or_default().to_os_string();
+        sidecar_name.push(".json");
+        let sidecar_path = path.with_file_name(sidecar_name);
+        let sidecar_path = sidecar_path
+            .is_file()
+            .then(|| sidecar_path.display().to_string());
+        if let Some(sidecar_path) = &sidecar_path {
+            response_text.push_str(&tr!("response.sidecar", path = sidecar_path));
+        }
+
+        let output = ToolOutput::new("read_image_metadata").with_data(json!({
+            "path": path.display().to_string(),
+            "generated_by_us": entries
+                .iter()
+                .any(|(_, value)| value == provenance::GENERATOR_MARKER),
+            "entries": entries
+                .iter()
+                .map(|(key, value)| json!({ "key": key, "value": value }))
+                .collect::<Vec<_>>(),
+            "sidecar_path": sidecar_path,
+        }));
+        Ok(output.into_result(response_text))
+    }
+}
+
+impl OpenRouterServer {
+    pub(crate) fn create_tool_router() -> rmcp::handler::server::router::tool::ToolRouter<Self> {
+        let mut router = Self::tool_router();
+        // 工具描述和 schema 按当前语言覆盖
+        for route in router.map.values_mut() {
+            i18n::localize_tool(&mut route.attr);
+        }
+        router
+    }
+
+    /// generate_image 的实现，供工具调用和 regenerate 共用
+    async fn generate_image_with(
+        &self,
+        args: GenerateImageArgs,
+        replay: Option<&Replay>,
+    ) -> Result<CallToolResult, McpError> {
+        let enhanced = if args.enhance.unwrap_or(false) {
+            Some(self.enhance_prompt_text(&args.prompt, None).await?)
+        } else {
+            None
+        };
+        let prompt = enhanced
+            .as_ref()
+            .map_or(args.prompt.as_str(), |e| e.enhanced.as_str());
+        let mut arguments = serde_json::to_value(&args).unwrap_or_default();
+        if let Some(enhanced) = &enhanced {
+            arguments["enhanced_prompt"] = json!(enhanced.enhanced);
+        }
+        let transparent = args.transparent_background.unwrap_or(false);
+        let key_options = ChromaKeyOptions::default();
+        let (prompt, original_prompt) = if transparent {
+            (
+                format!(
+                    "{}\n\n{}",
+                    prompt,
+                    background::generate_instruction(key_options.key_color)
+                ),
+                Some(args.prompt.clone()),
+            )
+        } else {
+            (
+                prompt.to_string(),
+                enhanced.as_ref().map(|e| e.original.clone()),
+            )
+        };
+
+        let request = self
+            .image_request("generate_image", &prompt, arguments, replay)
+            .with_seed(args.seed)
+            .with_original_prompt(original_prompt);
+        let outcome = self.execute_image_request(request).await?;
+        let cutouts = if transparent {
+            self.cut_out_images(&outcome, key_options).await
+        } else {
+            Vec::new()
+        };
+
+        let mut response_text = tr!("response.model", model = outcome.model);
+        match &enhanced {
+            Some(enhanced) => response_text.push_str(&tr!(
+                "response.enhanced_prompt",
+                original = enhanced.original,
+                enhanced = enhanced.enhanced,
+                model = enhanced.model
+            )),
+            None => response_text.push_str(&tr!("response.prompt", prompt = args.prompt)),
+        }
+        response_text.push_str(&tr!(
+            "response.save_dir_and_text",
+            dir = outcome.save_directory,
+            text = outcome.text
+        ));
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        if transparent {
+            push_cutouts(&mut response_text, &cutouts);
+        }
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        let mut output =
+            ToolOutput::from_outcome("generate_image", &self.config.provider(), &outcome);
+        if transparent {
+            output.push_cutouts(&cutouts);
+        }
+        if let Some(enhanced) = &enhanced {
+            output = output.with_data(json!({
+                "original_prompt": enhanced.original,
+                "enhanced_prompt": enhanced.enhanced,
+                "enhance_model": enhanced.model,
+            }));
+        }
+        mark_replay(&mut response_text, &mut output, replay);
+        Ok(output.into_result(response_text))
+    }
+
+    /// remove_background 的实现，供工具调用和 regenerate 共用
+    async fn remove_background_with(
+        &self,
+        args: RemoveBackgroundArgs,
+        replay: Option<&Replay>,
+    ) -> Result<CallToolResult, McpError> {
+        let options = ChromaKeyOptions {
+            key_color: args.key_color.unwrap_or_default(),
+            tolerance: args.tolerance.unwrap_or(background::DEFAULT_TOLERANCE),
+            feather: args.feather.unwrap_or(background::DEFAULT_FEATHER),
+        };
+        options.validate().map_err(errors::invalid_params)?;
+        self.ensure_image_readable(&args.image).await?;
+
+        let instruction = background::removal_instruction(options.key_color);
+        let request = self
+            .image_request(
+                "remove_background",
+                &instruction,
+                serde_json::to_value(&args).unwrap_or_default(),
+                replay,
+            )
+            .with_images(vec![args.image.clone()])
+            .with_seed(args.seed)
+            .with_name_suffix("_keyed");
+        let outcome = self.execute_image_request(request).await?;
+        let cutouts = self.cut_out_images(&outcome, options).await;
+
+        let mut response_text = tr!(
+            "response.remove_background",
+            model = outcome.model,
+            image = display_image_input(&args.image),
+            key_color = options.key_color.as_str(),
+            tolerance = options.tolerance,
+            feather = options.feather,
+            text = outcome.text
+        );
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_cutouts(&mut response_text, &cutouts);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        let mut output =
+            ToolOutput::from_outcome("remove_background", &self.config.provider(), &outcome)
+                .with_data(json!({
+                    "key_color": options.key_color.as_str(),
+                    "tolerance": options.tolerance,
+                    "feather": options.feather,
+                }));
+        output.push_cutouts(&cutouts);
+        mark_replay(&mut response_text, &mut output, replay);
+        Ok(output.into_result(response_text))
+    }
+
+    /// edit_image 的实现，供工具调用和 regenerate 共用
+    async fn edit_image_with(
+        &self,
+        args: EditImageArgs,
+        replay: Option<&Replay>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.images.is_empty() {
+            return Err(McpError::invalid_params(tr!("edit.no_images"), None));
+        }
+
+        let image_roles = args.roles.clone().unwrap_or_default();
+        if args.roles.is_some() {
+            roles::validate_roles(&image_roles, args.images.len())
+                .map_err(errors::invalid_params)?;
+        }
+        for image in &args.images {
+            self.ensure_image_readable(image).await?;
+        }
+
+        let request = self
+            .image_request(
+                "edit_image",
+                &args.instruction,
+                serde_json::to_value(&args).unwrap_or_default(),
+                replay,
+            )
+            .with_images(args.images.clone())
+            .with_image_roles(image_roles.clone())
+            .with_seed(args.seed);
+        let outcome = self.execute_image_request(request).await?;
+
+        let mut response_text = tr!(
+            "response.edit",
+            model = outcome.model,
+            instruction = args.instruction,
+            count = args.images.len()
+        );
+        push_image_roles(&mut response_text, &args.images, &image_roles);
+        response_text.push_str(&tr!("response.text", text = outcome.text));
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        let mut output = ToolOutput::from_outcome("edit_image", &self.config.provider(), &outcome);
+        if !image_roles.is_empty() {
+            output = output.with_data(json!({ "roles": image_roles }));
+        }
+        mark_replay(&mut response_text, &mut output, replay);
+        Ok(output.into_result(response_text))
+    }
+
+    /// style_transfer 的实现，供工具调用和 regenerate 共用
+    async fn style_transfer_with(
+        &self,
+        args: StyleTransferArgs,
+        replay: Option<&Replay>,
+    ) -> Result<CallToolResult, McpError> {
+        if args.style_images.is_empty() {
+            return Err(McpError::invalid_params(tr!("style.no_style_images"), None));
+        }
+        self.ensure_image_readable(&args.image).await?;
+        for style_image in &args.style_images {
+            self.ensure_image_readable(style_image).await?;
+        }
+
+        let images: Vec<String> = std::iter::once(args.image.clone())
+            .chain(args.style_images.iter().cloned())
+            .collect();
+        let image_roles = style_transfer_roles(images.len());
+        let instruction =
+            roles::style_transfer_instruction(args.style_images.len(), args.prompt.as_deref());
+        let request = self
+            .image_request(
+                "style_transfer",
+                &instruction,
+                serde_json::to_value(&args).unwrap_or_default(),
+                replay,
+            )
+            .with_images(images.clone())
+            .with_image_roles(image_roles.clone())
+            .with_seed(args.seed)
+            .with_name_suffix("_styled");
+        let outcome = self.execute_image_request(request).await?;
+
+        let mut response_text = tr!("response.model", model = outcome.model);
+        push_image_roles(&mut response_text, &images, &image_roles);
+        if let Some(prompt) = &args.prompt {
+            response_text.push_str(&tr!("response.extra_prompt", prompt = prompt));
+        }
+        response_text.push_str(&tr!("response.text", text = outcome.text));
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        let mut output =
+            ToolOutput::from_outcome("style_transfer", &self.config.provider(), &outcome)
+                .with_data(json!({ "roles": image_roles }));
+        mark_replay(&mut response_text, &mut output, replay);
+        Ok(output.into_result(response_text))
+    }
+
+    /// 按工具和模型构造图像请求；重放时改用覆盖的模型并关联原始记录
+    fn image_request(
+        &self,
+        tool: &str,
+        prompt: &str,
+        arguments: Value,
+        replay: Option<&Replay>,
+    ) -> ImageRequest {
+        match replay {
+            Some(replay) => ImageRequest::new(tool, prompt, &replay.model, arguments)
+                .with_parent(Some(replay.parent.clone())),
+            None => ImageRequest::new(tool, prompt, &self.config.model, arguments),
+        }
+    }
+
+    /// create_variations 中一个变体的请求
+    fn variation_request(&self, variation: &Variation, replay: Option<&Replay>) -> ImageRequest {
+        let instruction = variations::variation_instruction(
+            variation.strength,
+            variation.hint,
+            variation.index,
+            variation.count,
+        );
+        let arguments = json!({
+            "instruction": instruction,
+            "images": [variation.image],
+            "seed": variation.seed,
+            "strength": variation.strength.as_str(),
+            "hint": variation.hint,
+            "variation_index": variation.index,
+            "variation_count": variation.count,
+        });
+        self.image_request("create_variations", &instruction, arguments, replay)
+            .with_images(vec![variation.image.to_string()])
+            .with_seed(variation.seed)
+            .with_name_suffix(format!("_var_{}", variation.index))
+    }
+
+    /// 重放 create_variations 中的一个变体，命名和变化程度与原变体一致
+    async fn replay_variation(
+        &self,
+        variation: &Variation<'_>,
+        replay: &Replay,
+    ) -> Result<CallToolResult, McpError> {
+        self.ensure_image_readable(variation.image).await?;
+        let request = self.variation_request(variation, Some(replay));
+        let outcome = self.execute_image_request(request).await?;
+
+        let mut response_text = tr!(
+            "response.variations",
+            image = display_image_input(variation.image),
+            model = outcome.model,
+            strength = variation.strength.as_str()
+        );
+        if let Some(hint) = variation.hint {
+            response_text.push_str(&tr!("response.variation_hint", hint = hint));
+        }
+        response_text.push_str(&tr!("response.text", text = outcome.text));
+        push_saved_images(&mut response_text, &outcome.images);
+        push_rephrased_prompt(&mut response_text, &outcome);
+        push_usage(&mut response_text, outcome.usage.as_ref());
+        response_text.push_str(&tr!("response.generation_id", id = outcome.generation_id));
+
+        let mut output =
+            ToolOutput::from_outcome("create_variations", &self.config.provider(), &outcome)
+                .with_data(json!({
+                    "strength": variation.strength.as_str(),
+                    "variation_index": variation.index,
+                }));
+        mark_replay(&mut response_text, &mut output, Some(replay));
+        Ok(output.into_result(response_text))
+    }
+
+    /// batch_generate 的实现，供工具调用和后台任务共用
+    pub(crate) async fn batch_generate_with(
+        &self,
+        args: BatchGenerateArgs,
+        progress: Option<BatchProgress>,
+        cancellation: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let prompts = match (args.prompts, args.template) {
+            (Some(prompts), None) => prompts,
+            (None, Some(template)) => {
+                batch::expand_template(&template, &args.variables.unwrap_or_default())
+                    .map_err(errors::invalid_params)?
+            }
+            _ => {
+                return Err(McpError::invalid_params(
+                    tr!("batch.prompts_or_template"),
+                    None,
+                ));
+            }
+        };
+        if prompts.is_empty() {
+            return Err(McpError::invalid_params(tr!("batch.empty_prompts"), None));
+        }
+        if prompts.len() > batch::MAX_BATCH_ITEMS {
+            return Err(ToolError::input_too_large(tr!(
+                "batch.too_many_prompts",
+                max = batch::MAX_BATCH_ITEMS,
+                count = prompts.len()
+            ))
+            .into());
+        }
+
+        let concurrency = args
+            .concurrency
+            .unwrap_or(self.config.batch_concurrency)
+            .clamp(1, self.config.batch_concurrency);
+        let requests = prompts
+            .iter()
+            .enumerate()
+            .map(|(offset, prompt)| {
+                let arguments = json!({
+                    "prompt": prompt,
+                    "seed": args.seed,
+                    "batch_index": offset + 1,
+                });
+                ImageRequest::new("batch_generate", prompt, &self.config.model, arguments)
+                    .with_seed(args.seed)
+            })
+            .collect();
+        let results = self
+            .run_batch(requests, concurrency, progress, cancellation)
+            .await;
+
+        let succeeded = results.iter().filter(|r| r.outcome.is_ok()).count();
+        let mut response_text = tr!(
+            "response.batch_summary",
+            succeeded = succeeded,
+            failed = results.len() - succeeded,
+            total = results.len(),
+            model = self.config.model,
+            concurrency = concurrency
+        );
+        let mut output = ToolOutput::new("batch_generate")
+            .with_model(self.config.model.clone())
+            .with_provider(self.config.provider())
+            .with_data(json!({
+                "succeeded": succeeded,
+                "failed": results.len() - succeeded,
+                "concurrency": concurrency,
+            }));
+        for result in &results {
+            let item_prompt = Some(result.prompt.clone());
+            output.items.push(match &result.outcome {
+                Ok(outcome) => ItemOutput::from_outcome(result.index, item_prompt, outcome),
+                Err(error) => ItemOutput::failed(result.index, item_prompt, error.clone()),
+            });
+            let prompt: String = result.prompt.chars().take(80).collect();
+            match &result.outcome {
+                Ok(outcome) => {
+                    response_text.push_str(&tr!(
+                        "response.batch_item_ok",
+                        index = result.index,
+                        prompt = prompt,
+                        id = outcome.generation_id
+                    ));
+                    for image in &outcome.images {
+                        match &image.saved_path {
+                            Some(path) => {
+                                response_text.push_str(&tr!("response.saved_to_item", path = path))
+                            }
+                            None => response_text.push_str(&tr!("response.not_saved_item")),
                         }
                     }
                 }
+                Err(error) => {
+                    response_text.push_str(&tr!(
+                        "response.batch_item_failed",
+                        index = result.index,
+                        prompt = prompt,
+                        error = error
+                    ));
+                }
             }
         }
 
-        let request_body = json!({
-            "model": model,
-            "messages": [{
-                "role": "user",
-                "content": content
-            }],
-            "max_tokens": 1000,
-            "temperature": 0.7
-        });
+        Ok(output.into_result(response_text))
+    }
 
-        match self.client.post(&url).json(&request_body).send().await {
-            Ok(response) => {
-                let status = response.status();
-                if !status.is_success() {
-                    let error_text = response
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "无法获取错误详情".to_string());
-                    return Err(McpError::internal_error(
-                        format!("API 请求失败，状态码: {}, 错误: {}", status, error_text),
-                        None,
-                    ));
-                }
+    /// 在后台任务中执行工具，返回工具响应的文本和结构化输出
+    pub(crate) async fn call_tool_for_job(
+        &self,
+        tool: &str,
+        arguments: Value,
+        cancellation: CancellationToken,
+    ) -> Result<(String, Option<Value>), McpError> {
+        let invalid = |e: serde_json::Error| McpError::invalid_params(e.to_string(), None);
+        let result = match tool {
+            "generate_image" => {
+                self.generate_image(Parameters(
+                    serde_json::from_value(arguments).map_err(invalid)?,
+                ))
+                .await?
+            }
+            "edit_image" => {
+                self.edit_image(Parameters(
+                    serde_json::from_value(arguments).map_err(invalid)?,
+                ))
+                .await?
+            }
+            "batch_generate" => {
+                self.batch_generate_with(
+                    serde_json::from_value(arguments).map_err(invalid)?,
+                    None,
+                    cancellation,
+                )
+                .await?
+            }
+            other => {
+                return Err(McpError::invalid_params(
+                    tr!("jobs.unsupported_tool", tool = other),
+                    None,
+                ));
+            }
+        };
 
-                match response.json::<serde_json::Value>().await {
-                    Ok(response_data) => {
-                        let (content, images_array) = extract_text_and_images(&response_data)?;
-
-                        let current_save_dir = {
-                            let save_dir = self.save_directory.read().await;
-                            save_dir.clone()
-                        };
-
-                        let base_filename = if !args.images.is_empty() {
-                            let first_image = &args.images[0];
-                            if !first_image.starts_with("http://")
-                                && !first_image.starts_with("https://")
-                                && !first_image.starts_with("data:image/")
-                            {
-                                Some(image_utils::extract_filename_without_extension(first_image))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        };
-
-                        let saved_images = image_utils::save_response_images(
-                            &images_array,
-                            Some(&current_save_dir),
-                            base_filename.as_deref(),
-                            true,
-                        );
-
-                        let mut response_text = format!(
-                            "**模型:** {}\n**指令:** {}\n**输入图像:** {} 张图像\n**响应:** {}",
-                            model,
-                            args.instruction,
-                            args.images.len(),
-                            content
-                        );
-                        if !images_array.is_empty() {
-                            response_text.push_str(&format!(
-                                "\n\n**生成的图像:** {} 张图像",
-                                images_array.len()
-                            ));
-                            for (index, img_info) in saved_images.iter().enumerate() {
-                                response_text.push_str(&format!(
-                                    "\n- 图像 {}: {}...",
-                                    index + 1,
-                                    &img_info.url[..std::cmp::min(50, img_info.url.len())]
-                                ));
-                                if let Some(saved_path) = &img_info.saved_path {
-                                    response_text
-                                        .push_str(&format!("\n  已保存到: {}", saved_path));
-                                }
-                            }
-                        }
+        let text = result
+            .content
+            .iter()
+            .filter_map(|content| content.as_text().map(|t| t.text.clone()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok((text, result.structured_content))
+    }
 
-                        if let Some(usage) = response_data.get("usage")
-                            && let (
-                                Some(prompt_tokens),
-                                Some(completion_tokens),
-                                Some(total_tokens),
-                            ) = (
-                                usage.get("prompt_tokens").and_then(|t| t.as_u64()),
-                                usage.get("completion_tokens").and_then(|t| t.as_u64()),
-                                usage.get("total_tokens").and_then(|t| t.as_u64()),
-                            )
-                        {
-                            response_text.push_str(&format!("\n\n**使用统计:**\n- 提示词tokens: {}\n- 完成tokens: {}\n- 总tokens: {}", prompt_tokens, completion_tokens, total_tokens));
-                        }
+    /// 获取任务管理器；任务数据库无法打开时返回错误
+    fn require_jobs(&self) -> Result<&JobManager, McpError> {
+        self.jobs
+            .as_ref()
+            .ok_or_else(|| McpError::invalid_request(tr!("jobs.unavailable"), None))
+    }
 
-                        Ok(CallToolResult::success(vec![Content::text(response_text)]))
-                    }
-                    Err(e) => Err(McpError::internal_error(
-                        format!("解析响应失败: {}", e),
-                        None,
-                    )),
-                }
+    async fn find_job(&self, job_id: &str) -> Result<JobRecord, McpError> {
+        self.require_jobs()?
+            .get(job_id)
+            .await
+            .map_err(|e| McpError::internal_error(tr!("jobs.query_failed", error = e), None))?
+            .ok_or_else(|| {
+                McpError::from(ToolError::input_not_found(tr!(
+                    "jobs.not_found",
+                    id = job_id
+                )))
+            })
+    }
+
+    /// 确认图像输入可以读取，避免为无效输入发出多次上游请求
+    async fn ensure_image_readable(&self, image_input: &str) -> Result<(), McpError> {
+        match image_utils::detect_and_process_image_input(image_input) {
+            Ok(_) => return Ok(()),
+            Err(e) => reject_too_large(e)?,
+        }
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        match image_utils::find_image_in_save_directory(image_input, &current_save_dir) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                reject_too_large(e)?;
+                Err(
+                    ToolError::input_not_found(tr!("imaging.read_failed", input = image_input))
+                        .into(),
+                )
+            }
+        }
+    }
+
+    /// 获取历史存储；历史功能被关闭时返回错误
+    fn require_history(&self) -> Result<&HistoryStore, McpError> {
+        self.history
+            .as_ref()
+            .ok_or_else(|| McpError::invalid_request(tr!("history.disabled"), None))
+    }
+
+    /// 将用户提供的图像输入解析为可直接发送给模型的 URL 或 data URL
+    ///
+    /// 依次尝试 URL / base64 / 本地路径，再到保存目录中查找；都失败时原样返回，
+    /// 只有输入超过大小上限时返回错误
+    pub(crate) async fn resolve_image_input(&self, image_input: &str) -> Result<String, McpError> {
+        match image_utils::detect_and_process_image_input(image_input) {
+            Ok(image_content) => return Ok(image_content.data),
+            Err(e) => reject_too_large(e)?,
+        }
+
+        let current_save_dir = {
+            let save_dir = self.save_directory.read().await;
+            save_dir.clone()
+        };
+        match image_utils::find_image_in_save_directory(image_input, &current_save_dir) {
+            Ok(image_content) => Ok(image_content.data),
+            Err(e) => {
+                reject_too_large(e)?;
+                Ok(image_input.to_string())
             }
-            Err(e) => Err(McpError::internal_error(format!("请求失败: {}", e), None)),
         }
     }
 }
 
-impl OpenRouterServer {
-    pub(crate) fn create_tool_router() -> rmcp::handler::server::router::tool::ToolRouter<Self> {
-        Self::tool_router()
+/// 输入超过大小上限时转换为工具错误，其余读取失败交给调用方处理
+fn reject_too_large(error: anyhow::Error) -> Result<(), McpError> {
+    match error.downcast::<ToolError>() {
+        Ok(error) if error.kind == ErrorKind::InputTooLarge => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+/// 提交任务前检查工具名和参数，尽早暴露错误
+fn validate_job_arguments(tool: &str, arguments: &Value) -> Result<(), McpError> {
+    let checked = match tool {
+        "generate_image" => {
+            serde_json::from_value::<GenerateImageArgs>(arguments.clone()).map(|_| ())
+        }
+        "edit_image" => serde_json::from_value::<EditImageArgs>(arguments.clone()).map(|_| ()),
+        "batch_generate" => {
+            serde_json::from_value::<BatchGenerateArgs>(arguments.clone()).map(|_| ())
+        }
+        other => {
+            return Err(McpError::invalid_params(
+                tr!(
+                    "jobs.unsupported_tool_with_list",
+                    tool = other,
+                    available = jobs::JOB_TOOLS.join(", ")
+                ),
+                None,
+            ));
+        }
+    };
+    checked.map_err(|e| {
+        McpError::invalid_params(tr!("jobs.invalid_arguments", tool = tool, error = e), None)
+    })
+}
+
+/// 格式化任务状态
+fn format_job_status(job: &JobRecord) -> String {
+    let mut text = tr!(
+        "response.job_status",
+        id = job.id,
+        tool = job.tool,
+        status = job.status,
+        created_at = job.created_at
+    );
+    if let Some(started_at) = &job.started_at {
+        text.push_str(&tr!("response.job_started", time = started_at));
+    }
+    if let Some(finished_at) = &job.finished_at {
+        text.push_str(&tr!("response.job_finished", time = finished_at));
+    }
+    if let Some(error) = &job.error {
+        text.push_str(&tr!("response.job_error", error = error));
+    }
+    if job.status == jobs::STATUS_SUCCEEDED {
+        text.push_str(&tr!("response.job_ready"));
+    }
+    text
+}
+
+/// 任务状态的结构化输出，不含任务结果本身
+fn job_output(tool: &str, job: &JobRecord) -> ToolOutput {
+    let mut data = serde_json::to_value(job).unwrap_or_default();
+    if let Some(data) = data.as_object_mut() {
+        data.remove("result");
+        data.remove("structured_result");
+    }
+    ToolOutput::new(tool).with_data(data)
+}
+
+/// 取出历史记录中可重放的输入图像
+///
+/// 早期记录没有 inputs 字段时退回到原始参数，已被截断的 base64 数据无法重放
+fn recorded_inputs(record: &HistoryRecord) -> Vec<String> {
+    if !record.inputs.is_empty() {
+        return record.inputs.clone();
+    }
+    record
+        .arguments
+        .get("images")
+        .and_then(|images| images.as_array())
+        .map(|images| {
+            images
+                .iter()
+                .filter_map(|image| image.as_str())
+                .filter(|image| !image.starts_with("data:"))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// 风格迁移的输入顺序：第一张为主体，其余为风格参考
+fn style_transfer_roles(image_count: usize) -> Vec<Option<ImageRole>> {
+    (0..image_count)
+        .map(|index| {
+            Some(if index == 0 {
+                ImageRole::Subject
+            } else {
+                ImageRole::StyleReference
+            })
+        })
+        .collect()
+}
+
+/// create_variations 中的一个变体
+struct Variation<'a> {
+    image: &'a str,
+    strength: VariationStrength,
+    hint: Option<&'a str>,
+    index: u32,
+    count: u32,
+    seed: Option<u64>,
+}
+
+/// 历史记录中 create_variations 一个变体的参数
+#[derive(Deserialize)]
+struct RecordedVariation {
+    strength: VariationStrength,
+    hint: Option<String>,
+    variation_index: u32,
+    /// 早期记录没有这一字段，此时按序号处理
+    variation_count: Option<u32>,
+    seed: Option<u64>,
+}
+
+/// 只接受一张输入图像的工具取出重放用的输入
+fn single_input(record: &HistoryRecord, images: Vec<String>) -> Result<String, McpError> {
+    match <[String; 1]>::try_from(images) {
+        Ok([image]) => Ok(image),
+        Err(images) if images.is_empty() => Err(McpError::invalid_params(
+            tr!("regenerate.no_inputs", id = record.id),
+            None,
+        )),
+        Err(images) => Err(McpError::invalid_params(
+            tr!(
+                "regenerate.single_input",
+                tool = record.tool,
+                count = images.len()
+            ),
+            None,
+        )),
+    }
+}
+
+/// 重放历史记录时，在响应和结构化输出中标出原始生成 ID
+fn mark_replay(response_text: &mut String, output: &mut ToolOutput, replay: Option<&Replay>) {
+    if let Some(replay) = replay {
+        response_text.push_str(&tr!("response.replay_of", parent = replay.parent));
+        output.parent_id = Some(replay.parent.clone());
+    }
+}
+
+/// 在响应中列出标注了角色的输入图像
+fn push_image_roles(response_text: &mut String, images: &[String], roles: &[Option<ImageRole>]) {
+    if roles.is_empty() {
+        return;
+    }
+    response_text.push_str(&tr!("response.image_roles"));
+    for (index, (image, role)) in images.iter().zip(roles).enumerate() {
+        response_text.push_str(&tr!(
+            "response.image_role",
+            index = index + 1,
+            role = role.map_or_else(
+                || tr!("response.role_unset"),
+                |role| role.as_str().to_string()
+            ),
+            image = display_image_input(image)
+        ));
+    }
+}
+
+/// 在响应中展示图像输入，base64 数据只保留开头部分
+pub(crate) fn display_image_input(image_input: &str) -> String {
+    if image_input.starts_with("data:") && image_input.len() > 50 {
+        format!("{}...", &image_input[..50])
+    } else {
+        image_input.to_string()
+    }
+}
+
+/// 在响应文本中追加已保存图像的列表
+fn push_saved_images(response_text: &mut String, images: &[ImageInfo]) {
+    if images.is_empty() {
+        return;
+    }
+    response_text.push_str(&tr!("response.generated_images", count = images.len()));
+    for (index, img_info) in images.iter().enumerate() {
+        response_text.push_str(&tr!(
+            "response.generated_image",
+            index = index + 1,
+            preview = &img_info.url[..std::cmp::min(50, img_info.url.len())]
+        ));
+        if let Some(saved_path) = &img_info.saved_path {
+            response_text.push_str(&tr!("response.saved_to_item", path = saved_path));
+            if img_info.deduplicated {
+                response_text.push_str(&tr!("response.deduplicated"));
+            }
+            if let Some(sidecar_path) = &img_info.sidecar_path {
+                response_text.push_str(&tr!("response.sidecar_item", path = sidecar_path));
+            }
+        } else {
+            response_text.push_str(&tr!("response.not_saved_item"));
+        }
+        if !img_info.debug_info.is_empty() {
+            response_text.push_str(&tr!("response.debug", info = img_info.debug_info));
+        }
+    }
+}
+
+/// 首次请求疑似被误拦截、改写提示词后重试成功时说明实际使用的提示词
+fn push_rephrased_prompt(response_text: &mut String, outcome: &ImageOutcome) {
+    if let Some(prompt) = &outcome.rephrased_prompt {
+        response_text.push_str(&tr!("response.rephrased_prompt", prompt = prompt));
+    }
+}
+
+/// 在响应文本中追加本地抠图的结果
+fn push_cutouts(response_text: &mut String, cutouts: &[Cutout]) {
+    if cutouts.is_empty() {
+        response_text.push_str(&tr!("response.no_image_for_cutout"));
+        return;
+    }
+    response_text.push_str(&tr!("response.transparent"));
+    for (index, cutout) in cutouts.iter().enumerate() {
+        match &cutout.result {
+            Ok(path) => response_text.push_str(&tr!(
+                "response.transparent_item",
+                index = index + 1,
+                path = path
+            )),
+            Err(error) => response_text.push_str(&tr!(
+                "response.transparent_failed",
+                index = index + 1,
+                source = cutout
+                    .source_path
+                    .clone()
+                    .unwrap_or_else(|| tr!("response.unsaved")),
+                error = error
+            )),
+        }
+    }
+}
+
+/// 在响应文本中追加会话的轮数和上下文大小
+fn push_session_status(response_text: &mut String, session: &EditSession, dropped: usize) {
+    response_text.push_str(&tr!(
+        "response.session_status",
+        turn = session.total_turns(),
+        max = session::MAX_SESSION_TURNS,
+        size = format!("{:.1}", session.bytes() as f64 / (1024.0 * 1024.0))
+    ));
+    if dropped > 0 {
+        response_text.push_str(&tr!(
+            "response.session_dropped",
+            max = session::MAX_SESSION_BYTES / (1024 * 1024),
+            count = dropped
+        ));
+    }
+}
+
+/// 在多轮编辑的输出中附加会话 ID 和轮数
+fn session_output(output: ToolOutput, session: &EditSession, dropped: usize) -> ToolOutput {
+    let mut output = output.with_data(json!({
+        "session_id": session.id,
+        "turn": session.total_turns(),
+        "max_turns": session::MAX_SESSION_TURNS,
+        "context_bytes": session.bytes(),
+    }));
+    if dropped > 0 {
+        output.warn(tr!(
+            "session.dropped",
+            max = session::MAX_SESSION_BYTES / (1024 * 1024),
+            count = dropped
+        ));
+    }
+    output
+}
+
+/// 在用量报告中追加一行累计用量
+fn push_usage_totals(response_text: &mut String, label: &str, totals: &UsageTotals) {
+    response_text.push_str(&tr!(
+        "response.usage_totals",
+        label = label,
+        calls = totals.calls,
+        prompt = totals.prompt_tokens,
+        completion = totals.completion_tokens,
+        total = totals.total_tokens,
+        cost = format!("{:.6}", totals.cost)
+    ));
+    if totals.unpriced_calls > 0 {
+        response_text.push_str(&tr!(
+            "response.usage_unpriced",
+            count = totals.unpriced_calls
+        ));
+    }
+}
+
+/// 在响应文本中追加 token 使用统计
+fn push_usage(response_text: &mut String, usage: Option<&Value>) {
+    if let Some(usage) = usage
+        && let (Some(prompt_tokens), Some(completion_tokens), Some(total_tokens)) = (
+            usage.get("prompt_tokens").and_then(|t| t.as_u64()),
+            usage.get("completion_tokens").and_then(|t| t.as_u64()),
+            usage.get("total_tokens").and_then(|t| t.as_u64()),
+        )
+    {
+        response_text.push_str(&tr!(
+            "response.usage",
+            prompt = prompt_tokens,
+            completion = completion_tokens,
+            total = total_tokens
+        ));
+        if let Some(cost) = usage.get("cost").and_then(Value::as_f64) {
+            let source = usage
+                .get("cost_source")
+                .and_then(|source| serde_json::from_value::<CostSource>(source.clone()).ok())
+                .unwrap_or(CostSource::Upstream);
+            response_text.push_str(&tr!(
+                "response.usage_cost",
+                cost = format!("{:.6}", cost),
+                source = source.label()
+            ));
+        }
     }
 }
 
 /// 从 markdown 文本中提取嵌入的 base64 图像，并返回清理后的文本
 /// 匹配格式: ![...](data:image/...;base64,...)
 /// 返回: (清理后的文本, 提取的图片URLs)
+#[allow(clippy::while_let_loop)]
 fn extract_images_from_markdown(text: &str) -> (String, Vec<String>) {
     let mut images = Vec::new();
     let mut cleaned_text = text.to_string();
@@ -341,7 +2601,7 @@ fn extract_images_from_markdown(text: &str) -> (String, Vec<String>) {
                 }
             }
             // 如果没匹配到完整的 markdown 图片，跳过这个 ![
-            cleaned_text.replace_range(start_idx..start_idx+2, "");
+            cleaned_text.replace_range(start_idx..start_idx + 2, "");
         } else {
             break;
         }
@@ -351,46 +2611,54 @@ fn extract_images_from_markdown(text: &str) -> (String, Vec<String>) {
 }
 
 /// 从 OpenRouter/Gemini 等兼容响应中提取文本和图像
-fn extract_text_and_images(response: &Value) -> Result<(String, Vec<Value>), McpError> {
+pub(crate) fn extract_text_and_images(response: &Value) -> Result<(String, Vec<Value>), McpError> {
     // 1) 规范错误字段
     if let Some(error) = response.get("error") {
         let error_message = error
             .get("message")
             .and_then(|m| m.as_str())
-            .unwrap_or("未知错误");
-        return Err(McpError::internal_error(
-            format!("API 返回错误: {}", error_message),
-            None,
-        ));
+            .map_or_else(|| tr!("api.unknown_error"), str::to_string);
+        let message = tr!("api.error", message = error_message);
+        // OpenRouter 在响应体中沿用 HTTP 状态码作为错误码
+        let error = match error.get("code").and_then(Value::as_u64) {
+            Some(code) => ToolError::from_status(code as u16, message),
+            None => ToolError::upstream_unavailable(message),
+        };
+        return Err(error
+            .with_finish(FinishStatus::from_response(response))
+            .into());
+    }
+
+    // Gemini 在提示词被拦截时不返回任何候选
+    let finish = FinishStatus::from_response(response);
+    if finish.block_reason.is_some() {
+        return Err(finish.no_image_error(&tr!("api.no_content")).into());
     }
 
     // 2) 提取第一条消息（兼容 choices / candidates）
     let message = if let Some(choices) = response.get("choices").and_then(|c| c.as_array()) {
         if choices.is_empty() {
-            return Err(McpError::internal_error(
-                "API 响应中 'choices' 数组为空".to_string(),
-                None,
-            ));
+            return Err(McpError::from(ToolError::upstream_unavailable(tr!(
+                "api.empty_choices"
+            ))));
         }
         choices[0].get("message").ok_or_else(|| {
-            McpError::internal_error("响应格式无效: choices[0].message 缺失".to_string(), None)
+            McpError::from(ToolError::upstream_unavailable(tr!("api.missing_message")))
         })?
     } else if let Some(candidates) = response.get("candidates").and_then(|c| c.as_array()) {
         // Gemini 风格
         if candidates.is_empty() {
-            return Err(McpError::internal_error(
-                "API 响应中 'candidates' 数组为空".to_string(),
-                None,
-            ));
+            return Err(McpError::from(ToolError::upstream_unavailable(tr!(
+                "api.empty_candidates"
+            ))));
         }
         candidates[0].get("content").ok_or_else(|| {
-            McpError::internal_error("响应格式无效: candidates[0].content 缺失".to_string(), None)
+            McpError::from(ToolError::upstream_unavailable(tr!("api.missing_content")))
         })?
     } else {
-        return Err(McpError::internal_error(
-            "响应格式无效: 未找到 choices 或 candidates".to_string(),
-            None,
-        ));
+        return Err(McpError::from(ToolError::upstream_unavailable(tr!(
+            "api.no_choices"
+        ))));
     };
 
     // 3) 统一提取 content/parts 字段
@@ -431,6 +2699,7 @@ fn extract_text_and_images(response: &Value) -> Result<(String, Vec<Value>), Mcp
                             }
                         }
                     }
+                    #[allow(clippy::collapsible_match)]
                     "image_url" => {
                         if part.get("image_url").is_some() {
                             images.push(json!({ "image_url": part.get("image_url").cloned().unwrap_or_default() }));
@@ -465,7 +2734,7 @@ fn extract_text_and_images(response: &Value) -> Result<(String, Vec<Value>), Mcp
     }
 
     let merged_text = if texts.is_empty() {
-        "无内容".to_string()
+        tr!("api.no_content")
     } else {
         texts.join("\n")
     };
@@ -0,0 +1,461 @@
+use crate::{i18n::tr, imaging};
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, RgbaImage, imageops::FilterType};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+
+/// 单次请求允许的最大操作数
+pub const MAX_TRANSFORM_OPS: usize = 32;
+/// resize 的 scale 上限
+pub const MAX_RESIZE_SCALE: f64 = 8.0;
+/// letterbox 填充的默认颜色
+pub const DEFAULT_PAD_COLOR: &str = "#000000";
+
+/// 翻转方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FlipDirection {
+    /// 左右翻转
+    Horizontal,
+    /// 上下翻转
+    Vertical,
+}
+
+/// 一个本地图像操作，按列表顺序依次执行
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformOp {
+    /// 裁剪：给出矩形（x、y 默认 0），或给出宽高比（如 "16:9"）从中心裁剪
+    Crop {
+        x: Option<u32>,
+        y: Option<u32>,
+        width: Option<u32>,
+        height: Option<u32>,
+        aspect: Option<String>,
+    },
+    /// 缩放：给出 scale，或给出宽/高（只给一个时按比例计算另一个；都给出时缩放到框内，exact 为 true 时拉伸到精确尺寸）
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+        scale: Option<f64>,
+        exact: Option<bool>,
+    },
+    /// 顺时针旋转 90 / 180 / 270 度（负数表示逆时针）
+    Rotate { degrees: i32 },
+    /// 翻转
+    Flip { direction: FlipDirection },
+    /// 填充：四周各增加 padding 像素，或以 letterbox 方式补齐到宽高比（如 "1:1"）；color 默认 #000000
+    Pad {
+        padding: Option<u32>,
+        aspect: Option<String>,
+        color: Option<String>,
+    },
+}
+
+impl TransformOp {
+    /// 用于响应中展示的简短描述
+    pub fn summary(&self) -> String {
+        match self {
+            TransformOp::Crop {
+                aspect: Some(aspect),
+                ..
+            } => format!("crop {}", aspect),
+            TransformOp::Crop {
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => format!(
+                "crop {}x{}+{}+{}",
+                width.unwrap_or(0),
+                height.unwrap_or(0),
+                x.unwrap_or(0),
+                y.unwrap_or(0)
+            ),
+            TransformOp::Resize {
+                scale: Some(scale), ..
+            } => format!("resize ×{}", scale),
+            TransformOp::Resize {
+                width,
+                height,
+                exact,
+                ..
+            } => format!(
+                "resize {}x{}{}",
+                width.map_or("auto".to_string(), |w| w.to_string()),
+                height.map_or("auto".to_string(), |h| h.to_string()),
+                if exact.unwrap_or(false) { " exact" } else { "" }
+            ),
+            TransformOp::Rotate { degrees } => format!("rotate {}°", degrees),
+            TransformOp::Flip { direction } => match direction {
+                FlipDirection::Horizontal => "flip horizontal".to_string(),
+                FlipDirection::Vertical => "flip vertical".to_string(),
+            },
+            TransformOp::Pad {
+                aspect: Some(aspect),
+                ..
+            } => format!("pad {}", aspect),
+            TransformOp::Pad { padding, .. } => format!("pad {}px", padding.unwrap_or(0)),
+        }
+    }
+
+    /// 对图像执行这一操作
+    pub fn apply(&self, image: DynamicImage) -> Result<DynamicImage> {
+        let (width, height) = (image.width(), image.height());
+        match self {
+            TransformOp::Crop {
+                x,
+                y,
+                width: crop_width,
+                height: crop_height,
+                aspect,
+            } => {
+                let (x, y, crop_width, crop_height) = match (aspect, crop_width, crop_height) {
+                    (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                        return Err(anyhow!(tr!("transform.crop_aspect_and_size")));
+                    }
+                    (Some(aspect), None, None) => {
+                        let target = parse_aspect(aspect)?;
+                        let (w, h) = if f64::from(width) / f64::from(height) > target {
+                            ((f64::from(height) * target).round() as u32, height)
+                        } else {
+                            (width, (f64::from(width) / target).round() as u32)
+                        };
+                        let (w, h) = (w.clamp(1, width), h.clamp(1, height));
+                        ((width - w) / 2, (height - h) / 2, w, h)
+                    }
+                    (None, Some(w), Some(h)) => (x.unwrap_or(0), y.unwrap_or(0), *w, *h),
+                    (None, _, _) => {
+                        return Err(anyhow!(tr!("transform.crop_needs_size")));
+                    }
+                };
+                if crop_width == 0
+                    || crop_height == 0
+                    || u64::from(x) + u64::from(crop_width) > u64::from(width)
+                    || u64::from(y) + u64::from(crop_height) > u64::from(height)
+                {
+                    return Err(anyhow!(tr!(
+                        "transform.crop_out_of_bounds",
+                        width = crop_width,
+                        height = crop_height,
+                        x = x,
+                        y = y,
+                        image_width = width,
+                        image_height = height
+                    )));
+                }
+                Ok(image.crop_imm(x, y, crop_width, crop_height))
+            }
+            TransformOp::Resize {
+                width: target_width,
+                height: target_height,
+                scale,
+                exact,
+            } => {
+                let (w, h) = match (scale, target_width, target_height) {
+                    (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                        return Err(anyhow!(tr!("transform.resize_scale_and_size")));
+                    }
+                    (Some(scale), None, None) => {
+                        if !(*scale > 0.0 && *scale <= MAX_RESIZE_SCALE) {
+                            return Err(anyhow!(tr!(
+                                "transform.scale_range",
+                                max = MAX_RESIZE_SCALE,
+                                value = scale
+                            )));
+                        }
+                        (
+                            (f64::from(width) * scale).round().max(1.0) as u32,
+                            (f64::from(height) * scale).round().max(1.0) as u32,
+                        )
+                    }
+                    (None, Some(w), Some(h)) if exact.unwrap_or(false) => (*w, *h),
+                    (None, Some(w), Some(h)) => fit_within(width, height, *w, *h),
+                    (None, Some(w), None) => (*w, scaled(height, *w, width)),
+                    (None, None, Some(h)) => (scaled(width, *h, height), *h),
+                    (None, None, None) => {
+                        return Err(anyhow!(tr!("transform.resize_needs_size")));
+                    }
+                };
+                imaging::ensure_output_size(w, h)?;
+                Ok(image.resize_exact(w, h, FilterType::Lanczos3))
+            }
+            TransformOp::Rotate { degrees } => match degrees.rem_euclid(360) {
+                0 => Ok(image),
+                90 => Ok(image.rotate90()),
+                180 => Ok(image.rotate180()),
+                270 => Ok(image.rotate270()),
+                _ => Err(anyhow!(tr!("transform.rotate_multiple", value = degrees))),
+            },
+            TransformOp::Flip { direction } => Ok(match direction {
+                FlipDirection::Horizontal => image.fliph(),
+                FlipDirection::Vertical => image.flipv(),
+            }),
+            TransformOp::Pad {
+                padding,
+                aspect,
+                color,
+            } => {
+                let color = imaging::parse_color(color.as_deref().unwrap_or(DEFAULT_PAD_COLOR))?;
+                let (new_width, new_height) = match (padding, aspect) {
+                    (Some(_), Some(_)) => {
+                        return Err(anyhow!(tr!("transform.pad_padding_and_aspect")));
+                    }
+                    (Some(padding), None) => (
+                        width.saturating_add(padding.saturating_mul(2)),
+                        height.saturating_add(padding.saturating_mul(2)),
+                    ),
+                    (None, Some(aspect)) => {
+                        let target = parse_aspect(aspect)?;
+                        if f64::from(width) / f64::from(height) < target {
+                            ((f64::from(height) * target).round() as u32, height)
+                        } else {
+                            (width, (f64::from(width) / target).round() as u32)
+                        }
+                    }
+                    (None, None) => return Err(anyhow!(tr!("transform.pad_needs_target"))),
+                };
+                let (new_width, new_height) = (new_width.max(width), new_height.max(height));
+                imaging::ensure_output_size(new_width, new_height)?;
+                let mut canvas = RgbaImage::from_pixel(new_width, new_height, color);
+                image::imageops::overlay(
+                    &mut canvas,
+                    &image.to_rgba8(),
+                    i64::from((new_width - width) / 2),
+                    i64::from((new_height - height) / 2),
+                );
+                Ok(DynamicImage::ImageRgba8(canvas))
+            }
+        }
+    }
+}
+
+/// 依次执行所有操作
+pub fn apply_all(image: DynamicImage, ops: &[TransformOp]) -> Result<DynamicImage> {
+    ops.iter()
+        .enumerate()
+        .try_fold(image, |image, (index, op)| {
+            op.apply(image).map_err(|e| {
+                let message = tr!(
+                    "transform.op_failed",
+                    index = index + 1,
+                    op = op.summary(),
+                    error = e
+                );
+                // 保留内部错误的类别（如输出尺寸超限）
+                e.context(message)
+            })
+        })
+}
+
+/// 解析宽高比：支持 "16:9"、"16/9" 和 "1.78"
+pub fn parse_aspect(value: &str) -> Result<f64> {
+    let value = value.trim();
+    let ratio = match value.split_once([':', '/']) {
+        Some((w, h)) => {
+            let w: f64 = w
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!(tr!("transform.invalid_aspect", value = value)))?;
+            let h: f64 = h
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!(tr!("transform.invalid_aspect", value = value)))?;
+            w / h
+        }
+        None => value
+            .parse()
+            .map_err(|_| anyhow!(tr!("transform.invalid_aspect", value = value)))?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(anyhow!(tr!("transform.invalid_aspect", value = value)))
+    }
+}
+
+/// 按比例缩放到给定框内
+fn fit_within(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let ratio =
+        (f64::from(max_width) / f64::from(width)).min(f64::from(max_height) / f64::from(height));
+    (
+        (f64::from(width) * ratio).round().max(1.0) as u32,
+        (f64::from(height) * ratio).round().max(1.0) as u32,
+    )
+}
+
+/// 另一条边按 `target / original` 等比缩放后的长度
+fn scaled(length: u32, target: u32, original: u32) -> u32 {
+    ((u64::from(length) * u64::from(target)) as f64 / f64::from(original.max(1)))
+        .round()
+        .max(1.0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ErrorKind, ToolError};
+    use serde_json::json;
+
+    fn op(value: serde_json::Value) -> TransformOp {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+    }
+
+    fn size(image: &DynamicImage) -> (u32, u32) {
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn ops_are_parsed_by_tag() {
+        assert!(matches!(
+            op(json!({"op": "crop", "width": 10, "height": 20})),
+            TransformOp::Crop {
+                x: None,
+                width: Some(10),
+                height: Some(20),
+                ..
+            }
+        ));
+        assert!(matches!(
+            op(json!({"op": "rotate", "degrees": -90})),
+            TransformOp::Rotate { degrees: -90 }
+        ));
+        // 拼错的字段和未知操作都应报错，而不是被静默忽略
+        for invalid in [
+            json!({"op": "crop", "widht": 10}),
+            json!({"op": "blur", "radius": 2}),
+            json!({"width": 10}),
+            json!({"op": "flip", "direction": "diagonal"}),
+        ] {
+            assert!(serde_json::from_value::<TransformOp>(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn aspects_are_parsed() {
+        assert!((parse_aspect("16:9").unwrap() - 16.0 / 9.0).abs() < 1e-9);
+        assert!((parse_aspect(" 4 / 3 ").unwrap() - 4.0 / 3.0).abs() < 1e-9);
+        assert_eq!(parse_aspect("1.5").unwrap(), 1.5);
+        for invalid in ["", "abc", "0:1", "1:0", "-1:1", "inf", "NaN"] {
+            assert!(parse_aspect(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn crop_by_rectangle_and_aspect() {
+        let cropped = op(json!({"op": "crop", "x": 10, "y": 5, "width": 30, "height": 20}))
+            .apply(image(100, 50))
+            .unwrap();
+        assert_eq!(size(&cropped), (30, 20));
+
+        // 宽高比裁剪保留中心区域的最大矩形
+        let square = op(json!({"op": "crop", "aspect": "1:1"}))
+            .apply(image(400, 300))
+            .unwrap();
+        assert_eq!(size(&square), (300, 300));
+        let wide = op(json!({"op": "crop", "aspect": "16:9"}))
+            .apply(image(300, 300))
+            .unwrap();
+        assert_eq!(size(&wide), (300, 169));
+        // 极端比例也至少保留 1 像素
+        let sliver = op(json!({"op": "crop", "aspect": "1000:1"}))
+            .apply(image(10, 10))
+            .unwrap();
+        assert_eq!(size(&sliver), (10, 1));
+    }
+
+    #[test]
+    fn invalid_crops_are_rejected() {
+        for invalid in [
+            json!({"op": "crop", "aspect": "1:1", "width": 10}),
+            json!({"op": "crop", "width": 10}),
+            json!({"op": "crop"}),
+            json!({"op": "crop", "width": 0, "height": 10}),
+            json!({"op": "crop", "x": 91, "width": 10, "height": 10}),
+            json!({"op": "crop", "y": 4294967295u32, "width": 10, "height": 10}),
+        ] {
+            assert!(
+                op(invalid.clone()).apply(image(100, 50)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        // 正好贴边的矩形是合法的
+        assert!(
+            op(json!({"op": "crop", "x": 90, "y": 40, "width": 10, "height": 10}))
+                .apply(image(100, 50))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn resize_computes_target_size() {
+        let cases = [
+            (json!({"op": "resize", "scale": 0.5}), (50, 25)),
+            (json!({"op": "resize", "scale": 0.001}), (1, 1)),
+            (json!({"op": "resize", "width": 40}), (40, 20)),
+            (json!({"op": "resize", "height": 10}), (20, 10)),
+            (json!({"op": "resize", "width": 40, "height": 40}), (40, 20)),
+            (
+                json!({"op": "resize", "width": 40, "height": 40, "exact": true}),
+                (40, 40),
+            ),
+        ];
+        for (resize, expected) in cases {
+            let resized = op(resize.clone()).apply(image(100, 50)).unwrap();
+            assert_eq!(size(&resized), expected, "{}", resize);
+        }
+    }
+
+    #[test]
+    fn invalid_resizes_are_rejected() {
+        for invalid in [
+            json!({"op": "resize"}),
+            json!({"op": "resize", "scale": 2.0, "width": 10}),
+            json!({"op": "resize", "scale": 0.0}),
+            json!({"op": "resize", "scale": -1.0}),
+            json!({"op": "resize", "scale": MAX_RESIZE_SCALE + 0.1}),
+            json!({"op": "resize", "width": 0}),
+        ] {
+            assert!(
+                op(invalid.clone()).apply(image(100, 50)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+
+        let error = op(json!({"op": "resize", "width": 100000, "height": 100000, "exact": true}))
+            .apply(image(10, 10))
+            .unwrap_err();
+        assert_eq!(
+            error.downcast::<ToolError>().unwrap().kind,
+            ErrorKind::InputTooLarge
+        );
+    }
+
+    #[test]
+    fn rotate_accepts_only_right_angles() {
+        let rotated = op(json!({"op": "rotate", "degrees": -90}))
+            .apply(image(100, 50))
+            .unwrap();
+        assert_eq!(size(&rotated), (50, 100));
+        assert_eq!(
+            size(
+                &op(json!({"op": "rotate", "degrees": 540}))
+                    .apply(image(100, 50))
+                    .unwrap()
+            ),
+            (100, 50)
+        );
+        assert!(
+            op(json!({"op": "rotate", "degrees": 45}))
+                .apply(image(100, 50))
+                .is_err()
+        );
+    }
+}
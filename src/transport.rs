@@ -1,3 +1,4 @@
+use crate::i18n::tr;
 use crate::server::OpenRouterServer;
 use anyhow::Result;
 use rmcp::{service::ServiceExt, transport::stdio};
@@ -27,12 +28,12 @@ pub async fn run_sse(handler: OpenRouterServer) -> Result<()> {
     let keep_alive = config.sse_keep_alive_secs.map(Duration::from_secs);
 
     println!();
-    println!("🚀 OpenRouter MCP Server (Rust) SSE 模式已启动!");
-    println!("🔗 MCP 端点: http://{}/mcp", bind_address);
-    println!("⏹️  按 Ctrl+C 停止服务器");
+    println!("{}", tr!("transport.sse_started"));
+    println!("{}", tr!("transport.endpoint", address = bind_address));
+    println!("{}", tr!("transport.stop_hint"));
     println!();
     if let Some(seconds) = keep_alive.map(|d| d.as_secs()) {
-        println!("📡 SSE keep-alive 已启用，间隔 {} 秒", seconds);
+        println!("{}", tr!("transport.keep_alive", seconds = seconds));
     }
 
     let server_config = SseServerConfig {
@@ -66,8 +67,8 @@ pub async fn run_sse(handler: OpenRouterServer) -> Result<()> {
         }
     });
 
-    let cancel_token = sse_server.with_service(move || handler.clone());
-    println!("🌐 CORS 已启用，支持跨域访问");
+    let cancel_token = sse_server.with_service(move || handler.new_session());
+    println!("{}", tr!("transport.cors"));
     tokio::signal::ctrl_c().await?;
     cancel_token.cancel();
     Ok(())
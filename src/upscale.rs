@@ -0,0 +1,183 @@
+use crate::{
+    errors::{self, ToolError},
+    generation::{DEFAULT_MAX_TOKENS, DEFAULT_TEMPERATURE},
+    i18n::tr,
+    imaging,
+    safety::FinishStatus,
+    server::OpenRouterServer,
+    tools::extract_text_and_images,
+};
+use anyhow::{Result, anyhow};
+use image::{DynamicImage, imageops::FilterType};
+use rmcp::{ErrorData as McpError, schemars};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::str::FromStr;
+
+/// 支持的放大倍数
+pub const UPSCALE_FACTORS: &[u32] = &[2, 4];
+/// 默认的放大倍数
+pub const DEFAULT_UPSCALE_FACTOR: u32 = 2;
+
+/// 锐化的阈值，避免放大平坦区域的噪点
+const SHARPEN_THRESHOLD: i32 = 2;
+
+/// 放大图像使用的后端
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum UpscaleBackend {
+    /// 本地 Lanczos 重采样加锐化，不调用模型
+    #[default]
+    Local,
+    /// 由上游图像模型重绘细节，再重采样到精确的目标尺寸
+    Model,
+}
+
+impl UpscaleBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpscaleBackend::Local => "local",
+            UpscaleBackend::Model => "model",
+        }
+    }
+}
+
+impl FromStr for UpscaleBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "local" => Ok(UpscaleBackend::Local),
+            "model" => Ok(UpscaleBackend::Model),
+            other => Err(anyhow!(tr!("upscale.invalid_backend", value = other))),
+        }
+    }
+}
+
+/// 放大的结果
+#[derive(Debug)]
+pub struct UpscaleOutcome {
+    pub backend: UpscaleBackend,
+    /// 使用模型后端时的模型名称
+    pub model: Option<String>,
+    pub image: DynamicImage,
+    pub usage: Option<Value>,
+}
+
+/// 本地放大：Lanczos3 重采样到目标尺寸后做 unsharp mask 锐化
+pub fn upscale_local(image: &DynamicImage, factor: u32, sharpen: bool) -> Result<DynamicImage> {
+    let (width, height) = target_size(image, factor)?;
+    let resized = image.resize_exact(width, height, FilterType::Lanczos3);
+    if sharpen {
+        Ok(resized.unsharpen(0.5 * factor as f32, SHARPEN_THRESHOLD))
+    } else {
+        Ok(resized)
+    }
+}
+
+/// 放大后的目标尺寸
+pub fn target_size(image: &DynamicImage, factor: u32) -> Result<(u32, u32)> {
+    let width = image
+        .width()
+        .checked_mul(factor)
+        .ok_or_else(|| ToolError::input_too_large(tr!("upscale.too_large")))?;
+    let height = image
+        .height()
+        .checked_mul(factor)
+        .ok_or_else(|| ToolError::input_too_large(tr!("upscale.too_large")))?;
+    imaging::ensure_output_size(width, height)?;
+    Ok((width, height))
+}
+
+fn model_instruction(width: u32, height: u32) -> String {
+    format!(
+        "Upscale this image to {}x{} pixels. Reproduce it exactly with sharper, finer detail: \
+         do not change the composition, subject, colors, text or style, and do not add or remove \
+         anything. Return only the upscaled image.",
+        width, height
+    )
+}
+
+impl OpenRouterServer {
+    /// 按配置（或调用时指定）的后端放大图像
+    pub(crate) async fn upscale(
+        &self,
+        image_input: &str,
+        image: DynamicImage,
+        factor: u32,
+        backend: UpscaleBackend,
+        sharpen: bool,
+    ) -> Result<UpscaleOutcome, McpError> {
+        let (width, height) = target_size(&image, factor).map_err(errors::invalid_params)?;
+        match backend {
+            UpscaleBackend::Local => {
+                let upscaled =
+                    tokio::task::spawn_blocking(move || upscale_local(&image, factor, sharpen))
+                        .await
+                        .map_err(|e| {
+                            McpError::internal_error(tr!("upscale.task_failed", error = e), None)
+                        })?
+                        .map_err(errors::invalid_params)?;
+                Ok(UpscaleOutcome {
+                    backend,
+                    model: None,
+                    image: upscaled,
+                    usage: None,
+                })
+            }
+            UpscaleBackend::Model => {
+                let model = self.config.upscale_model.clone();
+                let resolved = self.resolve_image_input(image_input).await?;
+                let request_body = json!({
+                    "model": model,
+                    "messages": [{
+                        "role": "user",
+                        "content": [
+                            {"type": "text", "text": model_instruction(width, height)},
+                            {"type": "image_url", "image_url": {"url": resolved}}
+                        ]
+                    }],
+                    "max_tokens": DEFAULT_MAX_TOKENS,
+                    "temperature": DEFAULT_TEMPERATURE
+                });
+                let response_data = self.send_chat_completion(&request_body).await?;
+                let (text, images) = extract_text_and_images(&response_data)?;
+                let url = images
+                    .first()
+                    .and_then(|image| image.pointer("/image_url/url"))
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| {
+                        let finish = FinishStatus::from_response(&response_data);
+                        let error = if finish.is_blocked() {
+                            finish.no_image_error(&text)
+                        } else {
+                            ToolError::no_image(tr!("upscale.no_image", model = model, text = text))
+                                .with_finish(finish)
+                        };
+                        McpError::from(error)
+                    })?;
+                let upscaled = self.load_image(url).await?;
+                // 模型输出的尺寸不一定精确，统一重采样到目标尺寸
+                let upscaled = if upscaled.width() == width && upscaled.height() == height {
+                    upscaled
+                } else {
+                    tokio::task::spawn_blocking(move || {
+                        upscaled.resize_exact(width, height, FilterType::Lanczos3)
+                    })
+                    .await
+                    .map_err(|e| {
+                        McpError::internal_error(tr!("upscale.task_failed", error = e), None)
+                    })?
+                };
+                Ok(UpscaleOutcome {
+                    backend,
+                    model: Some(model),
+                    image: upscaled,
+                    usage: response_data.get("usage").cloned(),
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    history::timestamp_now, metadata, pricing::CostSource, server::OpenRouterServer,
+    sqlite::Database,
+};
+use anyhow::Result;
+use rusqlite::{params, params_from_iter};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::path::Path;
+
+/// 默认的用量数据库文件（相对保存目录）
+pub const DEFAULT_USAGE_FILE: &str = ".nano-banana/usage.sqlite3";
+
+/// 每个分组默认返回的行数
+pub const DEFAULT_REPORT_LIMIT: u32 = 30;
+
+/// 每个分组最多返回的行数
+pub const MAX_REPORT_LIMIT: u32 = 366;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS usage_events (
+    id                INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at        TEXT NOT NULL,
+    day               TEXT NOT NULL,
+    session_id        TEXT NOT NULL,
+    api_key_id        TEXT NOT NULL,
+    model             TEXT NOT NULL,
+    prompt_tokens     INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    total_tokens      INTEGER NOT NULL,
+    cost              REAL,
+    cost_source       TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_usage_events_created_at ON usage_events(created_at);
+CREATE INDEX IF NOT EXISTS idx_usage_events_session_id ON usage_events(session_id);
+";
+
+/// 一次上游调用的用量
+#[derive(Debug, Clone)]
+pub struct UsageEvent {
+    pub session_id: String,
+    pub api_key_id: String,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost: Option<f64>,
+    pub cost_source: Option<CostSource>,
+}
+
+/// usage_report 的过滤条件
+#[derive(Debug, Default)]
+pub struct UsageQuery {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub session_id: Option<String>,
+    pub limit: u32,
+}
+
+/// 一组调用的累计用量
+#[derive(Debug, Default, Serialize)]
+pub struct UsageTotals {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// 已知费用之和（美元）
+    pub cost: f64,
+    /// 没有费用信息的调用数，这些调用不计入 `cost`
+    pub unpriced_calls: u64,
+}
+
+/// 按某一维度分组的累计用量
+#[derive(Debug, Serialize)]
+pub struct UsageGroup {
+    pub key: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// 用量报告：总计，以及按日期（UTC）、会话和 API 密钥分组的累计
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub totals: UsageTotals,
+    pub by_day: Vec<UsageGroup>,
+    pub by_session: Vec<UsageGroup>,
+    pub by_api_key: Vec<UsageGroup>,
+}
+
+/// 基于 SQLite 的用量记录，按次记录每个上游调用的 token 和费用
+#[derive(Clone)]
+pub struct UsageLedger {
+    db: Database,
+}
+
+impl UsageLedger {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            db: Database::open(path, SCHEMA, &[])?,
+        })
+    }
+
+    pub async fn insert(&self, event: UsageEvent) -> Result<()> {
+        self.db
+            .with_conn(move |conn| {
+                let created_at = timestamp_now();
+                let day = created_at[..10].to_string();
+                conn.execute(
+                    "INSERT INTO usage_events (created_at, day, session_id, api_key_id, model,
+                     prompt_tokens, completion_tokens, total_tokens, cost, cost_source)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        created_at,
+                        day,
+                        event.session_id,
+                        event.api_key_id,
+                        event.model,
+                        event.prompt_tokens as i64,
+                        event.completion_tokens as i64,
+                        event.total_tokens as i64,
+                        event.cost,
+                        event.cost_source.map(|source| source.as_str()),
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn report(&self, query: UsageQuery) -> Result<UsageReport> {
+        self.db
+            .with_conn(move |conn| {
+                let mut filter = String::from(" WHERE 1 = 1");
+                let mut values: Vec<String> = Vec::new();
+                if let Some(since) = &query.since {
+                    filter.push_str(" AND created_at >= ?");
+                    values.push(since.clone());
+                }
+                if let Some(until) = &query.until {
+                    filter.push_str(" AND created_at <= ?");
+                    values.push(until.clone());
+                }
+                if let Some(session_id) = &query.session_id {
+                    filter.push_str(" AND session_id = ?");
+                    values.push(session_id.clone());
+                }
+                let limit = query.limit.clamp(1, MAX_REPORT_LIMIT);
+
+                let totals = conn.query_row(
+                    &format!("SELECT {} FROM usage_events{}", TOTALS_COLUMNS, filter),
+                    params_from_iter(values.iter()),
+                    |row| totals_from_row(row, 0),
+                )?;
+                let group = |column: &str, order: &str| -> Result<Vec<UsageGroup>> {
+                    let sql = format!(
+                        "SELECT {column}, {TOTALS_COLUMNS} FROM usage_events{filter}
+                     GROUP BY {column} ORDER BY {order} LIMIT {limit}"
+                    );
+                    let mut stmt = conn.prepare(&sql)?;
+                    let groups = stmt
+                        .query_map(params_from_iter(values.iter()), |row| {
+                            Ok(UsageGroup {
+                                key: row.get(0)?,
+                                totals: totals_from_row(row, 1)?,
+                            })
+                        })?
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    Ok(groups)
+                };
+
+                Ok(UsageReport {
+                    totals,
+                    by_day: group("day", "day DESC")?,
+                    by_session: group("session_id", "MAX(created_at) DESC")?,
+                    by_api_key: group("api_key_id", "MAX(created_at) DESC")?,
+                })
+            })
+            .await
+    }
+}
+
+/// 累计用量的聚合列，顺序与 `totals_from_row` 一致
+const TOTALS_COLUMNS: &str = "COUNT(*), COALESCE(SUM(prompt_tokens), 0),
+    COALESCE(SUM(completion_tokens), 0), COALESCE(SUM(total_tokens), 0),
+    COALESCE(SUM(cost), 0), COALESCE(SUM(cost IS NULL), 0)";
+
+fn totals_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<UsageTotals> {
+    Ok(UsageTotals {
+        calls: row.get::<_, i64>(offset)? as u64,
+        prompt_tokens: row.get::<_, i64>(offset + 1)? as u64,
+        completion_tokens: row.get::<_, i64>(offset + 2)? as u64,
+        total_tokens: row.get::<_, i64>(offset + 3)? as u64,
+        cost: row.get(offset + 4)?,
+        unpriced_calls: row.get::<_, i64>(offset + 5)? as u64,
+    })
+}
+
+/// API 密钥的标识：SHA-256 的前 12 位十六进制，用于区分密钥而不保存密钥本身
+pub fn api_key_id(api_key: &str) -> String {
+    metadata::sha256_hex(api_key.as_bytes())[..12].to_string()
+}
+
+impl OpenRouterServer {
+    /// 计算一次上游调用的费用并写入用量记录
+    ///
+    /// 上游返回了 `usage.cost` 时直接使用，否则按模型单价估算；估算结果写回响应的
+    /// `usage.cost` 和 `usage.cost_source`，后续的输出和生成历史都能看到
+    pub(crate) async fn account_usage(&self, request_body: &Value, response: &mut Value) {
+        let Some(usage) = response.get("usage").filter(|usage| usage.is_object()) else {
+            return;
+        };
+        let tokens = |key: &str| usage.get(key).and_then(Value::as_u64).unwrap_or(0);
+        let prompt_tokens = tokens("prompt_tokens");
+        let completion_tokens = tokens("completion_tokens");
+        let total_tokens = match tokens("total_tokens") {
+            0 => prompt_tokens + completion_tokens,
+            total => total,
+        };
+        let upstream_cost = usage.get("cost").and_then(Value::as_f64);
+        let model = request_body
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        let (cost, cost_source) = match upstream_cost {
+            Some(cost) => (Some(cost), Some(CostSource::Upstream)),
+            None => match self.model_price(&model).await {
+                Some((price, source)) => {
+                    let images = count_input_images(request_body);
+                    match price.estimate(prompt_tokens, completion_tokens, images) {
+                        Some(cost) => (Some(cost), Some(source)),
+                        None => (None, None),
+                    }
+                }
+                None => (None, None),
+            },
+        };
+        if let (Some(cost), Some(source)) = (cost, cost_source) {
+            response["usage"]["cost"] = json!(cost);
+            response["usage"]["cost_source"] = json!(source);
+        }
+
+        if let Some(ledger) = &self.usage {
+            let event = UsageEvent {
+                session_id: self.session_id.clone(),
+                api_key_id: self.api_key_id.clone(),
+                model,
+                prompt_tokens,
+                completion_tokens,
+                total_tokens,
+                cost,
+                cost_source,
+            };
+            if let Err(e) = ledger.insert(event).await {
+                tracing::warn!("写入用量记录失败: {}", e);
+            }
+        }
+    }
+}
+
+/// 请求中输入图像的数量，用于按张计价的模型
+fn count_input_images(request_body: &Value) -> u64 {
+    request_body
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message.get("content").and_then(Value::as_array))
+        .flatten()
+        .filter(|part| part.get("type").and_then(Value::as_str) == Some("image_url"))
+        .count() as u64
+}
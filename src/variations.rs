@@ -0,0 +1,68 @@
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+
+/// 默认生成的变体数量
+pub const DEFAULT_VARIATION_COUNT: u32 = 4;
+/// 单次最多生成的变体数量
+pub const MAX_VARIATION_COUNT: u32 = 8;
+
+/// 变体与原图的差异程度
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum VariationStrength {
+    /// 保持构图和主体，只调整细节、光线和色调
+    Subtle,
+    /// 保持主体，可以改变姿态、角度和背景
+    #[default]
+    Medium,
+    /// 只保留核心概念，自由重新诠释
+    Strong,
+}
+
+impl VariationStrength {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VariationStrength::Subtle => "subtle",
+            VariationStrength::Medium => "medium",
+            VariationStrength::Strong => "strong",
+        }
+    }
+
+    fn guidance(&self) -> &'static str {
+        match self {
+            VariationStrength::Subtle => {
+                "Keep the composition, subject and style the same; only vary small details, lighting and color grading."
+            }
+            VariationStrength::Medium => {
+                "Keep the main subject and overall style recognizable, but feel free to change pose, camera angle, background and color palette."
+            }
+            VariationStrength::Strong => {
+                "Keep only the core concept of the image and reinterpret it freely with a noticeably different composition and style."
+            }
+        }
+    }
+}
+
+/// 生成第 `index` 个（从 1 开始）变体的编辑指令
+///
+/// 指令中带上序号，避免并发请求得到完全相同的结果
+pub fn variation_instruction(
+    strength: VariationStrength,
+    hint: Option<&str>,
+    index: u32,
+    count: u32,
+) -> String {
+    let mut instruction = format!(
+        "Create a new variation of this image (variation {} of {}). {}",
+        index,
+        count,
+        strength.guidance()
+    );
+    if let Some(hint) = hint.map(str::trim).filter(|h| !h.is_empty()) {
+        instruction.push_str(&format!(" Direction for the variation: {}", hint));
+    }
+    instruction.push_str(" Return only the new image.");
+    instruction
+}